@@ -27,6 +27,10 @@ impl<T: Completer> Autocomplete<T> {
         }
     }
 
+    pub fn completer_mut(&mut self) -> &mut T {
+        &mut self.completer
+    }
+
     pub fn invalidate(&mut self) {
         self.completions = None;
         self.range = 0..0;
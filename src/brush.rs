@@ -1,8 +1,9 @@
+use crate::dither::Dither;
 use crate::pixels::PixelsMut;
 use crate::view::layer::LayerCoords;
 use crate::view::{ViewCoords, ViewExtent};
 
-use rgx::kit::shape2d::{Fill, Rotation, Shape, Stroke};
+use rgx::kit::shape2d::{Fill, Line, Rotation, Shape, Stroke};
 use rgx::kit::{Rgba8, ZDepth};
 use rgx::math::{Point2, Vector2};
 use rgx::rect::Rect;
@@ -45,6 +46,12 @@ pub enum BrushMode {
         /// snap angle (degrees)
         Option<u32>,
     ),
+    /// Dither between the stroke color and the background color.
+    Dither(Dither),
+    /// Paint the stamp with the stroke color, keeping its transparency.
+    Tint,
+    /// Replace pixels with the next or previous color in their palette ramp.
+    Shade(Step),
 }
 
 impl fmt::Display for BrushMode {
@@ -58,10 +65,406 @@ impl fmt::Display for BrushMode {
             Self::XRay => "xray".fmt(f),
             Self::Line(Some(snap)) => write!(f, "{} degree snap line", snap),
             Self::Line(None) => write!(f, "line"),
+            Self::Dither(dither) => write!(f, "dither {}", dither),
+            Self::Tint => "tint".fmt(f),
+            Self::Shade(step) => write!(f, "shade {}", step),
         }
     }
 }
 
+/// Direction in which shading steps along a color ramp.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub enum Step {
+    /// Towards the end of the ramp.
+    Up,
+    /// Towards the start of the ramp.
+    Down,
+}
+
+impl Step {
+    /// Offset of the shaded color in the ramp.
+    pub fn offset(self) -> isize {
+        match self {
+            Self::Up => 1,
+            Self::Down => -1,
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Up => "up".fmt(f),
+            Self::Down => "down".fmt(f),
+        }
+    }
+}
+
+/// Shape of the brush tip.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Tip {
+    /// Square of the brush size.
+    Square,
+    /// Circle with the brush size as its diameter.
+    Circle,
+    /// Diamond with the brush size as its diagonal.
+    Diamond,
+    /// Horizontal line, one pixel high.
+    HLine,
+    /// Vertical line, one pixel wide.
+    VLine,
+}
+
+impl Tip {
+    /// Return the pixels covered by a tip of the given size, as runs along
+    /// each row. Runs are relative to the bottom-left corner of the tip.
+    pub fn runs(&self, size: usize) -> Vec<Rect<i32>> {
+        let n = size as i32;
+        let center = n / 2;
+
+        match self {
+            Self::Square => vec![Rect::new(0, 0, n, n)],
+            Self::HLine => vec![Rect::new(0, center, n, center + 1)],
+            Self::VLine => vec![Rect::new(center, 0, center + 1, n)],
+            Self::Circle => {
+                let mut points = Vec::new();
+                Figure::Ellipse { filled: true }.points(
+                    Point2::new(0, 0),
+                    Point2::new(n - 1, n - 1),
+                    &mut points,
+                );
+                // Filled ellipses are sorted by row, without gaps.
+                let mut runs: Vec<Rect<i32>> = Vec::new();
+                for p in points {
+                    match runs.last_mut() {
+                        Some(r) if r.y1 == p.y => r.x2 = p.x + 1,
+                        _ => runs.push(Rect::new(p.x, p.y, p.x + 1, p.y + 1)),
+                    }
+                }
+                runs
+            }
+            Self::Diamond => (0..n)
+                .filter_map(|y| {
+                    // Include every pixel whose center is within the diamond.
+                    let x = (2 * y + 1 - n).abs() / 2;
+
+                    if x < n - x {
+                        Some(Rect::new(x, y, n - x, y + 1))
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for Tip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Square => "square".fmt(f),
+            Self::Circle => "circle".fmt(f),
+            Self::Diamond => "diamond".fmt(f),
+            Self::HLine => "hline".fmt(f),
+            Self::VLine => "vline".fmt(f),
+        }
+    }
+}
+
+/// A figure drawn by dragging from a starting point, rather than
+/// following the brush freehand.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Figure {
+    /// Axis-aligned rectangle, outlined or filled.
+    Rectangle { filled: bool },
+    /// Ellipse inscribed in the bounding box, outlined or filled.
+    Ellipse { filled: bool },
+}
+
+impl Figure {
+    /// Rasterize the figure spanning the two given points, which are included.
+    pub fn points(&self, p0: Point2<i32>, p1: Point2<i32>, canvas: &mut Vec<Point2<i32>>) {
+        let (x0, x1) = (p0.x.min(p1.x), p0.x.max(p1.x));
+        let (y0, y1) = (p0.y.min(p1.y), p0.y.max(p1.y));
+
+        match self {
+            Self::Rectangle { filled: true } => {
+                for y in y0..=y1 {
+                    for x in x0..=x1 {
+                        canvas.push(Point2::new(x, y));
+                    }
+                }
+            }
+            Self::Rectangle { filled: false } => {
+                for x in x0..=x1 {
+                    canvas.push(Point2::new(x, y0));
+                }
+                for y in y0 + 1..y1 {
+                    canvas.push(Point2::new(x1, y));
+                }
+                if y1 > y0 {
+                    for x in (x0..=x1).rev() {
+                        canvas.push(Point2::new(x, y1));
+                    }
+                }
+                if x1 > x0 {
+                    for y in (y0 + 1..y1).rev() {
+                        canvas.push(Point2::new(x0, y));
+                    }
+                }
+            }
+            Self::Ellipse { filled } => {
+                let mut outline = ellipse(x0, y0, x1, y1);
+
+                if *filled {
+                    // The outline is sorted by row, so each row spans from
+                    // its first point to its last.
+                    let mut i = 0;
+                    while i < outline.len() {
+                        let row = outline[i].y;
+                        let min = outline[i].x;
+                        while i + 1 < outline.len() && outline[i + 1].y == row {
+                            i += 1;
+                        }
+                        for x in min..=outline[i].x {
+                            canvas.push(Point2::new(x, row));
+                        }
+                        i += 1;
+                    }
+                } else {
+                    canvas.append(&mut outline);
+                }
+            }
+        }
+    }
+}
+
+/// Rasterize the outline of the ellipse inscribed in the given inclusive
+/// bounds, using the midpoint algorithm. Returns the points sorted by row,
+/// with no point repeated.
+///
+/// Based on "A Rasterizing Algorithm for Drawing Curves" by Alois Zingl.
+fn ellipse(x0: i32, y0: i32, x1: i32, y1: i32) -> Vec<Point2<i32>> {
+    let (mut x0, mut x1) = (x0 as i64, x1 as i64);
+    let a = x1 - x0;
+    let b = (y1 - y0) as i64;
+    let b1 = b & 1;
+
+    let mut dx = 4 * (1 - a) * b * b;
+    let mut dy = 4 * (b1 + 1) * a * a;
+    let mut err = dx + dy + b1 * a * a;
+
+    let mut y0 = y0 as i64 + (b + 1) / 2;
+    let mut y1 = y0 - b1;
+    let a8 = 8 * a * a;
+    let b8 = 8 * b * b;
+
+    let mut points = Vec::new();
+    let mut plot = |x: i64, y: i64| points.push(Point2::new(x as i32, y as i32));
+
+    loop {
+        plot(x1, y0);
+        plot(x0, y0);
+        plot(x0, y1);
+        plot(x1, y1);
+
+        let e2 = 2 * err;
+        if e2 <= dy {
+            y0 += 1;
+            y1 -= 1;
+            dy += a8;
+            err += dy;
+        }
+        if e2 >= dx || 2 * err > dy {
+            x0 += 1;
+            x1 -= 1;
+            dx += b8;
+            err += dx;
+        }
+        if x0 > x1 {
+            break;
+        }
+    }
+    // Finish the tips of very flat ellipses.
+    while y0 - y1 <= b {
+        plot(x0 - 1, y0);
+        plot(x1 + 1, y0);
+        plot(x0 - 1, y1);
+        plot(x1 + 1, y1);
+        y0 += 1;
+        y1 -= 1;
+    }
+    points.sort_by_key(|p| (p.y, p.x));
+    points.dedup();
+    points
+}
+
+/// Kind of curve drawn through the points of a [`Curve`].
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum CurveKind {
+    /// Straight segments joining the points.
+    Polyline,
+    /// Quadratic Bézier segments, with one control point between anchors.
+    Quadratic,
+    /// Cubic Bézier segments, with two control points between anchors.
+    Cubic,
+}
+
+impl CurveKind {
+    /// The number of points from one anchor to the next.
+    fn degree(self) -> usize {
+        match self {
+            Self::Polyline => 1,
+            Self::Quadratic => 2,
+            Self::Cubic => 3,
+        }
+    }
+}
+
+/// A curve placed one point at a time, whose points can be moved
+/// until it is painted.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Curve {
+    /// The kind of curve.
+    pub kind: CurveKind,
+    /// Anchors and control points, in order. The curve passes through
+    /// the first point and every `degree`-th point after it.
+    pub points: Vec<Point2<i32>>,
+    /// The point being dragged, if any.
+    pub dragging: Option<usize>,
+}
+
+impl Curve {
+    /// Create an empty curve.
+    pub fn new(kind: CurveKind) -> Self {
+        Self {
+            kind,
+            points: Vec::new(),
+            dragging: None,
+        }
+    }
+
+    /// Whether the point at the given index is an anchor, as opposed to a
+    /// control point.
+    pub fn is_anchor(&self, i: usize) -> bool {
+        let offset = i % self.kind.degree();
+
+        offset == 0 || i == self.points.len() - 1
+    }
+
+    /// The point at or next to the given position, if any. Later points
+    /// take precedence.
+    pub fn point_at(&self, p: Point2<i32>) -> Option<usize> {
+        self.points
+            .iter()
+            .rposition(|q| (q.x - p.x).abs() <= 1 && (q.y - p.y).abs() <= 1)
+    }
+
+    /// Rasterize the curve, filtering out 'L' shapes like the brush does in
+    /// pixel-perfect mode. Trailing points which don't complete a segment
+    /// are joined with straight lines.
+    pub fn points(&self) -> Vec<Point2<i32>> {
+        let degree = self.kind.degree();
+        let mut stroke = Vec::new();
+
+        stroke.extend(self.points.first().cloned());
+
+        let mut i = 0;
+        while i + 1 < self.points.len() {
+            let end = usize::min(i + degree, self.points.len() - 1);
+            let segment = &self.points[i..=end];
+
+            if segment.len() == degree + 1 {
+                Curve::bezier(segment, &mut stroke);
+            } else {
+                for pair in segment.windows(2) {
+                    Brush::line(pair[0], pair[1], &mut stroke);
+                }
+            }
+            i = end;
+        }
+        stroke.dedup();
+
+        let mut filtered = Brush::filter(&stroke);
+        filtered.dedup();
+        filtered
+    }
+
+    /// Rasterize a Bézier segment of any degree, by joining points sampled
+    /// along it with lines.
+    fn bezier(points: &[Point2<i32>], canvas: &mut Vec<Point2<i32>>) {
+        let points: Vec<Vector2<f32>> = points
+            .iter()
+            .map(|p| Vector2::new(p.x as f32, p.y as f32))
+            .collect();
+        // The curve is never longer than its control polygon, so sampling at
+        // this rate leaves no gaps larger than a pixel between samples.
+        let length: f32 = points.windows(2).map(|w| w[0].distance(w[1])).sum();
+        let steps = usize::max(length.ceil() as usize, 1);
+
+        let mut prev = None;
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            // De Casteljau's algorithm.
+            let mut ps = points.clone();
+            for n in (1..ps.len()).rev() {
+                for i in 0..n {
+                    ps[i] = ps[i] * (1. - t) + ps[i + 1] * t;
+                }
+            }
+            let p = Point2::new(ps[0].x.round() as i32, ps[0].y.round() as i32);
+
+            if let Some(prev) = prev {
+                Brush::line(prev, p, canvas);
+            }
+            prev = Some(p);
+        }
+    }
+}
+
+/// A bitmap stamped by the brush, instead of a square of its size.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Stamp {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels, row by row, starting from the top.
+    pub pixels: Vec<Rgba8>,
+}
+
+impl Stamp {
+    /// Create a stamp from pixels, row by row, starting from the top.
+    pub fn new(width: u32, height: u32, pixels: Vec<Rgba8>) -> Self {
+        debug_assert_eq!(pixels.len(), (width * height) as usize);
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Tint a stamp pixel with the given color, keeping the pixel's transparency.
+    pub fn tint(pixel: Rgba8, color: Rgba8) -> Rgba8 {
+        color.alpha((pixel.a as u32 * color.a as u32 / 255) as u8)
+    }
+
+    /// The stamp's visible pixels when centered on the given point.
+    pub fn pixels_at(&self, p: Point2<i32>) -> impl Iterator<Item = (Point2<i32>, Rgba8)> + '_ {
+        let (w, h) = (self.width as i32, self.height as i32);
+        let (x0, y0) = (p.x - w / 2, p.y - h / 2);
+
+        self.pixels
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.a > 0)
+            .map(move |(i, c)| {
+                let (x, row) = (i as i32 % w, i as i32 / w);
+                // Layer coordinates grow upwards, while rows grow downwards.
+                (Point2::new(x0 + x, y0 + h - row - 1), *c)
+            })
+    }
+}
+
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum Align {
     Center,
@@ -79,9 +482,19 @@ pub struct Brush {
     pub stroke: Vec<Point2<i32>>,
     /// Current stroke color.
     pub color: Rgba8,
+    /// Color painted between the pixels of the dither pattern, if any.
+    pub background: Rgba8,
+    /// Bitmap stamped by the brush, if any.
+    pub stamp: Option<Stamp>,
+    /// Shape of the brush tip.
+    pub tip: Tip,
 
     /// Currently active brush modes.
     modes: BTreeSet<BrushMode>,
+    /// Figure being drawn, if any.
+    figure: Option<Figure>,
+    /// Position at which drawing started.
+    origin: Point2<i32>,
     /// Current brush position.
     curr: Point2<i32>,
     /// Previous brush position.
@@ -95,7 +508,12 @@ impl Default for Brush {
             state: BrushState::NotDrawing,
             stroke: Vec::with_capacity(32),
             color: Rgba8::TRANSPARENT,
+            background: Rgba8::TRANSPARENT,
+            stamp: None,
+            tip: Tip::Square,
             modes: BTreeSet::new(),
+            figure: None,
+            origin: Point2::new(0, 0),
             curr: Point2::new(0, 0),
             prev: Point2::new(0, 0),
         }
@@ -116,14 +534,32 @@ impl Brush {
                 self.unset(line_mode);
             }
         }
+        if let BrushMode::Dither(_) = m {
+            // only one dither pattern may be active at a time
+            if let Some(dither) = self.dither() {
+                self.modes.remove(&BrushMode::Dither(dither));
+            }
+        }
+        if let BrushMode::Shade(_) = m {
+            // only one shading direction may be active at a time
+            if let Some(step) = self.shading() {
+                self.modes.remove(&BrushMode::Shade(step));
+            }
+        }
         self.modes.insert(m)
     }
 
     /// De-activate the given brush mode.
     pub fn unset(&mut self, m: BrushMode) -> bool {
-        match self.line_mode() {
-            Some(line_mode) if matches!(m, BrushMode::Line(_)) => self.modes.remove(&line_mode),
-            _ => self.modes.remove(&m),
+        let active = match m {
+            BrushMode::Line(_) => self.line_mode(),
+            BrushMode::Dither(_) => self.dither().map(BrushMode::Dither),
+            BrushMode::Shade(_) => self.shading().map(BrushMode::Shade),
+            _ => Some(m),
+        };
+        match active {
+            Some(m) => self.modes.remove(&m),
+            None => false,
         }
     }
 
@@ -156,12 +592,68 @@ impl Brush {
 
     /// Start drawing. Called when input is first pressed.
     pub fn start_drawing(&mut self, p: LayerCoords<i32>, color: Rgba8, extent: ViewExtent) {
+        self.start(p, color, extent, None);
+    }
+
+    /// Start drawing a figure, from the given point to the brush position.
+    pub fn start_figure(
+        &mut self,
+        figure: Figure,
+        p: LayerCoords<i32>,
+        color: Rgba8,
+        extent: ViewExtent,
+    ) {
+        self.start(p, color, extent, Some(figure));
+    }
+
+    /// The figure being drawn, if any.
+    pub fn figure(&self) -> Option<Figure> {
+        self.figure
+    }
+
+    /// Constrain a point so that the figure spanning from the origin to it
+    /// fits in a square, eg. to draw circles instead of ellipses.
+    pub fn constrain(&self, p: LayerCoords<i32>) -> LayerCoords<i32> {
+        let (dx, dy) = (p.x - self.origin.x, p.y - self.origin.y);
+        let d = dx.abs().max(dy.abs());
+
+        LayerCoords::new(
+            self.origin.x + if dx < 0 { -d } else { d },
+            self.origin.y + if dy < 0 { -d } else { d },
+        )
+    }
+
+    fn start(
+        &mut self,
+        p: LayerCoords<i32>,
+        color: Rgba8,
+        extent: ViewExtent,
+        figure: Option<Figure>,
+    ) {
         self.state = BrushState::DrawStarted(extent);
         self.color = color;
         self.stroke = Vec::with_capacity(32);
+        self.figure = figure;
+        self.origin = *p;
         self.draw(p);
     }
 
+    /// If a dither mode is active, return its pattern.
+    pub fn dither(&self) -> Option<Dither> {
+        self.modes.iter().find_map(|mode| match mode {
+            BrushMode::Dither(dither) => Some(*dither),
+            _ => None,
+        })
+    }
+
+    /// If shading is active, return the direction of the shading.
+    pub fn shading(&self) -> Option<Step> {
+        self.modes.iter().find_map(|mode| match mode {
+            BrushMode::Shade(step) => Some(*step),
+            _ => None,
+        })
+    }
+
     /// If a line mode is active, return it
     fn line_mode(&self) -> Option<BrushMode> {
         self.modes
@@ -180,7 +672,10 @@ impl Brush {
         };
         self.curr = *p;
 
-        if let Some(BrushMode::Line(snap)) = self.line_mode() {
+        if let Some(figure) = self.figure {
+            self.stroke.clear();
+            figure.points(self.origin, self.curr, &mut self.stroke);
+        } else if let Some(BrushMode::Line(snap)) = self.line_mode() {
             let start = *self.stroke.first().unwrap_or(&p);
             self.stroke.clear();
 
@@ -204,7 +699,7 @@ impl Brush {
             self.stroke.dedup();
         }
 
-        if self.is_set(BrushMode::Perfect) {
+        if self.is_set(BrushMode::Perfect) && self.figure.is_none() {
             self.stroke = Brush::filter(&self.stroke);
         }
 
@@ -265,36 +760,160 @@ impl Brush {
             BrushState::DrawStarted(extent)
             | BrushState::Drawing(extent)
             | BrushState::DrawEnded(extent) => {
-                let mut pixels = Vec::new();
+                self.shapes(&self.stroke, extent, stroke, fill, scale, align)
+            }
+            _ => Vec::new(),
+        }
+    }
 
-                for p in &self.stroke {
-                    pixels.extend_from_slice(
-                        self.expand(ViewCoords::new(p.x, p.y), extent).as_slice(),
-                    );
-                }
-                pixels
-                    .iter()
-                    .map(|p| {
-                        self.shape(
-                            Point2::new(p.x as f32, p.y as f32),
-                            ZDepth::ZERO,
-                            stroke,
-                            fill,
-                            scale,
-                            align,
+    /// Return the shapes painted by the brush at the given points, taking
+    /// brush size, tip, stamp and modes into account.
+    pub fn shapes(
+        &self,
+        points: &[Point2<i32>],
+        extent: ViewExtent,
+        stroke: Stroke,
+        fill: Fill,
+        scale: f32,
+        align: Align,
+    ) -> Vec<Shape> {
+        let mut pixels = Vec::new();
+
+        for p in points {
+            pixels.extend_from_slice(self.expand(ViewCoords::new(p.x, p.y), extent).as_slice());
+        }
+
+        if let (Some(stamp), Fill::Solid(color)) = (&self.stamp, fill) {
+            let color = Rgba8::from(color);
+
+            return pixels
+                .iter()
+                .flat_map(|p| stamp.pixels_at(**p))
+                .map(|(p, c)| {
+                    let c = if self.is_set(BrushMode::Erase) {
+                        color
+                    } else if self.is_set(BrushMode::Tint) {
+                        Stamp::tint(c, color)
+                    } else {
+                        c
+                    };
+                    Shape::Rectangle(
+                        Rect::new(p.x, p.y, p.x + 1, p.y + 1).map(|n| n as f32),
+                        ZDepth::ZERO,
+                        Rotation::ZERO,
+                        stroke,
+                        Fill::Solid(c.into()),
+                    )
+                })
+                .collect();
+        }
+
+        if let (Some(dither), Fill::Solid(color)) = (self.dither(), fill) {
+            // When dithering, every pixel under the brush is painted on its own,
+            // so that the pattern is anchored to the canvas. When erasing, only
+            // the pixels of the pattern are erased.
+            let background = if self.is_set(BrushMode::Erase) {
+                None
+            } else {
+                Some(self.background)
+            };
+            let offset = (self.size / 2) as i32;
+            let runs = self.tip.runs(self.size);
+
+            return pixels
+                .iter()
+                .flat_map(|p| {
+                    let (x, y) = (p.x - offset, p.y - offset);
+                    runs.iter().flat_map(move |r| {
+                        dither.fill(
+                            Rect::new(x + r.x1, y + r.y1, x + r.x2, y + r.y2),
+                            color.into(),
+                            background,
                         )
                     })
-                    .collect()
+                })
+                .map(|(r, color)| {
+                    Shape::Rectangle(
+                        r.map(|n| n as f32),
+                        ZDepth::ZERO,
+                        Rotation::ZERO,
+                        stroke,
+                        Fill::Solid(color.into()),
+                    )
+                })
+                .collect();
+        }
+        pixels
+            .iter()
+            .flat_map(|p| {
+                self.tip_shapes(
+                    Point2::new(p.x as f32, p.y as f32),
+                    ZDepth::ZERO,
+                    stroke,
+                    fill,
+                    scale,
+                    align,
+                )
+            })
+            .collect()
+    }
+
+    /// Return the pixels covered by the brush at the given points, each
+    /// appearing once.
+    pub fn pixels(&self, points: &[Point2<i32>], extent: ViewExtent) -> Vec<Point2<i32>> {
+        let mut pixels = Vec::new();
+
+        for p in points {
+            for p in self.expand(ViewCoords::new(p.x, p.y), extent) {
+                if let Some(stamp) = &self.stamp {
+                    pixels.extend(stamp.pixels_at(*p).filter(|(_, c)| c.a > 0).map(|(p, _)| p));
+                } else {
+                    let offset = (self.size / 2) as i32;
+
+                    for r in self.tip.runs(self.size) {
+                        for y in r.y1..r.y2 {
+                            for x in r.x1..r.x2 {
+                                pixels.push(Point2::new(p.x - offset + x, p.y - offset + y));
+                            }
+                        }
+                    }
+                }
             }
-            _ => Vec::new(),
         }
+        pixels.sort_by_key(|p| (p.y, p.x));
+        pixels.dedup();
+        pixels
+    }
+
+    /// Return the shapes painted by shading the pixels covered by the brush at
+    /// the given points. The shaded color of a pixel is looked up with the
+    /// given function, and pixels without one are left alone.
+    pub fn shade(
+        &self,
+        points: &[Point2<i32>],
+        extent: ViewExtent,
+        shaded: impl Fn(Point2<i32>) -> Option<Rgba8>,
+    ) -> Vec<Shape> {
+        self.pixels(points, extent)
+            .into_iter()
+            .filter_map(|p| shaded(p).map(|c| (p, c)))
+            .map(|(p, c)| {
+                Shape::Rectangle(
+                    Rect::new(p.x, p.y, p.x + 1, p.y + 1).map(|n| n as f32),
+                    ZDepth::ZERO,
+                    Rotation::ZERO,
+                    Stroke::NONE,
+                    Fill::Solid(c.into()),
+                )
+            })
+            .collect()
     }
 
-    /// Return the shape that should be painted when the brush is at the given
+    /// Return the shapes that should be painted when the brush is at the given
     /// position with the given parameters. Takes an `Origin` which describes
-    /// whether to align the position to the bottom-left of the shape, or the
+    /// whether to align the position to the bottom-left of the tip, or the
     /// center.
-    pub fn shape(
+    pub fn tip_shapes(
         &self,
         p: Point2<f32>,
         z: ZDepth,
@@ -302,24 +921,75 @@ impl Brush {
         fill: Fill,
         scale: f32,
         align: Align,
-    ) -> Shape {
-        let x = p.x;
-        let y = p.y;
-
+    ) -> Vec<Shape> {
         let size = self.size as f32;
 
         let offset = match align {
             Align::Center => size * scale / 2.,
             Align::BottomLeft => (self.size / 2) as f32 * scale,
         };
+        let origin = p - Vector2::new(offset, offset);
+        let runs = self.tip.runs(self.size);
+        let rect = |r: &Rect<i32>| {
+            Rect::new(
+                origin.x + r.x1 as f32 * scale,
+                origin.y + r.y1 as f32 * scale,
+                origin.x + r.x2 as f32 * scale,
+                origin.y + r.y2 as f32 * scale,
+            )
+        };
 
-        Shape::Rectangle(
-            Rect::new(x, y, x + size * scale, y + size * scale) - Vector2::new(offset, offset),
-            z,
-            Rotation::ZERO,
-            stroke,
-            fill,
-        )
+        if self.tip == Tip::Square {
+            return runs
+                .iter()
+                .map(|r| Shape::Rectangle(rect(r), z, Rotation::ZERO, stroke, fill))
+                .collect();
+        }
+
+        let mut shapes: Vec<Shape> = match fill {
+            Fill::Empty => Vec::new(),
+            fill => runs
+                .iter()
+                .map(|r| Shape::Rectangle(rect(r), z, Rotation::ZERO, Stroke::NONE, fill))
+                .collect(),
+        };
+
+        if stroke != Stroke::NONE {
+            // Outline the pixels on the edge of the tip, rather than each run.
+            // Edges are extended by half a pixel so that the corners are closed.
+            let covered = |x: i32, y: i32| {
+                runs.iter()
+                    .any(|r| r.x1 <= x && x < r.x2 && r.y1 <= y && y < r.y2)
+            };
+            let ext = 0.5;
+
+            for r in &runs {
+                for y in r.y1..r.y2 {
+                    for x in r.x1..r.x2 {
+                        let (x0, y0) = (origin.x + x as f32 * scale, origin.y + y as f32 * scale);
+                        let (x1, y1) = (x0 + scale, y0 + scale);
+                        let edges = [
+                            (covered(x, y - 1), [x0 - ext, y0], [x1 + ext, y0]),
+                            (covered(x, y + 1), [x0 - ext, y1], [x1 + ext, y1]),
+                            (covered(x - 1, y), [x0, y0 - ext], [x0, y1 + ext]),
+                            (covered(x + 1, y), [x1, y0 - ext], [x1, y1 + ext]),
+                        ];
+
+                        for (neighbour, a, b) in edges.iter() {
+                            if !neighbour {
+                                shapes.push(Shape::Line(
+                                    Line::new(*a, *b),
+                                    z,
+                                    Rotation::ZERO,
+                                    stroke,
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        shapes
     }
 
     ///////////////////////////////////////////////////////////////////////////
@@ -414,6 +1084,351 @@ impl Brush {
 #[cfg(test)]
 mod test {
     use super::*;
+    use rgx::kit::Rgba;
+
+    #[test]
+    fn test_rectangle() {
+        let mut outline = Vec::new();
+        Figure::Rectangle { filled: false }.points(
+            Point2::new(3, 2),
+            Point2::new(0, 0),
+            &mut outline,
+        );
+        assert_eq!(outline.len(), 10);
+        assert!(!outline.contains(&Point2::new(1, 1)));
+        assert!(outline.contains(&Point2::new(0, 1)));
+        assert!(outline.contains(&Point2::new(3, 1)));
+
+        let mut dedup = outline.clone();
+        dedup.sort_by_key(|p| (p.x, p.y));
+        dedup.dedup();
+        assert_eq!(dedup.len(), outline.len(), "no point is painted twice");
+
+        let mut filled = Vec::new();
+        Figure::Rectangle { filled: true }.points(
+            Point2::new(0, 0),
+            Point2::new(3, 2),
+            &mut filled,
+        );
+        assert_eq!(filled.len(), 12);
+
+        let mut line = Vec::new();
+        Figure::Rectangle { filled: false }.points(Point2::new(0, 0), Point2::new(0, 2), &mut line);
+        assert_eq!(
+            line,
+            vec![Point2::new(0, 0), Point2::new(0, 1), Point2::new(0, 2)]
+        );
+    }
+
+    #[test]
+    fn test_ellipse() {
+        let ellipse = |p0: (i32, i32), p1: (i32, i32), filled: bool| {
+            let mut points = Vec::new();
+            Figure::Ellipse { filled }.points(
+                Point2::new(p0.0, p0.1),
+                Point2::new(p1.0, p1.1),
+                &mut points,
+            );
+            points
+        };
+
+        let circle = ellipse((0, 0), (4, 4), false);
+        #[rustfmt::skip]
+        let expected = vec![
+                  (1, 0), (2, 0), (3, 0),
+          (0, 1),                         (4, 1),
+          (0, 2),                         (4, 2),
+          (0, 3),                         (4, 3),
+                  (1, 4), (2, 4), (3, 4),
+        ];
+        assert_eq!(
+            circle,
+            expected
+                .into_iter()
+                .map(|(x, y)| Point2::new(x, y))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(ellipse((0, 0), (4, 4), true).len(), 21);
+        assert_eq!(ellipse((4, 4), (0, 0), false), circle);
+
+        for (w, h) in &[(1, 1), (2, 7), (9, 4), (16, 16), (31, 12), (1, 20)] {
+            let outline = ellipse((0, 0), (*w - 1, *h - 1), false);
+            let filled = ellipse((0, 0), (*w - 1, *h - 1), true);
+
+            for p in &outline {
+                assert!(p.x >= 0 && p.x < *w && p.y >= 0 && p.y < *h);
+            }
+            assert!(outline.iter().any(|p| p.x == 0) && outline.iter().any(|p| p.x == w - 1));
+            assert!(outline.iter().any(|p| p.y == 0) && outline.iter().any(|p| p.y == h - 1));
+
+            // Two points stepping off the same pixel horizontally and vertically
+            // form a doubled corner, which looks jagged in pixel art.
+            for p in &outline {
+                for (dx, dy) in &[(-1, -1), (-1, 1), (1, -1), (1, 1)] {
+                    let has = |x, y| outline.contains(&Point2::new(x, y));
+                    assert!(
+                        !(has(p.x + dx, p.y) && has(p.x, p.y + dy) && !has(p.x + dx, p.y + dy)),
+                        "{}x{} ellipse has a doubled corner at {:?}",
+                        w,
+                        h,
+                        p
+                    );
+                }
+            }
+            let mut dedup = filled.clone();
+            dedup.sort_by_key(|p| (p.x, p.y));
+            dedup.dedup();
+            assert_eq!(dedup.len(), filled.len(), "no point is painted twice");
+            assert!(outline.iter().all(|p| filled.contains(p)));
+        }
+    }
+
+    #[test]
+    fn test_curve() {
+        let curve = |kind, points: &[(i32, i32)]| {
+            let mut curve = Curve::new(kind);
+            curve.points = points.iter().map(|(x, y)| Point2::new(*x, *y)).collect();
+            curve.points()
+        };
+        let is_clean = |points: &[Point2<i32>]| {
+            points.windows(2).all(|w| {
+                let (dx, dy) = ((w[1].x - w[0].x).abs(), (w[1].y - w[0].y).abs());
+                dx <= 1 && dy <= 1 && dx + dy > 0
+            }) && points.windows(3).all(|w| {
+                // No 'L' shapes.
+                !(w[0].x == w[1].x && w[1].y == w[2].y || w[0].y == w[1].y && w[1].x == w[2].x)
+            })
+        };
+
+        assert_eq!(curve(CurveKind::Cubic, &[(3, 4)]), vec![Point2::new(3, 4)]);
+
+        let polyline = curve(CurveKind::Polyline, &[(0, 0), (4, 0), (4, 4), (8, 8)]);
+        assert_eq!(polyline.first(), Some(&Point2::new(0, 0)));
+        assert_eq!(polyline.last(), Some(&Point2::new(8, 8)));
+        assert!(
+            !polyline.contains(&Point2::new(4, 0)),
+            "corners are filtered"
+        );
+        assert!(is_clean(&polyline));
+
+        let arch = curve(CurveKind::Quadratic, &[(0, 0), (8, 16), (16, 0)]);
+        assert_eq!(arch.first(), Some(&Point2::new(0, 0)));
+        assert_eq!(arch.last(), Some(&Point2::new(16, 0)));
+        assert!(arch.contains(&Point2::new(8, 8)), "the curve peaks halfway");
+        assert!(!arch.iter().any(|p| p.y > 8));
+        assert!(is_clean(&arch));
+
+        let wave = curve(
+            CurveKind::Cubic,
+            &[(0, 0), (6, 12), (12, -12), (18, 0), (24, 12)],
+        );
+        assert!(
+            wave.contains(&Point2::new(18, 0)),
+            "the curve passes through anchors"
+        );
+        assert_eq!(wave.last(), Some(&Point2::new(24, 12)));
+        assert!(is_clean(&wave));
+
+        let mut c = Curve::new(CurveKind::Quadratic);
+        c.points = vec![Point2::new(0, 0), Point2::new(5, 5), Point2::new(9, 0)];
+        assert!(c.is_anchor(0) && !c.is_anchor(1) && c.is_anchor(2));
+        assert_eq!(c.point_at(Point2::new(6, 4)), Some(1));
+        assert_eq!(c.point_at(Point2::new(3, 3)), None);
+    }
+
+    #[test]
+    fn test_dither() {
+        let (fg, bg) = (Rgba8::WHITE, Rgba8::BLACK);
+        let extent = ViewExtent::new(16, 16, 1);
+        let pixels = |brush: &Brush, points: &[Point2<i32>]| {
+            let mut pixels = Vec::new();
+            for shape in brush.shapes(
+                points,
+                extent,
+                Stroke::NONE,
+                Fill::Solid(fg.into()),
+                1.0,
+                Align::BottomLeft,
+            ) {
+                if let Shape::Rectangle(r, _, _, _, Fill::Solid(c)) = shape {
+                    for x in r.x1 as i32..r.x2 as i32 {
+                        pixels.push((x, r.y1 as i32, Rgba8::from(c)));
+                    }
+                }
+            }
+            pixels.sort_by_key(|(x, y, _)| (*y, *x));
+            pixels.dedup();
+            pixels
+        };
+
+        let mut brush = Brush::default();
+        brush.size = 3;
+        brush.background = bg;
+        brush.set(BrushMode::Dither(Dither::Checker));
+        brush.set(BrushMode::Dither(Dither::Bayer(4)));
+        assert_eq!(
+            brush.dither(),
+            Some(Dither::Bayer(4)),
+            "only one pattern is active"
+        );
+
+        // Strokes starting at different points share the same pattern.
+        let a = pixels(&brush, &[Point2::new(5, 5)]);
+        let b = pixels(
+            &brush,
+            &[Point2::new(4, 4), Point2::new(5, 5), Point2::new(6, 6)],
+        );
+        assert_eq!(a.len(), 9);
+        assert!(a.iter().all(|p| b.contains(p)));
+        assert_eq!(a.iter().filter(|(_, _, c)| *c == fg).count(), 4);
+
+        brush.set(BrushMode::Erase);
+        let erased = pixels(&brush, &[Point2::new(5, 5)]);
+        assert_eq!(erased.len(), 4, "only the pattern is erased");
+
+        brush.unset(BrushMode::Dither(Dither::Checker));
+        assert_eq!(brush.dither(), None);
+    }
+
+    #[test]
+    fn test_tip() {
+        let widths = |tip: Tip, size: usize| -> Vec<i32> {
+            let runs = tip.runs(size);
+            assert!(
+                runs.windows(2).all(|w| w[0].y2 <= w[1].y1),
+                "{} runs are sorted by row",
+                tip
+            );
+            runs.iter().map(|r| r.width()).collect()
+        };
+        assert_eq!(widths(Tip::Square, 4), vec![4]);
+        assert_eq!(widths(Tip::Circle, 1), vec![1]);
+        assert_eq!(widths(Tip::Circle, 3), vec![1, 3, 1]);
+        assert_eq!(widths(Tip::Circle, 5), vec![3, 5, 5, 5, 3]);
+        assert_eq!(widths(Tip::Diamond, 2), vec![2, 2]);
+        assert_eq!(widths(Tip::Diamond, 5), vec![1, 3, 5, 3, 1]);
+        assert_eq!(Tip::HLine.runs(5), vec![Rect::new(0, 2, 5, 3)]);
+        assert_eq!(Tip::VLine.runs(5), vec![Rect::new(2, 0, 3, 5)]);
+
+        // Round tips are symmetric.
+        for size in 1..16 {
+            for tip in &[Tip::Circle, Tip::Diamond] {
+                let runs = tip.runs(size);
+                let n = size as i32;
+
+                for r in &runs {
+                    assert_eq!(r.x1, n - r.x2, "{} {} is symmetric along x", tip, size);
+                }
+                let rows: Vec<_> = runs.iter().map(|r| r.width()).collect();
+                let flipped: Vec<_> = rows.iter().rev().copied().collect();
+                assert_eq!(rows, flipped, "{} {} is symmetric along y", tip, size);
+            }
+        }
+
+        let mut brush = Brush::default();
+        brush.size = 3;
+        brush.tip = Tip::Circle;
+
+        let shapes = |brush: &Brush, stroke: Stroke, fill: Fill| {
+            brush.tip_shapes(
+                Point2::new(0., 0.),
+                ZDepth::ZERO,
+                stroke,
+                fill,
+                2.0,
+                Align::Center,
+            )
+        };
+        let outline = shapes(&brush, Stroke::new(1.0, Rgba::WHITE), Fill::Empty);
+        assert_eq!(outline.len(), 12, "every edge of the plus is outlined");
+        assert!(outline.iter().all(|s| matches!(s, Shape::Line(..))));
+
+        let filled = shapes(&brush, Stroke::NONE, Fill::Solid(Rgba::WHITE));
+        assert_eq!(filled.len(), 3);
+
+        brush.tip = Tip::Square;
+        let square = shapes(&brush, Stroke::new(1.0, Rgba::WHITE), Fill::Empty);
+        assert!(
+            matches!(square.as_slice(), [Shape::Rectangle(r, ..)] if *r == Rect::new(-3., -3., 3., 3.))
+        );
+
+        // Dithered tips only cover the pixels of the tip.
+        brush.tip = Tip::Diamond;
+        brush.size = 5;
+        brush.set(BrushMode::Dither(Dither::Bayer(16)));
+
+        let painted: i32 = brush
+            .shapes(
+                &[Point2::new(8, 8)],
+                ViewExtent::new(16, 16, 1),
+                Stroke::NONE,
+                Fill::Solid(Rgba::WHITE),
+                1.0,
+                Align::BottomLeft,
+            )
+            .iter()
+            .map(|s| match s {
+                Shape::Rectangle(r, ..) => r.width() as i32 * r.height() as i32,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(painted, 13);
+    }
+
+    #[test]
+    fn test_stamp() {
+        let (z, r, g) = (
+            Rgba8::TRANSPARENT,
+            Rgba8::RED,
+            Rgba8::new(0x0, 0xff, 0x0, 0x80),
+        );
+        #[rustfmt::skip]
+        let stamp = Stamp::new(3, 2, vec![
+            r, z, z,
+            z, g, r,
+        ]);
+        let mut pixels: Vec<_> = stamp.pixels_at(Point2::new(10, 10)).collect();
+        pixels.sort_by_key(|(p, _)| (p.x, p.y));
+        assert_eq!(
+            pixels,
+            vec![
+                (Point2::new(9, 10), r),
+                (Point2::new(10, 9), g),
+                (Point2::new(11, 9), r)
+            ],
+            "the top row is painted above the bottom one"
+        );
+
+        let mut brush = Brush::default();
+        brush.stamp = Some(stamp);
+
+        let colors = |brush: &Brush| -> Vec<Rgba8> {
+            brush
+                .shapes(
+                    &[Point2::new(10, 10)],
+                    ViewExtent::new(16, 16, 1),
+                    Stroke::NONE,
+                    Fill::Solid(Rgba8::BLUE.into()),
+                    1.0,
+                    Align::BottomLeft,
+                )
+                .into_iter()
+                .filter_map(|s| match s {
+                    Shape::Rectangle(_, _, _, _, Fill::Solid(c)) => Some(Rgba8::from(c)),
+                    _ => None,
+                })
+                .collect()
+        };
+        let mut stamped = colors(&brush);
+        stamped.sort_by_key(|c| (c.r, c.g));
+        assert_eq!(stamped, vec![g, r, r]);
+
+        brush.set(BrushMode::Tint);
+        let tinted = colors(&brush);
+        assert_eq!(tinted.len(), 3);
+        assert!(tinted.contains(&Rgba8::BLUE));
+        assert!(tinted.contains(&Rgba8::BLUE.alpha(0x80)));
+    }
 
     #[test]
     fn test_paint() {
@@ -1,9 +1,9 @@
 use crate::autocomplete::{self, Autocomplete, FileCompleter, FileCompleterOpts};
-use crate::brush::BrushMode;
+use crate::brush::{BrushMode, Tip};
 use crate::history::History;
 use crate::parser::*;
 use crate::platform;
-use crate::session::{Direction, Input, Mode, PanState, Tool, VisualState};
+use crate::session::{Direction, Input, MessageType, Mode, PanState, Settings, Tool, VisualState};
 use crate::view::layer::LayerId;
 
 use memoir::traits::Parse;
@@ -13,6 +13,7 @@ use rgx::kit::Rgba8;
 use rgx::rect::Rect;
 
 use std::fmt;
+use std::iter::Peekable;
 use std::path::Path;
 
 pub const COMMENT: char = '-';
@@ -30,6 +31,60 @@ pub enum Axis {
     Vertical,
 }
 
+/// Pixels affected by a command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// The selection, on the active layer.
+    Selection,
+    /// The frame at the given index, on the active layer.
+    Frame(usize),
+    /// The active layer of the active view.
+    View,
+    /// All layers of the active view.
+    Layers,
+    /// All layers of all views.
+    Views,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Selection => "selection".fmt(f),
+            Self::Frame(i) => write!(f, "frame {}", i),
+            Self::View => "view".fmt(f),
+            Self::Layers => "layers".fmt(f),
+            Self::Views => "views".fmt(f),
+        }
+    }
+}
+
+impl Parse for Scope {
+    fn parser() -> Parser<Self> {
+        Parser::new(
+            |input| {
+                let (id, p) = identifier().parse(input)?;
+                match id.as_str() {
+                    "selection" => Ok((Scope::Selection, p)),
+                    "frame" => {
+                        let ((_, i), p) = whitespace()
+                            .then(natural::<usize>().label("<frame>"))
+                            .parse(p)?;
+                        Ok((Scope::Frame(i), p))
+                    }
+                    "view" => Ok((Scope::View, p)),
+                    "layers" => Ok((Scope::Layers, p)),
+                    "views" => Ok((Scope::Views, p)),
+                    scope => Err((
+                        memoir::result::Error::new(format!("unknown scope '{}'", scope)),
+                        input,
+                    )),
+                }
+            },
+            "<scope>",
+        )
+    }
+}
+
 /// User command. Most of the interactions available to
 /// the user are modeled as commands that are processed
 /// by the session.
@@ -41,6 +96,11 @@ pub enum Command {
     BrushToggle(BrushMode),
     BrushSize(Op),
     BrushUnset(BrushMode),
+    BrushCapture,
+    BrushShape(Tip),
+    BrushReset,
+    BrushSave(String),
+    BrushLoad(String),
 
     #[allow(dead_code)]
     Crop(Rect<u32>),
@@ -59,6 +119,10 @@ pub enum Command {
     ForceQuit,
     ForceQuitAll,
     Source(Option<String>),
+    SourceStrict(Option<String>),
+    MkConfig(Option<String>, bool),
+    Errors,
+    Messages(Option<MessageType>),
 
     // Frames
     FrameAdd,
@@ -69,9 +133,12 @@ pub enum Command {
     FrameResize(u32, u32),
 
     // Palette
-    PaletteAdd(Rgba8),
+    PaletteAdd(ColorRef),
+    ColorDef(String, Rgba8),
+    ColorReplace(ColorRef, ColorRef, Scope),
     PaletteClear,
-    PaletteGradient(Rgba8, Rgba8, usize),
+    PaletteGradient(ColorRef, ColorRef, usize),
+    PaletteRamp(Vec<ColorRef>),
     PaletteSample,
     PaletteSort,
     PaletteWrite(String),
@@ -82,11 +149,16 @@ pub enum Command {
 
     // TODO: These operate on the active layer. We should have a command
     // to set the active layer.
-    PaintColor(Rgba8, i32, i32),
+    PaintColor(ColorRef, i32, i32),
     PaintForeground(i32, i32),
     PaintBackground(i32, i32),
     PaintPalette(usize, i32, i32),
-    PaintLine(Rgba8, i32, i32, i32, i32),
+    PaintLine(ColorRef, i32, i32, i32, i32),
+    PaintRect(i32, i32, i32, i32),
+    PaintEllipse(i32, i32, i32, i32),
+    PaintGradient(i32, i32, i32, i32),
+    CurveCommit,
+    CurveCancel,
 
     // Selection
     SelectionMove(i32, i32),
@@ -96,20 +168,24 @@ pub enum Command {
     SelectionPaste,
     SelectionYank,
     SelectionCut,
-    SelectionFill(Option<Rgba8>),
+    SelectionFill(Option<ColorRef>),
     SelectionErase,
     SelectionJump(Direction),
     SelectionFlip(Axis),
 
     // Settings
     Set(String, Value),
+    Let(String, Value),
+    Settings,
     Toggle(String),
     Reset,
     Map(Box<KeyMapping>),
     MapClear,
+    Unmap(KeySequence, Vec<Mode>),
+    Maps(Option<Mode>, Option<KeySequence>),
 
     Slice(Option<usize>),
-    Fill(Option<Rgba8>),
+    Fill(Option<ColorRef>),
 
     SwapColors,
 
@@ -130,6 +206,14 @@ pub enum Command {
     LayerRemove(Option<LayerId>),
     LayerExtend(Option<LayerId>),
 
+    // Macros
+    MacroRecord(Option<char>),
+    MacroStop,
+    MacroPlay(Option<char>, usize),
+    MacroAppend(char, Box<Command>),
+    MacroClear(Option<char>),
+    MacroWrite(String),
+
     Noop,
 }
 
@@ -150,6 +234,17 @@ impl Command {
                 | Self::SelectionOffset(_, _)
         )
     }
+
+    /// Apply a count to the command, eg. from a `5l` key sequence. Returns the
+    /// command to run, and how many times to run it.
+    pub fn with_count(self, n: usize) -> (Self, usize) {
+        match self {
+            Self::MacroPlay(r, _) => (Self::MacroPlay(r, n), 1),
+            Self::FramePrev | Self::FrameNext => (self, n),
+            cmd if cmd.repeats() => (cmd, n),
+            cmd => (cmd, 1),
+        }
+    }
 }
 
 impl fmt::Display for Command {
@@ -162,6 +257,9 @@ impl fmt::Display for Command {
             Self::BrushSize(Op::Decr) => write!(f, "Decrease brush size"),
             Self::BrushSize(Op::Set(s)) => write!(f, "Set brush size to {}", s),
             Self::BrushUnset(m) => write!(f, "Unset brush `{}` mode", m),
+            Self::BrushCapture => write!(f, "Use selection as brush stamp"),
+            Self::BrushShape(tip) => write!(f, "Set brush shape to `{}`", tip),
+            Self::BrushReset => write!(f, "Stop using brush stamp"),
             Self::Crop(_) => write!(f, "Crop view"),
             Self::ChangeDir(_) => write!(f, "Change the current working directory"),
             Self::Echo(_) => write!(f, "Echo a value"),
@@ -173,6 +271,8 @@ impl fmt::Display for Command {
             Self::ForceQuitAll => write!(f, "Quit all views without saving"),
             Self::Map(_) => write!(f, "Map a key combination to a command"),
             Self::MapClear => write!(f, "Clear all key mappings"),
+            Self::Unmap(_, _) => write!(f, "Remove a key mapping"),
+            Self::Maps(_, _) => write!(f, "List key mappings"),
             Self::Mode(Mode::Help) => write!(f, "Toggle help"),
             Self::Mode(m) => write!(f, "Switch to {} mode", m),
             Self::FrameAdd => write!(f, "Add a blank frame to the view"),
@@ -182,6 +282,10 @@ impl fmt::Display for Command {
             Self::FrameNext => write!(f, "Navigate to next frame"),
             Self::Noop => write!(f, "No-op"),
             Self::PaletteAdd(c) => write!(f, "Add {color} to palette", color = c),
+            Self::ColorDef(name, c) => write!(f, "Define color `{}` as {}", name, c),
+            Self::ColorReplace(from, to, scope) => {
+                write!(f, "Replace {} with {} in {}", from, to, scope)
+            }
             Self::PaletteClear => write!(f, "Clear palette"),
             Self::PaletteGradient(cs, ce, n) => write!(
                 f,
@@ -190,6 +294,7 @@ impl fmt::Display for Command {
                 colorstart = cs,
                 colorend = ce
             ),
+            Self::PaletteRamp(colors) => write!(f, "Add a {} color shading ramp", colors.len()),
             Self::PaletteSample => write!(f, "Sample palette from view"),
             Self::PaletteSort => write!(f, "Sort palette colors"),
             Self::Pan(x, 0) if *x > 0 => write!(f, "Pan workspace right"),
@@ -205,11 +310,35 @@ impl fmt::Display for Command {
             Self::Tool(Tool::Brush) => write!(f, "Brush tool"),
             Self::Tool(Tool::Sampler) => write!(f, "Color sampler tool"),
             Self::Tool(Tool::FloodFill) => write!(f, "Flood fill tool"),
+            Self::Tool(Tool::Rectangle) => write!(f, "Rectangle tool"),
+            Self::Tool(Tool::Ellipse) => write!(f, "Ellipse tool"),
+            Self::Tool(Tool::Curve) => write!(f, "Curve tool"),
+            Self::Tool(Tool::Gradient) => write!(f, "Gradient tool"),
             Self::ToolPrev => write!(f, "Switch to previous tool"),
             Self::Set(s, v) => write!(f, "Set {setting} to {val}", setting = s, val = v),
+            Self::Let(s, v) => write!(f, "Set variable ${var} to {val}", var = s, val = v),
+            Self::Settings => write!(f, "List settings"),
+            Self::MacroRecord(Some(r)) => write!(f, "Record macro into register `{}`", r),
+            Self::MacroRecord(None) => write!(f, "Record macro (toggle)"),
+            Self::MacroStop => write!(f, "Stop recording macro"),
+            Self::MacroPlay(Some(r), 1) => write!(f, "Play macro in register `{}`", r),
+            Self::MacroPlay(Some(r), n) => write!(f, "Play macro in register `{}` {} times", r, n),
+            Self::MacroPlay(None, _) => write!(f, "Play macro"),
+            Self::MacroAppend(r, _) => write!(f, "Append command to register `{}`", r),
+            Self::MacroClear(Some(r)) => write!(f, "Clear register `{}`", r),
+            Self::MacroClear(None) => write!(f, "Clear all macro registers"),
+            Self::MacroWrite(_) => write!(f, "Write macros to a file"),
             Self::Slice(Some(n)) => write!(f, "Slice view into {} frame(s)", n),
             Self::Slice(None) => write!(f, "Reset view slices"),
             Self::Source(_) => write!(f, "Source an rx script (eg. a palette)"),
+            Self::SourceStrict(_) => {
+                write!(f, "Source an rx script, stopping at the first error")
+            }
+            Self::MkConfig(_, _) => {
+                write!(f, "Write settings, key mappings and palette to a script")
+            }
+            Self::Errors => write!(f, "List errors from sourced scripts"),
+            Self::Messages(_) => write!(f, "List recent messages"),
             Self::SwapColors => write!(f, "Swap foreground & background colors"),
             Self::Toggle(s) => write!(f, "Toggle {setting} on/off", setting = s),
             Self::Undo => write!(f, "Undo view edit"),
@@ -252,53 +381,199 @@ impl fmt::Display for Command {
 
 impl From<Command> for String {
     fn from(cmd: Command) -> Self {
+        fn brush_mode(m: BrushMode) -> String {
+            match m {
+                BrushMode::Line(Some(snap)) => format!("line {}", snap),
+                other => other.to_string(),
+            }
+        }
+        fn register(r: Option<char>) -> String {
+            r.map_or(String::new(), |r| format!(" {}", r))
+        }
+
         match cmd {
             Command::Brush => format!("brush"),
-            Command::BrushSet(m) => format!("brush/set {}", m),
+            Command::BrushSet(m) => format!("brush/set {}", brush_mode(m)),
+            Command::BrushToggle(m) => format!("brush/toggle {}", brush_mode(m)),
             Command::BrushSize(Op::Incr) => format!("brush/size +"),
             Command::BrushSize(Op::Decr) => format!("brush/size -"),
             Command::BrushSize(Op::Set(s)) => format!("brush/size {}", s),
-            Command::BrushUnset(m) => format!("brush/unset {}", m),
-            Command::Echo(_) => unimplemented!(),
-            Command::Edit(_) => unimplemented!(),
+            Command::BrushUnset(m) => format!("brush/unset {}", brush_mode(m)),
+            Command::BrushCapture => format!("brush/capture"),
+            Command::BrushShape(tip) => format!("brush/shape {}", tip),
+            Command::BrushReset => format!("brush/reset"),
+            Command::BrushSave(path) => format!("brush/save {}", path),
+            Command::BrushLoad(path) => format!("brush/load {}", path),
+            Command::ChangeDir(None) => format!("cd"),
+            Command::ChangeDir(Some(path)) => format!("cd {}", path),
+            Command::Echo(v) => format!("echo {}", v.literal()),
+            Command::Edit(paths) => format!("e {}", paths.join(" ")),
+            Command::EditFrames(paths) => format!("e/frames {}", paths.join(" ")),
             Command::Fill(Some(c)) => format!("v/fill {}", c),
             Command::Fill(None) => format!("v/fill"),
             Command::ForceQuit => format!("q!"),
             Command::ForceQuitAll => format!("qa!"),
-            Command::Map(_) => format!("map <key> <command> {{<command>}}"),
+            Command::Map(km) => {
+                let KeyMapping {
+                    input,
+                    prefix,
+                    modifiers,
+                    press,
+                    release,
+                    modes,
+                } = *km;
+
+                let map = format!("map{}", map_suffix(&modes));
+                let input = KeySequence {
+                    input,
+                    prefix,
+                    modifiers,
+                };
+                let press = String::from(press);
+
+                match release {
+                    Some(release) => format!(
+                        "{} {} :{} {{:{}}}",
+                        map,
+                        input,
+                        press,
+                        String::from(release)
+                    ),
+                    None => format!("{} {} :{}", map, input, press),
+                }
+            }
+            Command::MapClear => format!("map/clear!"),
+            Command::Unmap(keys, modes) => format!("unmap{} {}", map_suffix(&modes), keys),
+            Command::Maps(mode, keys) => {
+                let mut s = String::from("maps");
+                if let Some(m) = mode {
+                    s.push_str(&format!(" {}", m));
+                }
+                if let Some(k) = keys {
+                    s.push_str(&format!(" {}", k));
+                }
+                s
+            }
+            Command::Mode(Mode::Help) => format!("help"),
+            Command::Mode(Mode::Visual(_)) => format!("mode visual"),
             Command::Mode(m) => format!("mode {}", m),
             Command::FrameAdd => format!("f/add"),
             Command::FrameClone(i) => format!("f/clone {}", i),
             Command::FrameRemove => format!("f/remove"),
+            Command::FramePrev => format!("f/prev"),
+            Command::FrameNext => format!("f/next"),
+            Command::FrameResize(w, h) => format!("f/resize {} {}", w, h),
             Command::Export(None, path) => format!("export {}", path),
             Command::Export(Some(s), path) => format!("export @{}x {}", s, path),
+            Command::LayerAdd => format!("l/add"),
+            Command::MacroRecord(r) => format!("macro/record{}", register(r)),
+            Command::MacroStop => format!("macro/stop"),
+            Command::MacroPlay(r, 1) => format!("macro/play{}", register(r)),
+            Command::MacroPlay(r, n) => format!("macro/play{} {}", register(r), n),
+            Command::MacroAppend(r, cmd) => format!("macro/append {} :{}", r, String::from(*cmd)),
+            Command::MacroClear(r) => format!("macro/clear{}", register(r)),
+            Command::MacroWrite(path) => format!("macro/write {}", path),
             Command::Noop => format!(""),
+            Command::PaintColor(c, x, y) => format!("paint/color {} {} {}", c, x, y),
+            Command::PaintForeground(x, y) => format!("paint/fg {} {}", x, y),
+            Command::PaintBackground(x, y) => format!("paint/bg {} {}", x, y),
+            Command::PaintPalette(i, x, y) => format!("paint/p {} {} {}", i, x, y),
+            Command::PaintLine(c, x1, y1, x2, y2) => {
+                format!("paint/line {} {} {} {} {}", c, x1, y1, x2, y2)
+            }
+            Command::PaintRect(x1, y1, x2, y2) => {
+                format!("paint/rect {} {} {} {}", x1, y1, x2, y2)
+            }
+            Command::PaintEllipse(x1, y1, x2, y2) => {
+                format!("paint/ellipse {} {} {} {}", x1, y1, x2, y2)
+            }
+            Command::PaintGradient(x1, y1, x2, y2) => {
+                format!("paint/gradient {} {} {} {}", x1, y1, x2, y2)
+            }
+            Command::CurveCommit => format!("curve/commit"),
+            Command::CurveCancel => format!("curve/cancel"),
             Command::PaletteAdd(c) => format!("p/add {}", c),
+            Command::ColorDef(name, c) => format!("color/def {} {}", name, c),
+            Command::ColorReplace(from, to, scope) => {
+                format!("color/replace {} {} {}", from, to, scope)
+            }
             Command::PaletteClear => format!("p/clear"),
-            Command::PaletteWrite(_) => format!("p/write"),
+            Command::PaletteWrite(path) => format!("p/write {}", path),
             Command::PaletteSample => format!("p/sample"),
+            Command::PaletteSort => format!("p/sort"),
             Command::PaletteGradient(cs, ce, n) => format!("p/gradient {} {} {}", cs, ce, n),
+            Command::PaletteRamp(colors) => format!(
+                "p/ramp {}",
+                colors
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
             Command::Pan(x, y) => format!("pan {} {}", x, y),
             Command::Quit => format!("q"),
+            Command::QuitAll => format!("qa"),
             Command::Redo => format!("redo"),
-            Command::FrameResize(w, h) => format!("f/resize {} {}", w, h),
-            Command::Set(s, v) => format!("set {} = {}", s, v),
+            Command::Reset => format!("reset!"),
+            Command::SelectionMove(x, y) => format!("selection/move {} {}", x, y),
+            Command::SelectionResize(x, y) => format!("selection/resize {} {}", x, y),
+            Command::SelectionOffset(x, y) => format!("selection/offset {} {}", x, y),
+            Command::SelectionExpand => format!("selection/expand"),
+            Command::SelectionPaste => format!("selection/paste"),
+            Command::SelectionYank => format!("selection/yank"),
+            Command::SelectionCut => format!("selection/cut"),
+            Command::SelectionFill(Some(c)) => format!("selection/fill {}", c),
+            Command::SelectionFill(None) => format!("selection/fill"),
+            Command::SelectionErase => format!("selection/erase"),
+            Command::SelectionJump(Direction::Forward) => format!("selection/jump +"),
+            Command::SelectionJump(Direction::Backward) => format!("selection/jump -"),
+            Command::SelectionFlip(Axis::Horizontal) => format!("selection/flip x"),
+            Command::SelectionFlip(Axis::Vertical) => format!("selection/flip y"),
+            Command::Set(s, v) => format!("set {} = {}", s, v.literal()),
+            Command::Let(s, v) => format!("let {} = {}", s, v.literal()),
+            Command::Settings => format!("set"),
             Command::Slice(Some(n)) => format!("slice {}", n),
             Command::Slice(None) => format!("slice"),
             Command::Source(Some(path)) => format!("source {}", path),
+            Command::Source(None) => format!("source"),
+            Command::SourceStrict(Some(path)) => format!("source! {}", path),
+            Command::SourceStrict(None) => format!("source!"),
+            Command::MkConfig(path, force) => {
+                let cmd = if force { "mkconfig!" } else { "mkconfig" };
+                match path {
+                    Some(path) => format!("{} {}", cmd, path),
+                    None => cmd.to_owned(),
+                }
+            }
+            Command::Errors => format!("errors"),
+            Command::Messages(None) => format!("messages"),
+            Command::Messages(Some(t)) => format!("messages {}", t),
             Command::SwapColors => format!("swap"),
             Command::Toggle(s) => format!("toggle {}", s),
+            Command::Tool(Tool::Brush) => format!("tool brush"),
+            Command::Tool(Tool::Sampler) => format!("tool sampler"),
+            Command::Tool(Tool::Pan(_)) => format!("tool pan"),
+            Command::Tool(Tool::FloodFill) => format!("flood"),
+            Command::Tool(Tool::Rectangle) => format!("tool rect"),
+            Command::Tool(Tool::Ellipse) => format!("tool ellipse"),
+            Command::Tool(Tool::Curve) => format!("tool curve"),
+            Command::Tool(Tool::Gradient) => format!("tool gradient"),
+            Command::ToolPrev => format!("tool/prev"),
             Command::Undo => format!("undo"),
             Command::ViewCenter => format!("v/center"),
             Command::ViewNext => format!("v/next"),
             Command::ViewPrev => format!("v/prev"),
             Command::Write(None) => format!("w"),
             Command::Write(Some(path)) => format!("w {}", path),
+            Command::WriteFrames(None) => format!("w/frames"),
+            Command::WriteFrames(Some(dir)) => format!("w/frames {}", dir),
             Command::WriteQuit => format!("wq"),
-            Command::Zoom(Op::Incr) => format!("v/zoom +"),
-            Command::Zoom(Op::Decr) => format!("v/zoom -"),
-            Command::Zoom(Op::Set(z)) => format!("v/zoom {}", z),
-            _ => unimplemented!(),
+            Command::Zoom(Op::Incr) => format!("zoom +"),
+            Command::Zoom(Op::Decr) => format!("zoom -"),
+            Command::Zoom(Op::Set(z)) => format!("zoom {}", z),
+            Command::Crop(_) | Command::LayerRemove(_) | Command::LayerExtend(_) => {
+                unimplemented!()
+            }
         }
     }
 }
@@ -308,6 +583,10 @@ impl From<Command> for String {
 #[derive(PartialEq, Debug, Clone)]
 pub struct KeyMapping {
     pub input: Input,
+    /// Inputs which must precede `input`, for key sequences like `g g`.
+    pub prefix: Vec<Input>,
+    /// Modifiers which must be held with `input`.
+    pub modifiers: platform::ModifiersState,
     pub press: Command,
     pub release: Option<Command>,
     pub modes: Vec<Mode>,
@@ -341,30 +620,182 @@ impl KeyMapping {
             "<cmd>",
         );
 
-        let character = between('\'', '\'', character())
-            .map(Input::Character)
-            .skip(whitespace())
-            .then(press.clone())
-            .map(|(input, press)| ((input, press), None));
-        let key = param::<platform::Key>()
-            .map(Input::Key)
-            .skip(whitespace())
+        many::<_, Vec<(platform::ModifiersState, Input)>>(KeySequence::input().skip(whitespace()))
             .then(press)
             .skip(optional(whitespace()))
-            .then(optional(between('{', '}', release)));
+            .then(optional(between('{', '}', release)))
+            .try_map(move |((inputs, press), release)| {
+                if inputs.len() > 1 && release.is_some() {
+                    return Err("key sequences can't have a release command".to_owned());
+                }
+                let KeySequence {
+                    input,
+                    prefix,
+                    modifiers,
+                } = KeySequence::from_inputs(inputs)?;
+
+                if let (Input::Character(_), Some(_)) = (input, &release) {
+                    return Err("character mappings can't have a release command".to_owned());
+                }
 
-        character
-            .or(key)
-            .map(move |((input, press), release)| KeyMapping {
-                input,
-                press,
-                release,
-                modes: modes.clone(),
+                Ok(KeyMapping {
+                    input,
+                    prefix,
+                    modifiers,
+                    press,
+                    release,
+                    modes: modes.clone(),
+                })
             })
             .label("<key> <cmd>") // TODO: We should provide the full command somehow.
     }
 }
 
+/// A sequence of inputs identifying a key mapping, eg. `g g` or `<ctrl>z`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct KeySequence {
+    pub input: Input,
+    /// Inputs which must precede `input`.
+    pub prefix: Vec<Input>,
+    /// Modifiers which must be held with `input`.
+    pub modifiers: platform::ModifiersState,
+}
+
+impl KeySequence {
+    /// Build a key sequence from a list of inputs and the modifiers held with each.
+    fn from_inputs(mut inputs: Vec<(platform::ModifiersState, Input)>) -> Result<Self, String> {
+        let (modifiers, mut input) = inputs.pop().ok_or("expected at least one input")?;
+
+        // Key sequences are matched on keys, so characters are converted to
+        // their equivalent key.
+        let sequence_input = |i: Input| match i {
+            Input::Character(c) => match platform::Key::from(c) {
+                platform::Key::Unknown => {
+                    Err(format!("character {:?} can't be used in a key sequence", c))
+                }
+                key => Ok(Input::Key(key)),
+            },
+            Input::Mouse(_) | Input::Wheel(_) => {
+                Err(format!("{} can't be used in a key sequence", i))
+            }
+            other => Ok(other),
+        };
+
+        if !inputs.is_empty() {
+            input = sequence_input(input)?;
+        }
+        let mut prefix = Vec::with_capacity(inputs.len());
+        for (mods, i) in inputs {
+            if mods != platform::ModifiersState::default() {
+                return Err("modifiers are only supported on the last key of a sequence".to_owned());
+            }
+            prefix.push(sequence_input(i)?);
+        }
+        Ok(Self {
+            input,
+            prefix,
+            modifiers,
+        })
+    }
+
+    /// Parse a single input, with its modifiers.
+    fn input() -> Parser<(platform::ModifiersState, Input)> {
+        // Nb. A space after the modifiers is never taken as the input itself.
+        let input = expect(|s| !s.starts_with(char::is_whitespace), "<input>")
+            .then(param::<Input>())
+            .map(|(_, i)| i);
+
+        peek(modifiers().then(input))
+            .or(param::<Input>().map(|i| (platform::ModifiersState::default(), i)))
+    }
+}
+
+impl Parse for KeySequence {
+    fn parser() -> Parser<Self> {
+        Self::input()
+            .then(any::<_, Vec<_>>(
+                peek(whitespace().then(Self::input())).map(|(_, i)| i),
+            ))
+            .try_map(|(first, mut rest)| {
+                rest.insert(0, first);
+                Self::from_inputs(rest)
+            })
+            .label("<key>..")
+    }
+}
+
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fmt_input = |i: &Input| match i {
+            Input::Character(c) => format!("'{}'", c),
+            other => other.to_string(),
+        };
+        for i in self.prefix.iter() {
+            write!(f, "{} ", fmt_input(i))?;
+        }
+        write!(f, "{}{}", self.modifiers, fmt_input(&self.input))
+    }
+}
+
+/// The suffix of the `map` and `unmap` commands for the given modes, eg. `/normal`.
+fn map_suffix(modes: &[Mode]) -> &'static str {
+    match modes {
+        [Mode::Normal] => "/normal",
+        [Mode::Help] => "/help",
+        [Mode::Visual(_), Mode::Visual(_)] => "/visual",
+        _ => "",
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A color argument. Either a color literal, or a reference to a palette
+/// entry (`@3`) or color name (`@skin`) that is resolved when the command runs.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ColorRef {
+    Color(Rgba8),
+    Palette(usize),
+    Named(String),
+}
+
+impl ColorRef {
+    /// A color reference, eg. `@3` or `@skin`.
+    pub fn reference() -> Parser<Self> {
+        symbol('@')
+            .then(
+                natural::<usize>()
+                    .map(ColorRef::Palette)
+                    .or(identifier().map(ColorRef::Named)),
+            )
+            .map(|(_, r)| r)
+            .label("@<index>/@<name>")
+    }
+}
+
+impl From<Rgba8> for ColorRef {
+    fn from(color: Rgba8) -> Self {
+        Self::Color(color)
+    }
+}
+
+impl fmt::Display for ColorRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Color(c) => c.fmt(f),
+            Self::Palette(i) => write!(f, "@{}", i),
+            Self::Named(name) => write!(f, "@{}", name),
+        }
+    }
+}
+
+impl Parse for ColorRef {
+    fn parser() -> Parser<Self> {
+        Self::reference()
+            .or(color().map(ColorRef::Color))
+            .label("<color>")
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone, PartialEq, Debug)]
@@ -377,6 +808,7 @@ pub enum Value {
     Str(String),
     Ident(String),
     Rgba8(Rgba8),
+    ColorRef(ColorRef),
 }
 
 impl Value {
@@ -418,10 +850,163 @@ impl Value {
             Self::Str(_) => "string, eg. \"fnord\"",
             Self::Rgba8(_) => "color, eg. #ffff00",
             Self::Ident(_) => "identifier, eg. fnord",
+            Self::ColorRef(_) => "color reference, eg. @3",
+        }
+    }
+
+    /// Return the value as a literal that can be parsed back into the same value.
+    /// Unlike the `Display` implementation, this preserves the value's type.
+    pub fn literal(&self) -> String {
+        match self {
+            Self::F64(x) => format!("{:?}", x),
+            Self::U32Tuple(x, y) => format!("{} {}", x, y),
+            Self::F32Tuple(x, y) => format!("{:?} {:?}", x, y),
+            Self::Str(s) => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+            other => other.to_string(),
+        }
+    }
+
+    /// Apply a binary operator to two values. Numbers and tuples support
+    /// the usual arithmetic, colors can be lightened or darkened by a fraction,
+    /// eg. `#ff0000 + 10%`, scaled, or combined channel-wise with other colors.
+    pub fn apply(self, op: Operator, rhs: Value) -> Result<Value, String> {
+        use Operator::*;
+
+        fn arith(op: Operator, a: f64, b: f64) -> Result<f64, String> {
+            match op {
+                Add => Ok(a + b),
+                Sub => Ok(a - b),
+                Mul => Ok(a * b),
+                Div if b == 0. => Err("division by zero".to_owned()),
+                Div => Ok(a / b),
+            }
+        }
+
+        fn unsigned(op: Operator, a: u32, b: u32) -> Result<u32, String> {
+            match op {
+                Add => a.checked_add(b),
+                Sub => a.checked_sub(b),
+                Mul => a.checked_mul(b),
+                Div => a.checked_div(b),
+            }
+            .ok_or_else(|| format!("invalid operation `{} {} {}`", a, op, b))
+        }
+
+        fn scalar(v: &Value) -> Option<f64> {
+            match v {
+                Value::U32(n) => Some(*n as f64),
+                Value::F64(n) => Some(*n),
+                _ => None,
+            }
+        }
+
+        fn channel(c: f64) -> u8 {
+            c.round().max(0.).min(u8::MAX as f64) as u8
+        }
+
+        match (self, rhs) {
+            (Value::U32(a), Value::U32(b)) => unsigned(op, a, b).map(Value::U32),
+            (Value::U32Tuple(x, y), Value::U32(n)) => {
+                Ok(Value::U32Tuple(unsigned(op, x, n)?, unsigned(op, y, n)?))
+            }
+            (Value::U32Tuple(x1, y1), Value::U32Tuple(x2, y2)) => Ok(Value::U32Tuple(
+                unsigned(op, x1, x2)?,
+                unsigned(op, y1, y2)?,
+            )),
+            (Value::F32Tuple(x1, y1), Value::F32Tuple(x2, y2)) => Ok(Value::F32Tuple(
+                arith(op, x1 as f64, x2 as f64)? as f32,
+                arith(op, y1 as f64, y2 as f64)? as f32,
+            )),
+            (Value::F32Tuple(x, y), ref n) if scalar(n).is_some() => {
+                let n = scalar(n).unwrap_or_default();
+                Ok(Value::F32Tuple(
+                    arith(op, x as f64, n)? as f32,
+                    arith(op, y as f64, n)? as f32,
+                ))
+            }
+            (Value::Str(a), Value::Str(b)) if op == Add => Ok(Value::Str(a + &b)),
+            (Value::Rgba8(a), Value::Rgba8(b)) if op == Add || op == Sub => {
+                let f = |x: u8, y: u8| channel(arith(op, x as f64, y as f64).unwrap_or_default());
+                Ok(Value::Rgba8(Rgba8::new(
+                    f(a.r, b.r),
+                    f(a.g, b.g),
+                    f(a.b, b.b),
+                    a.a,
+                )))
+            }
+            (Value::Rgba8(c), ref n) if scalar(n).is_some() => {
+                let n = scalar(n).unwrap_or_default();
+                let f = |x: u8| {
+                    let x = x as f64;
+                    match op {
+                        // Move the channel towards white.
+                        Add => channel(x + (u8::MAX as f64 - x) * n),
+                        // Move the channel towards black.
+                        Sub => channel(x * (1. - n)),
+                        Mul => channel(x * n),
+                        Div => channel(arith(op, x, n).unwrap_or_default()),
+                    }
+                };
+                if op == Div && n == 0. {
+                    return Err("division by zero".to_owned());
+                }
+                Ok(Value::Rgba8(Rgba8::new(f(c.r), f(c.g), f(c.b), c.a)))
+            }
+            (ref a, ref b) => match (scalar(a), scalar(b)) {
+                (Some(a), Some(b)) => arith(op, a, b).map(Value::F64),
+                _ => Err(format!(
+                    "can't apply `{}` to `{}` and `{}`",
+                    op,
+                    a.literal(),
+                    b.literal()
+                )),
+            },
+        }
+    }
+}
+
+/// A binary operator used in value expressions.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Add => "+".fmt(f),
+            Self::Sub => "-".fmt(f),
+            Self::Mul => "*".fmt(f),
+            Self::Div => "/".fmt(f),
+        }
+    }
+}
+
+impl Operator {
+    /// How tightly the operator binds. Operators with a higher precedence are
+    /// applied first.
+    pub fn precedence(self) -> u8 {
+        match self {
+            Self::Add | Self::Sub => 1,
+            Self::Mul | Self::Div => 2,
         }
     }
 }
 
+impl Parse for Operator {
+    fn parser() -> Parser<Self> {
+        symbol('+')
+            .value(Operator::Add)
+            .or(symbol('-').value(Operator::Sub))
+            .or(symbol('*').value(Operator::Mul))
+            .or(symbol('/').value(Operator::Div))
+            .label("+/-/*//")
+    }
+}
+
 impl From<Value> for (u32, u32) {
     fn from(other: Value) -> (u32, u32) {
         if let Value::U32Tuple(x, y) = other {
@@ -461,6 +1046,7 @@ impl fmt::Display for Value {
             Value::Str(s) => s.fmt(f),
             Value::Rgba8(c) => c.fmt(f),
             Value::Ident(i) => i.fmt(f),
+            Value::ColorRef(r) => r.fmt(f),
         }
     }
 }
@@ -474,24 +1060,70 @@ impl Parse for Value {
         let f64_tuple_val =
             tuple::<f32>(rational(), rational()).map(|(x, y)| Value::F32Tuple(x, y));
         let f64_val = rational::<f64>().map(Value::F64).label("0.0 .. 4096.0");
+        let percent_val = rational::<f64>()
+            .skip(symbol('%'))
+            .map(|n| Value::F64(n / 100.))
+            .label("<percent>");
         let bool_val = string("on")
             .value(Value::Bool(true))
             .or(string("off").value(Value::Bool(false)))
             .label("on/off");
         let ident_val = identifier().map(Value::Ident);
+        let ref_val = ColorRef::reference().map(Value::ColorRef);
 
-        greediest(vec![
+        let term = greediest(vec![
             rgba8_val,
+            ref_val,
             u32_tuple_val,
             f64_tuple_val,
             u32_val,
             f64_val,
+            percent_val,
             bool_val,
             ident_val,
             str_val,
-        ])
-        .label("<value>")
+        ]);
+
+        // Operators must be surrounded by whitespace, since eg. `-` and `/`
+        // are valid identifier characters.
+        let operation = whitespace()
+            .then(param::<Operator>())
+            .skip(whitespace())
+            .then(term.clone())
+            .map(|((_, op), v)| (op, v));
+
+        term.then(any::<_, Vec<_>>(operation))
+            .try_map(|(init, ops)| evaluate(init, &mut ops.into_iter().peekable(), 0))
+            .label("<value>")
+    }
+}
+
+/// Evaluate an expression by precedence climbing: operators with a higher
+/// precedence are applied first, and operators of equal precedence are applied
+/// from left to right. Only operators of at least the given precedence are
+/// consumed.
+fn evaluate<I>(lhs: Value, ops: &mut Peekable<I>, min: u8) -> Result<Value, String>
+where
+    I: Iterator<Item = (Operator, Value)>,
+{
+    let mut lhs = lhs;
+
+    while let Some(op) = ops.peek().map(|(op, _)| *op) {
+        if op.precedence() < min {
+            break;
+        }
+        let (_, mut rhs) = ops.next().expect("the operator was peeked");
+
+        // Operators which bind tighter take the right-hand side first.
+        while let Some(next) = ops.peek().map(|(op, _)| op.precedence()) {
+            if next <= op.precedence() {
+                break;
+            }
+            rhs = evaluate(rhs, ops, next)?;
+        }
+        lhs = lhs.apply(op, rhs)?;
     }
+    Ok(lhs)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -507,6 +1139,8 @@ pub struct CommandLine {
     pub parser: Parser<Command>,
     /// Commands.
     pub commands: Commands,
+    /// Reverse history search, if one is in progress.
+    pub search: Option<HistorySearch>,
     /// The current input string displayed to the user.
     input: String,
     /// File extensions supported.
@@ -524,6 +1158,7 @@ impl CommandLine {
             cursor: 0,
             parser: cmds.line_parser(),
             commands: cmds,
+            search: None,
             history: History::new(history_path, 1024),
             autocomplete: Autocomplete::new(CommandCompleter::new(cwd, extensions)),
             extensions: extensions.iter().map(|e| (*e).into()).collect(),
@@ -568,6 +1203,16 @@ impl CommandLine {
         }
     }
 
+    /// The usage and help text of the command being typed, if any.
+    pub fn help(&self) -> Option<String> {
+        let name = self.input.strip_prefix(':')?.split_whitespace().next()?;
+
+        self.commands
+            .iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, help, parser)| format!("{}: {}", parser, help))
+    }
+
     pub fn completion_next(&mut self) {
         let prefix = self.prefix();
 
@@ -612,12 +1257,104 @@ impl CommandLine {
     }
 
     pub fn puts(&mut self, s: &str) {
-        // TODO: Check capacity.
-        self.input.push_str(s);
-        self.cursor += s.len();
+        for c in s.chars().filter(|c| !c.is_control()) {
+            self.putc(c);
+        }
+    }
+
+    pub fn cursor_home(&mut self) {
+        self.cursor = self.start();
         self.autocomplete.invalidate();
     }
 
+    pub fn cursor_end(&mut self) {
+        self.cursor = self.input.len();
+        self.autocomplete.invalidate();
+    }
+
+    /// Delete the word before the cursor.
+    pub fn delw(&mut self) {
+        let start = self.start();
+        let word = self.input[..self.cursor]
+            .trim_end()
+            .rfind(char::is_whitespace)
+            .map_or(start, |i| usize::max(i + 1, start));
+
+        if word < self.cursor {
+            self.input.replace_range(word..self.cursor, "");
+            self.cursor = word;
+            self.autocomplete.invalidate();
+        }
+    }
+
+    /// Delete everything before the cursor.
+    pub fn kill(&mut self) {
+        let start = self.start();
+
+        if start < self.cursor {
+            self.input.replace_range(start..self.cursor, "");
+            self.cursor = start;
+            self.autocomplete.invalidate();
+        }
+    }
+
+    /// Start a reverse history search, or search further back in
+    /// history if a search is already in progress.
+    pub fn search_start(&mut self) {
+        match &self.search {
+            Some(search) => {
+                let start = search.index.map_or(0, |i| i + 1);
+                self.search_from(start);
+            }
+            None => {
+                self.search = Some(HistorySearch {
+                    query: String::new(),
+                    index: None,
+                    failed: false,
+                    input: self.input.clone(),
+                });
+            }
+        }
+    }
+
+    pub fn search_putc(&mut self, c: char) {
+        if let Some(search) = &mut self.search {
+            search.query.push(c);
+
+            let start = search.index.unwrap_or(0);
+            self.search_from(start);
+        }
+    }
+
+    pub fn search_delc(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.query.pop();
+            search.index = None;
+
+            if search.query.is_empty() {
+                let input = search.input.clone();
+                search.failed = false;
+                self.replace(&input);
+                self.cursor_end();
+            } else {
+                self.search_from(0);
+            }
+        }
+    }
+
+    /// Stop searching, keeping the current match as input.
+    pub fn search_accept(&mut self) {
+        self.search = None;
+    }
+
+    /// Stop searching, and restore the input from before the search.
+    pub fn search_cancel(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.replace(&search.input);
+            self.cursor_end();
+        }
+    }
+
     pub fn delc(&mut self) {
         match self.peek_back() {
             // Don't allow deleting the ':' unless it's the last remaining character.
@@ -632,6 +1369,7 @@ impl CommandLine {
 
     pub fn clear(&mut self) {
         self.cursor = 0;
+        self.search = None;
         self.input.clear();
         self.history.reset();
         self.autocomplete.invalidate();
@@ -652,6 +1390,36 @@ impl CommandLine {
         self.putc(':');
     }
 
+    /// Position of the first editable character, after the `:` prefix.
+    fn start(&self) -> usize {
+        if self.input.starts_with(':') {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn search_from(&mut self, start: usize) {
+        if let Some(search) = &mut self.search {
+            if search.query.is_empty() {
+                return;
+            }
+            match self.history.search(&search.query, start) {
+                Some((index, entry)) => {
+                    let entry = entry.to_owned();
+
+                    search.index = Some(index);
+                    search.failed = false;
+                    self.replace(&entry);
+                    self.cursor_end();
+                }
+                None => {
+                    search.failed = true;
+                }
+            }
+        }
+    }
+
     fn prefix(&self) -> String {
         self.input[..self.cursor].to_owned()
     }
@@ -666,6 +1434,19 @@ impl CommandLine {
     }
 }
 
+/// An incremental reverse search through the command history.
+#[derive(Debug)]
+pub struct HistorySearch {
+    /// The text being searched for.
+    pub query: String,
+    /// Whether the last search came up empty.
+    pub failed: bool,
+    /// History index of the current match.
+    index: Option<usize>,
+    /// The input before the search started.
+    input: String,
+}
+
 pub struct Commands {
     commands: Vec<(&'static str, &'static str, Parser<Command>)>,
 }
@@ -676,7 +1457,10 @@ impl Commands {
             commands: vec![(
                 "#",
                 "Add color to palette",
-                color().map(Command::PaletteAdd),
+                // Only hex colors, so that color names don't shadow commands.
+                expect(|s| s.starts_with('#'), "#")
+                    .then(color())
+                    .map(|(_, rgba)| Command::PaletteAdd(rgba.into())),
             )],
         }
     }
@@ -759,16 +1543,27 @@ impl Default for Commands {
             .command("help", "Display help", |p| {
                 p.value(Command::Mode(Mode::Help))
             })
-            .command("set", "Set setting to value", |p| {
-                p.then(setting())
-                    .skip(optional(whitespace()))
-                    .then(optional(
+            .command("set", "Set setting to value, or list settings", |p| {
+                p.then(optional(
+                    setting().skip(optional(whitespace())).then(optional(
                         symbol('=')
                             .skip(optional(whitespace()))
                             .then(Value::parser())
                             .map(|(_, v)| v),
-                    ))
-                    .map(|((_, k), v)| Command::Set(k, v.unwrap_or(Value::Bool(true))))
+                    )),
+                ))
+                .map(|(_, kv)| match kv {
+                    Some((k, v)) => Command::Set(k, v.unwrap_or(Value::Bool(true))),
+                    None => Command::Settings,
+                })
+            })
+            .command("let", "Set variable to value", |p| {
+                p.then(identifier().label("<name>"))
+                    .skip(optional(whitespace()))
+                    .skip(symbol('='))
+                    .skip(optional(whitespace()))
+                    .then(Value::parser())
+                    .map(|((_, k), v)| Command::Let(k, v))
             })
             .command("unset", "Set setting to `off`", |p| {
                 p.then(setting())
@@ -789,6 +1584,41 @@ impl Default for Commands {
                 "Source an rx script (eg. palette or config)",
                 |p| p.then(optional(path())).map(|(_, p)| Command::Source(p)),
             )
+            .command(
+                "source!",
+                "Source an rx script, stopping at the first error",
+                |p| {
+                    p.then(optional(path()))
+                        .map(|(_, p)| Command::SourceStrict(p))
+                },
+            )
+            .command(
+                "mkconfig",
+                "Write settings, key mappings and palette to a script",
+                |p| {
+                    p.then(optional(path()))
+                        .map(|(_, p)| Command::MkConfig(p, false))
+                },
+            )
+            .command(
+                "mkconfig!",
+                "Write settings, key mappings and palette to a script, overwriting it",
+                |p| {
+                    p.then(optional(path()))
+                        .map(|(_, p)| Command::MkConfig(p, true))
+                },
+            )
+            .command("errors", "List errors from sourced scripts", |p| {
+                p.value(Command::Errors)
+            })
+            .command(
+                "messages",
+                "List recent messages, optionally of one type",
+                |p| {
+                    p.then(optional(param::<MessageType>()))
+                        .map(|(_, t)| Command::Messages(t))
+                },
+            )
             .command("cd", "Change current directory", |p| {
                 p.then(optional(path())).map(|(_, p)| Command::ChangeDir(p))
             })
@@ -839,6 +1669,22 @@ impl Default for Commands {
                 p.then(param::<BrushMode>())
                     .map(|(_, m)| Command::BrushToggle(m))
             })
+            .command("brush/capture", "Use the selection as a brush stamp", |p| {
+                p.value(Command::BrushCapture)
+            })
+            .command("brush/reset", "Stop using a brush stamp", |p| {
+                p.value(Command::BrushReset)
+            })
+            .command("brush/shape", "Set brush shape, eg. `circle`", |p| {
+                p.then(param::<Tip>())
+                    .map(|(_, tip)| Command::BrushShape(tip))
+            })
+            .command("brush/save", "Save the brush stamp to a file", |p| {
+                p.then(path()).map(|(_, path)| Command::BrushSave(path))
+            })
+            .command("brush/load", "Load a brush stamp from a file", |p| {
+                p.then(path()).map(|(_, path)| Command::BrushLoad(path))
+            })
             .command("brush", "Switch to brush", |p| {
                 p.value(Command::Tool(Tool::Brush))
             })
@@ -867,10 +1713,11 @@ impl Default for Commands {
                 p.value(Command::ViewCenter)
             })
             .command("v/clear", "Clear the active view", |p| {
-                p.value(Command::Fill(Some(Rgba8::TRANSPARENT)))
+                p.value(Command::Fill(Some(ColorRef::Color(Rgba8::TRANSPARENT))))
             })
             .command("v/fill", "Fill the active view", |p| {
-                p.then(optional(color())).map(|(_, c)| Command::Fill(c))
+                p.then(optional(param::<ColorRef>()))
+                    .map(|(_, c)| Command::Fill(c))
             })
             .command("pan", "Switch to the pan tool", |p| {
                 p.then(tuple::<i32>(integer().label("<x>"), integer().label("<y>")))
@@ -902,21 +1749,93 @@ impl Default for Commands {
             .command("map/clear!", "Clear all key mappings", |p| {
                 p.value(Command::MapClear)
             })
+            .command("unmap", "Remove a key mapping in all modes", |p| {
+                p.then(param::<KeySequence>()).map(|(_, keys)| {
+                    Command::Unmap(
+                        keys,
+                        vec![
+                            Mode::Normal,
+                            Mode::Visual(VisualState::selecting()),
+                            Mode::Visual(VisualState::Pasting),
+                        ],
+                    )
+                })
+            })
+            .command("unmap/visual", "Remove a key mapping in visual mode", |p| {
+                p.then(param::<KeySequence>()).map(|(_, keys)| {
+                    Command::Unmap(
+                        keys,
+                        vec![
+                            Mode::Visual(VisualState::selecting()),
+                            Mode::Visual(VisualState::Pasting),
+                        ],
+                    )
+                })
+            })
+            .command("unmap/normal", "Remove a key mapping in normal mode", |p| {
+                p.then(param::<KeySequence>())
+                    .map(|(_, keys)| Command::Unmap(keys, vec![Mode::Normal]))
+            })
+            .command("unmap/help", "Remove a key mapping in help mode", |p| {
+                p.then(param::<KeySequence>())
+                    .map(|(_, keys)| Command::Unmap(keys, vec![Mode::Help]))
+            })
+            .command("maps", "List key mappings", |p| {
+                p.then(optional(param::<Mode>().skip(optional(whitespace()))))
+                    .then(optional(param::<KeySequence>()))
+                    .map(|((_, mode), keys)| Command::Maps(mode, keys))
+            })
             .command("p/add", "Add a color to the palette", |p| {
-                p.then(color()).map(|(_, rgba)| Command::PaletteAdd(rgba))
+                p.then(param::<ColorRef>())
+                    .map(|(_, color)| Command::PaletteAdd(color))
+            })
+            .command("color/def", "Define a color name", |p| {
+                p.then(identifier().label("<name>"))
+                    .skip(whitespace())
+                    .then(color())
+                    .map(|((_, name), rgba)| Command::ColorDef(name, rgba))
             })
+            .command(
+                "color/replace",
+                "Replace every pixel of a color with another",
+                |p| {
+                    p.then(tuple::<ColorRef>(
+                        param::<ColorRef>().label("<from>"),
+                        param::<ColorRef>().label("<to>"),
+                    ))
+                    .then(optional(whitespace().then(param::<Scope>())))
+                    .map(|((_, (from, to)), scope)| {
+                        Command::ColorReplace(from, to, scope.map_or(Scope::View, |(_, s)| s))
+                    })
+                },
+            )
             .command("p/clear", "Clear the color palette", |p| {
                 p.value(Command::PaletteClear)
             })
             .command("p/gradient", "Add a gradient to the palette", |p| {
-                p.then(tuple::<Rgba8>(
-                    color().label("<from>"),
-                    color().label("<to>"),
+                p.then(tuple::<ColorRef>(
+                    param::<ColorRef>().label("<from>"),
+                    param::<ColorRef>().label("<to>"),
                 ))
                 .skip(whitespace())
                 .then(natural::<usize>().label("<count>"))
                 .map(|((_, (cs, ce)), n)| Command::PaletteGradient(cs, ce, n))
             })
+            .command("p/ramp", "Add a color ramp used for shading", |p| {
+                p.then(tuple::<ColorRef>(
+                    param::<ColorRef>().label("<color>"),
+                    param::<ColorRef>().label("<color>"),
+                ))
+                .then(any::<_, Vec<_>>(
+                    whitespace().then(param::<ColorRef>().label("<color>")),
+                ))
+                .map(|((_, (first, second)), rest)| {
+                    let mut colors = vec![first, second];
+                    colors.extend(rest.into_iter().map(|(_, c)| c));
+
+                    Command::PaletteRamp(colors)
+                })
+            })
             .command(
                 "p/sample",
                 "Sample palette colors from the active view",
@@ -964,6 +1883,10 @@ impl Default for Commands {
                         "pan" => Ok(Command::Tool(Tool::Pan(PanState::default()))),
                         "brush" => Ok(Command::Tool(Tool::Brush)),
                         "sampler" => Ok(Command::Tool(Tool::Sampler)),
+                        "rect" => Ok(Command::Tool(Tool::Rectangle)),
+                        "ellipse" => Ok(Command::Tool(Tool::Ellipse)),
+                        "curve" => Ok(Command::Tool(Tool::Curve)),
+                        "gradient" => Ok(Command::Tool(Tool::Gradient)),
                         _ => Err(format!("unknown tool {:?}", t)),
                     })
             })
@@ -1008,8 +1931,8 @@ impl Default for Commands {
                     .map(|(_, dir)| Command::SelectionJump(dir))
             })
             .command("selection/fill", "Fill selection with color", |p| {
-                p.then(optional(color()))
-                    .map(|(_, rgba)| Command::SelectionFill(rgba))
+                p.then(optional(param::<ColorRef>()))
+                    .map(|(_, c)| Command::SelectionFill(c))
             })
             .command("selection/flip", "Flip selection", |p| {
                 p.then(word().label("x/y"))
@@ -1020,13 +1943,13 @@ impl Default for Commands {
                     })
             })
             .command("paint/color", "Paint color", |p| {
-                p.then(color())
+                p.then(param::<ColorRef>())
                     .skip(whitespace())
                     .then(tuple::<i32>(integer().label("<x>"), integer().label("<y>")))
-                    .map(|((_, rgba), (x, y))| Command::PaintColor(rgba, x, y))
+                    .map(|((_, color), (x, y))| Command::PaintColor(color, x, y))
             })
             .command("paint/line", "Draw a line between two points", |p| {
-                p.then(color())
+                p.then(param::<ColorRef>())
                     .skip(whitespace())
                     .then(tuple::<i32>(
                         integer().label("<x1>"),
@@ -1041,7 +1964,61 @@ impl Default for Commands {
                         Command::PaintLine(color, x1, y1, x2, y2)
                     })
             })
-            .command("paint/fg", "Paint foreground color", |p| {
+            .command(
+                "paint/rect",
+                "Draw a rectangle between two corners with the brush",
+                |p| {
+                    p.then(tuple::<i32>(
+                        integer().label("<x1>"),
+                        integer().label("<y1>"),
+                    ))
+                    .skip(whitespace())
+                    .then(tuple::<i32>(
+                        integer().label("<x2>"),
+                        integer().label("<y2>"),
+                    ))
+                    .map(|((_, (x1, y1)), (x2, y2))| Command::PaintRect(x1, y1, x2, y2))
+                },
+            )
+            .command(
+                "paint/ellipse",
+                "Draw an ellipse inside the box between two corners with the brush",
+                |p| {
+                    p.then(tuple::<i32>(
+                        integer().label("<x1>"),
+                        integer().label("<y1>"),
+                    ))
+                    .skip(whitespace())
+                    .then(tuple::<i32>(
+                        integer().label("<x2>"),
+                        integer().label("<y2>"),
+                    ))
+                    .map(|((_, (x1, y1)), (x2, y2))| Command::PaintEllipse(x1, y1, x2, y2))
+                },
+            )
+            .command(
+                "paint/gradient",
+                "Fill the selection, or the region under the first point, with a gradient",
+                |p| {
+                    p.then(tuple::<i32>(
+                        integer().label("<x1>"),
+                        integer().label("<y1>"),
+                    ))
+                    .skip(whitespace())
+                    .then(tuple::<i32>(
+                        integer().label("<x2>"),
+                        integer().label("<y2>"),
+                    ))
+                    .map(|((_, (x1, y1)), (x2, y2))| Command::PaintGradient(x1, y1, x2, y2))
+                },
+            )
+            .command("curve/commit", "Paint the curve being placed", |p| {
+                p.value(Command::CurveCommit)
+            })
+            .command("curve/cancel", "Discard the curve being placed", |p| {
+                p.value(Command::CurveCancel)
+            })
+            .command("paint/fg", "Paint foreground color", |p| {
                 p.then(tuple::<i32>(integer().label("<x>"), integer().label("<y>")))
                     .map(|(_, (x, y))| Command::PaintForeground(x, y))
             })
@@ -1055,6 +2032,44 @@ impl Default for Commands {
                     .then(tuple::<i32>(integer().label("<x>"), integer().label("<y>")))
                     .map(|((_, i), (x, y))| Command::PaintPalette(i, x, y))
             })
+            .command("macro/record", "Record a macro into a register", |p| {
+                p.then(optional(register()))
+                    .map(|(_, r)| Command::MacroRecord(r))
+            })
+            .command("macro/stop", "Stop recording a macro", |p| {
+                p.value(Command::MacroStop)
+            })
+            .command("macro/play", "Play a macro <count> times", |p| {
+                p.then(optional(
+                    register().then(optional(
+                        whitespace()
+                            .then(natural::<usize>().label("<count>"))
+                            .map(|(_, n)| n),
+                    )),
+                ))
+                .map(|(_, r)| match r {
+                    Some((r, n)) => Command::MacroPlay(Some(r), n.unwrap_or(1)),
+                    None => Command::MacroPlay(None, 1),
+                })
+            })
+            .command("macro/append", "Append a command to a register", |p| {
+                // Prevent stack overflow.
+                let cmd = Parser::new(
+                    move |input| Commands::default().parser().parse(input),
+                    "<cmd>",
+                );
+                p.then(register())
+                    .skip(whitespace())
+                    .then(cmd)
+                    .map(|((_, r), cmd)| Command::MacroAppend(r, Box::new(cmd)))
+            })
+            .command("macro/clear", "Clear a register, or all registers", |p| {
+                p.then(optional(register()))
+                    .map(|(_, r)| Command::MacroClear(r))
+            })
+            .command("macro/write", "Write macros to a file", |p| {
+                p.then(path()).map(|(_, path)| Command::MacroWrite(path))
+            })
     }
 }
 
@@ -1063,12 +2078,15 @@ impl Default for Commands {
 #[derive(Debug)]
 pub struct CommandCompleter {
     file_completer: FileCompleter,
+    /// Colors offered for color arguments, usually the palette colors.
+    pub colors: Vec<Rgba8>,
 }
 
 impl CommandCompleter {
     fn new<P: AsRef<Path>>(cwd: P, exts: &[&str]) -> Self {
         Self {
             file_completer: FileCompleter::new(cwd, exts),
+            colors: Vec::new(),
         }
     }
 }
@@ -1086,20 +2104,110 @@ impl autocomplete::Completer for CommandCompleter {
                     input,
                     FileCompleterOpts { directories: true },
                 ),
-                Command::Source(path) | Command::Write(path) => {
+                Command::Source(path) | Command::SourceStrict(path) | Command::Write(path) => {
                     self.complete_path(path.as_ref(), input, Default::default())
                 }
                 Command::Edit(paths) | Command::EditFrames(paths) => {
                     self.complete_path(paths.last(), input, Default::default())
                 }
-                _ => vec![],
+                _ => self.complete_words(input),
             },
-            Err(_) => vec![],
+            Err(_) => self.complete_words(input),
         }
     }
 }
 
 impl CommandCompleter {
+    const BRUSH_MODES: &'static [&'static str] = &[
+        "erase", "multi", "perfect", "xsym", "ysym", "xray", "line", "dither", "tint", "shade",
+    ];
+    const BRUSH_SHAPES: &'static [&'static str] =
+        &["square", "circle", "diamond", "hline", "vline"];
+    const SCOPES: &'static [&'static str] = &["selection", "frame", "view", "layers", "views"];
+    const TOOLS: &'static [&'static str] = &[
+        "pan", "brush", "sampler", "rect", "ellipse", "curve", "gradient",
+    ];
+    const MESSAGE_TYPES: &'static [&'static str] = &["error", "warning", "info", "echo", "okay"];
+    const MODES: &'static [&'static str] = &["normal", "visual", "command", "present", "help"];
+
+    /// Complete command names, and the arguments of commands which take one of
+    /// a known set of words.
+    fn complete_words(&self, input: &str) -> Vec<String> {
+        let input = match input.strip_prefix(':') {
+            Some(input) => input,
+            None => return vec![],
+        };
+        let (name, args) = match input.find(char::is_whitespace) {
+            Some(i) => input.split_at(i),
+            None => {
+                let mut names: Vec<&str> = Commands::default()
+                    .iter()
+                    .map(|(name, _, _)| *name)
+                    .filter(|name| *name != "#")
+                    .collect();
+                names.sort_unstable();
+                names.dedup();
+
+                return Self::candidates(names.into_iter(), input);
+            }
+        };
+        let mut args: Vec<&str> = args.split_whitespace().collect();
+        let word = match input.chars().next_back() {
+            Some(c) if c.is_whitespace() => "",
+            _ => args.pop().unwrap_or_default(),
+        };
+        let settings = Settings::default();
+        let colors = || {
+            self.colors
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        match (name, args.as_slice()) {
+            ("set", []) | ("unset", []) | ("toggle", []) | ("echo", []) => {
+                Self::candidates(settings.names().into_iter(), word)
+            }
+            ("set", [setting, "="]) => match settings.get(setting) {
+                Some(Value::Rgba8(_)) => {
+                    Self::candidates(colors().iter().map(|c| c.as_str()), word)
+                }
+                _ => vec![],
+            },
+            ("brush/set", []) | ("brush/unset", []) | ("brush/toggle", []) => {
+                Self::candidates(Self::BRUSH_MODES.iter().copied(), word)
+            }
+            ("brush/shape", []) => Self::candidates(Self::BRUSH_SHAPES.iter().copied(), word),
+            ("color/replace", [_, _]) => Self::candidates(Self::SCOPES.iter().copied(), word),
+            ("tool", []) => Self::candidates(Self::TOOLS.iter().copied(), word),
+            ("messages", []) => Self::candidates(Self::MESSAGE_TYPES.iter().copied(), word),
+            ("mode", []) | ("maps", []) => Self::candidates(Self::MODES.iter().copied(), word),
+            ("v/fill", [])
+            | ("selection/fill", [])
+            | ("p/add", [])
+            | ("p/gradient", [])
+            | ("p/gradient", [_])
+            | ("p/ramp", _)
+            | ("paint/color", [])
+            | ("paint/line", []) => Self::candidates(colors().iter().map(|c| c.as_str()), word),
+            _ => vec![],
+        }
+    }
+
+    /// Filter candidates by prefix, returning the remainder of each candidate.
+    fn candidates<'a>(candidates: impl Iterator<Item = &'a str>, prefix: &str) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+
+        for c in candidates {
+            if let Some(rest) = c.strip_prefix(prefix) {
+                if !rest.is_empty() && !result.iter().any(|r| r == rest) {
+                    result.push(rest.to_owned());
+                }
+            }
+        }
+        result
+    }
+
     fn complete_path(
         &self,
         path: Option<&String>,
@@ -1123,6 +2231,8 @@ impl CommandCompleter {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::brush::Step;
+    use crate::dither::Dither;
     use std::{fs, fs::File};
 
     #[test]
@@ -1170,6 +2280,56 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_command_completer_words() {
+        use crate::autocomplete::Completer;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cc = CommandCompleter::new(tmp.path(), &["png"]);
+
+        assert_eq!(cc.complete(":brush/t", ()), vec!["oggle"]);
+        assert!(cc.complete(":macro/", ()).contains(&"play".to_owned()));
+        assert_eq!(cc.complete(":tool s", ()), vec!["ampler"]);
+        assert_eq!(
+            cc.complete(":mode ", ()),
+            vec!["normal", "visual", "command", "present", "help"]
+        );
+        assert_eq!(cc.complete(":brush/set x", ()), vec!["sym", "ray"]);
+        assert_eq!(cc.complete(":set ui/pa", ()), vec!["lette"]);
+        assert_eq!(cc.complete(":toggle checke", ()), vec!["r"]);
+        assert!(
+            cc.complete(":set frame_", ()).is_empty(),
+            "deprecated settings"
+        );
+        assert!(cc.complete(":tool sampler ", ()).is_empty());
+
+        cc.colors = vec![
+            Rgba8::new(0xff, 0, 0, 0xff),
+            Rgba8::new(0xff, 0xff, 0, 0xff),
+        ];
+        assert_eq!(cc.complete(":v/fill #ff", ()), vec!["0000", "ff00"]);
+        assert_eq!(cc.complete(":p/gradient #ff0000 #ffff", ()), vec!["00"]);
+        assert_eq!(
+            cc.complete(":set grid/color = ", ()),
+            vec!["#ff0000", "#ffff00"]
+        );
+        assert!(cc.complete(":set scale = ", ()).is_empty());
+    }
+
+    #[test]
+    fn test_command_line_help() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cli = CommandLine::new(tmp.path(), &tmp.path().join(".history"), &["png"]);
+
+        cli.puts(":brush/se");
+        cli.completion_next();
+        assert_eq!(cli.input(), ":brush/set");
+        assert_eq!(
+            cli.help(),
+            Some("brush/set <mode>: Set brush mode, eg. `xsym` for x-symmetry".to_owned())
+        );
+    }
+
     #[test]
     fn test_command_line() {
         let tmp = tempfile::tempdir().unwrap();
@@ -1336,6 +2496,190 @@ mod test {
         assert_eq!(cli.input(), ":e");
     }
 
+    #[test]
+    fn test_command_line_editing() {
+        let mut cli = CommandLine::new("/dev/null", "/dev/null", &[]);
+
+        cli.puts(":p/gradient #ff0000 #0000ff 8");
+        cli.cursor_home();
+        assert_eq!(cli.peek_back(), Some(':'));
+
+        cli.cursor_end();
+        cli.delw();
+        assert_eq!(cli.input(), ":p/gradient #ff0000 #0000ff ");
+        cli.delw();
+        assert_eq!(cli.input(), ":p/gradient #ff0000 ");
+
+        cli.cursor_backward();
+        cli.puts("\t#00ff00\n");
+        assert_eq!(cli.input(), ":p/gradient #ff0000#00ff00 ");
+        assert_eq!(cli.peek(), Some(' '));
+
+        cli.kill();
+        assert_eq!(cli.input(), ": ");
+        cli.kill();
+        assert_eq!(cli.input(), ": ");
+        cli.delw();
+        assert_eq!(cli.input(), ": ");
+    }
+
+    #[test]
+    fn test_command_line_search() {
+        let mut cli = CommandLine::new("/dev/null", "/dev/null", &[]);
+
+        cli.history.add(":set scale = 2");
+        cli.history.add(":p/add #ff0000");
+        cli.history.add(":set grid");
+
+        cli.puts(":zoom");
+        cli.search_start();
+        cli.search_putc('s');
+        cli.search_putc('e');
+        assert_eq!(cli.input(), ":set grid");
+
+        cli.search_start();
+        assert_eq!(cli.input(), ":set scale = 2");
+
+        cli.search_putc('x');
+        assert!(cli.search.as_ref().unwrap().failed);
+        assert_eq!(cli.input(), ":set scale = 2");
+
+        cli.search_delc();
+        assert_eq!(cli.input(), ":set grid");
+
+        cli.search_cancel();
+        assert_eq!(cli.input(), ":zoom");
+        assert!(cli.search.is_none());
+
+        cli.search_start();
+        cli.search_putc('#');
+        cli.search_accept();
+        assert_eq!(cli.input(), ":p/add #ff0000");
+        assert_eq!(cli.peek(), None);
+    }
+
+    #[test]
+    fn test_color_commands() {
+        let p = Commands::default().line_parser();
+        let color = Rgba8::new(0xf0, 0xc0, 0xa0, 0xff);
+
+        assert_eq!(
+            p.parse(":color/def skin #f0c0a0"),
+            Ok((Command::ColorDef("skin".to_owned(), color), ""))
+        );
+        assert_eq!(
+            p.parse(":set background = rgb(240, 192, 160)"),
+            Ok((
+                Command::Set("background".to_owned(), Value::Rgba8(color)),
+                ""
+            ))
+        );
+        assert_eq!(
+            p.parse(":selection/fill hsl(0, 0%, 100%)"),
+            Ok((Command::SelectionFill(Some(Rgba8::WHITE.into())), ""))
+        );
+        assert_eq!(
+            p.parse(":paint/color #fff 1 2"),
+            Ok((Command::PaintColor(Rgba8::WHITE.into(), 1, 2), ""))
+        );
+        assert_eq!(
+            p.parse(":p/gradient black rgba(255, 255, 255, 1) 8"),
+            Ok((
+                Command::PaletteGradient(Rgba8::BLACK.into(), Rgba8::WHITE.into(), 8),
+                ""
+            ))
+        );
+        assert!(
+            p.parse(":white").is_err(),
+            "only hex colors can be added with `:`"
+        );
+    }
+
+    #[test]
+    fn test_color_refs() {
+        let p = Commands::default().line_parser();
+
+        assert_eq!(
+            p.parse(":selection/fill @5"),
+            Ok((Command::SelectionFill(Some(ColorRef::Palette(5))), ""))
+        );
+        assert_eq!(
+            p.parse(":v/fill @skin"),
+            Ok((Command::Fill(Some(ColorRef::Named("skin".to_owned()))), ""))
+        );
+        assert_eq!(
+            p.parse(":p/gradient @0 #fff 4"),
+            Ok((
+                Command::PaletteGradient(ColorRef::Palette(0), Rgba8::WHITE.into(), 4),
+                ""
+            ))
+        );
+        assert_eq!(
+            p.parse(":color/replace #f00 @1"),
+            Ok((
+                Command::ColorReplace(Rgba8::RED.into(), ColorRef::Palette(1), Scope::View),
+                ""
+            )),
+            "the scope defaults to the view"
+        );
+        assert_eq!(
+            p.parse(":color/replace @skin #00f layers"),
+            Ok((
+                Command::ColorReplace(
+                    ColorRef::Named("skin".to_owned()),
+                    Rgba8::BLUE.into(),
+                    Scope::Layers
+                ),
+                ""
+            ))
+        );
+        assert_eq!(
+            p.parse(":color/replace #f00 #00f frame 3"),
+            Ok((
+                Command::ColorReplace(Rgba8::RED.into(), Rgba8::BLUE.into(), Scope::Frame(3)),
+                ""
+            ))
+        );
+        assert!(p.parse(":color/replace #f00 #00f frame").is_err());
+        assert!(p.parse(":color/replace #f00 #00f everywhere").is_err());
+        assert_eq!(
+            p.parse(":p/ramp @0 @1 #fff"),
+            Ok((
+                Command::PaletteRamp(vec![
+                    ColorRef::Palette(0),
+                    ColorRef::Palette(1),
+                    Rgba8::WHITE.into()
+                ]),
+                ""
+            ))
+        );
+        assert!(
+            p.parse(":p/ramp @0").is_err(),
+            "a ramp has at least two colors"
+        );
+        assert_eq!(
+            p.parse(":brush/set shade"),
+            Ok((Command::BrushSet(BrushMode::Shade(Step::Up)), ""))
+        );
+        assert!(p.parse(":brush/set shade sideways").is_err());
+        assert_eq!(
+            p.parse(":set fg = @3"),
+            Ok((
+                Command::Set("fg".to_owned(), Value::ColorRef(ColorRef::Palette(3))),
+                ""
+            ))
+        );
+        match p.parse(":map <ctrl> f :selection/fill @5") {
+            Ok((Command::Map(mapping), _)) => assert_eq!(
+                mapping.press,
+                Command::SelectionFill(Some(ColorRef::Palette(5))),
+                "references are kept until the mapping runs"
+            ),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(p.parse(":v/fill @").is_err());
+    }
+
     #[test]
     fn test_parser() {
         let p = Commands::default().line_parser();
@@ -1362,6 +2706,8 @@ mod test {
             p.parse(":set foo"),
             Ok((Command::Set("foo".to_owned(), Value::Bool(true)), ""))
         );
+        assert_eq!(p.parse(":set"), Ok((Command::Settings, "")));
+        assert_eq!(p.parse(":set "), Ok((Command::Settings, "")));
 
         assert_eq!(
             param::<platform::Key>()
@@ -1432,6 +2778,85 @@ mod test {
         assert_eq!(rest, "");
     }
 
+    #[test]
+    fn test_keymapping_sequences() {
+        let p = string("map")
+            .skip(whitespace())
+            .then(KeyMapping::parser(&[]));
+
+        let ((_, km), _) = p.parse("map g 'g' :flood").unwrap();
+        assert_eq!(km.prefix, vec![Input::Key(platform::Key::G)]);
+        assert_eq!(km.input, Input::Key(platform::Key::G));
+
+        let ((_, km), _) = p.parse("map <leader> e x :undo").unwrap();
+        assert_eq!(km.prefix, vec![Input::Leader, Input::Key(platform::Key::E)]);
+        assert_eq!(km.input, Input::Key(platform::Key::X));
+
+        assert!(p.parse("map g g :undo {:redo}").is_err());
+        assert!(p.parse("map '@' x :undo").is_err());
+    }
+
+    #[test]
+    fn test_keymapping_mouse() {
+        let p = Commands::default().line_parser();
+
+        let cmd = p.parse(":map <ctrl><mouse/right> :swap {:swap}").unwrap().0;
+        if let Command::Map(km) = &cmd {
+            assert_eq!(km.input, Input::Mouse(platform::MouseButton::Right));
+            assert!(km.modifiers.ctrl);
+            assert_eq!(km.release, Some(Command::SwapColors));
+        } else {
+            panic!("expected a mapping, got {:?}", cmd);
+        }
+        let s = format!(":{}", String::from(cmd.clone()));
+        assert_eq!(p.parse(&s).unwrap().0, cmd);
+
+        let cmd = p.parse(":map/normal <wheel/up> :brush/size +").unwrap().0;
+        let s = format!(":{}", String::from(cmd.clone()));
+        assert_eq!(s, ":map/normal <wheel/up> :brush/size +");
+        assert_eq!(p.parse(&s).unwrap().0, cmd);
+
+        assert!(p.parse(":map g <mouse/left> :undo").is_err());
+    }
+
+    #[test]
+    fn test_unmap_and_maps() {
+        let p = Commands::default().line_parser();
+
+        assert_eq!(
+            p.parse(":unmap/normal g <ctrl>t").unwrap().0,
+            Command::Unmap(
+                KeySequence {
+                    input: Input::Key(platform::Key::T),
+                    prefix: vec![Input::Key(platform::Key::G)],
+                    modifiers: platform::ModifiersState {
+                        ctrl: true,
+                        ..Default::default()
+                    },
+                },
+                vec![Mode::Normal]
+            )
+        );
+        for s in &[
+            ":unmap '@'",
+            ":unmap/visual <leader> x",
+            ":unmap/help <esc>",
+            ":maps",
+            ":maps normal",
+            ":maps <tab>",
+            ":maps visual g g",
+        ] {
+            let cmd = p.parse(s).unwrap().0;
+            assert_eq!(&format!(":{}", String::from(cmd)), s);
+        }
+        assert_eq!(
+            p.parse(":maps help").unwrap().0,
+            Command::Maps(Some(Mode::Help), None)
+        );
+        assert!(p.parse(":unmap").is_err());
+        assert!(p.parse(":unmap <ctrl>g g").is_err());
+    }
+
     #[test]
     fn tes_value_parser() {
         let p = Value::parser();
@@ -1448,6 +2873,171 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_value_expressions() {
+        let p = Value::parser();
+
+        assert_eq!(p.parse("8 * 2").unwrap(), (Value::U32(16), ""));
+        assert_eq!(p.parse("8 8 * 2").unwrap(), (Value::U32Tuple(16, 16), ""));
+        assert_eq!(p.parse("1 + 2 * 3").unwrap(), (Value::U32(7), ""));
+        assert_eq!(p.parse("2 * 3 + 1").unwrap(), (Value::U32(7), ""));
+        assert_eq!(p.parse("1 + 8 / 2 * 3 - 2").unwrap(), (Value::U32(11), ""));
+        assert_eq!(p.parse("9 - 4 - 2").unwrap(), (Value::U32(3), ""));
+        assert_eq!(p.parse("3 / 2.0").unwrap(), (Value::F64(1.5), ""));
+        assert_eq!(p.parse("50%").unwrap(), (Value::F64(0.5), ""));
+        assert_eq!(
+            p.parse("#ff0000 + 50%").unwrap(),
+            (Value::Rgba8(Rgba8::new(0xff, 0x80, 0x80, 0xff)), "")
+        );
+        assert_eq!(
+            p.parse("#ff0000 - 50%").unwrap(),
+            (Value::Rgba8(Rgba8::new(0x80, 0x0, 0x0, 0xff)), "")
+        );
+        assert_eq!(
+            p.parse("#ff0000 + #00ff00").unwrap(),
+            (Value::Rgba8(Rgba8::new(0xff, 0xff, 0x0, 0xff)), "")
+        );
+        assert_eq!(
+            p.parse("\"hello \" + \"world\"").unwrap(),
+            (Value::Str("hello world".to_owned()), "")
+        );
+        assert_eq!(
+            p.parse("1 - 2").unwrap_err().0.to_string(),
+            "invalid operation `1 - 2`"
+        );
+        assert!(p.parse("on + 1").is_err());
+    }
+
+    #[test]
+    fn test_value_literal() {
+        let p = Value::parser();
+
+        for v in &[
+            Value::Bool(true),
+            Value::U32(42),
+            Value::F64(1.0),
+            Value::U32Tuple(8, 16),
+            Value::F32Tuple(1.5, -2.0),
+            Value::Str("fnord".to_owned()),
+            Value::Str("say \"hi\" \\o/".to_owned()),
+            Value::Rgba8(Rgba8::new(0xff, 0x0, 0xff, 0x80)),
+        ] {
+            assert_eq!(p.parse(&v.literal()).unwrap(), (v.clone(), ""));
+        }
+    }
+
+    #[test]
+    fn test_let_command() {
+        let p = Commands::default().line_parser();
+
+        assert_eq!(
+            p.parse(":let w = 16 * 2").unwrap(),
+            (Command::Let("w".to_owned(), Value::U32(32)), "")
+        );
+        assert_eq!(
+            p.parse(":let skin=#f0c0a0 -- Skin tone").unwrap(),
+            (
+                Command::Let(
+                    "skin".to_owned(),
+                    Value::Rgba8(Rgba8::new(0xf0, 0xc0, 0xa0, 0xff))
+                ),
+                ""
+            )
+        );
+    }
+
+    #[test]
+    fn test_macro_commands() {
+        let p = Commands::default().line_parser();
+
+        assert_eq!(
+            p.parse(":macro/record").unwrap(),
+            (Command::MacroRecord(None), "")
+        );
+        assert_eq!(
+            p.parse(":macro/play a 3").unwrap(),
+            (Command::MacroPlay(Some('a'), 3), "")
+        );
+        assert_eq!(
+            p.parse(":macro/append q :brush/size +").unwrap(),
+            (
+                Command::MacroAppend('q', Box::new(Command::BrushSize(Op::Incr))),
+                ""
+            )
+        );
+    }
+
+    #[test]
+    fn test_command_to_string() {
+        let p = Commands::default().line_parser();
+
+        for cmd in &[
+            Command::BrushSet(BrushMode::Line(Some(15))),
+            Command::BrushSet(BrushMode::Dither(Dither::Bayer(4))),
+            Command::BrushToggle(BrushMode::Dither(Dither::Checker)),
+            Command::BrushSet(BrushMode::Tint),
+            Command::BrushCapture,
+            Command::BrushReset,
+            Command::BrushSave("stamps/fern.png".to_owned()),
+            Command::BrushLoad("fern.png".to_owned()),
+            Command::BrushShape(Tip::Circle),
+            Command::BrushShape(Tip::HLine),
+            Command::BrushSet(BrushMode::Shade(Step::Down)),
+            Command::BrushToggle(BrushMode::Shade(Step::Up)),
+            Command::PaletteRamp(vec![
+                ColorRef::Palette(0),
+                ColorRef::Color(Rgba8::RED),
+                ColorRef::Named("skin".to_owned()),
+            ]),
+            Command::ColorReplace(
+                ColorRef::Color(Rgba8::RED),
+                ColorRef::Palette(2),
+                Scope::Frame(1),
+            ),
+            Command::ColorReplace(
+                ColorRef::Named("skin".to_owned()),
+                ColorRef::Color(Rgba8::BLUE),
+                Scope::Views,
+            ),
+            Command::Edit(vec!["one.png".to_owned(), "two.png".to_owned()]),
+            Command::PaintLine(Rgba8::new(0xff, 0x0, 0xff, 0x80).into(), 1, 2, 3, -4),
+            Command::PaintRect(-1, 2, 3, 4),
+            Command::Tool(Tool::Rectangle),
+            Command::PaintEllipse(0, 0, 7, 4),
+            Command::Tool(Tool::Ellipse),
+            Command::CurveCommit,
+            Command::CurveCancel,
+            Command::Tool(Tool::Curve),
+            Command::PaintGradient(2, 3, 12, -1),
+            Command::Tool(Tool::Gradient),
+            Command::SelectionJump(Direction::Backward),
+            Command::Set("scale".to_owned(), Value::F64(2.0)),
+            Command::Tool(Tool::Pan(PanState::default())),
+            Command::Zoom(Op::Incr),
+            Command::MacroPlay(Some('a'), 2),
+            Command::MacroAppend('b', Box::new(Command::Mode(Mode::Help))),
+        ] {
+            let s = format!(":{}", String::from(cmd.clone()));
+            assert_eq!(p.parse(&s).unwrap(), (cmd.clone(), ""), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_dither_modes() {
+        let p = Commands::default().line_parser();
+
+        assert_eq!(
+            p.parse(":brush/set dither bayer").unwrap(),
+            (Command::BrushSet(BrushMode::Dither(Dither::Bayer(8))), "")
+        );
+        assert_eq!(
+            p.parse(":brush/unset dither").unwrap(),
+            (Command::BrushUnset(BrushMode::Dither(Dither::Checker)), "")
+        );
+        assert!(p.parse(":brush/set dither bayer 17").is_err());
+        assert!(p.parse(":brush/set dither noise").is_err());
+    }
+
     #[test]
     fn test_parser_errors() {
         let p = Commands::default().line_parser();
@@ -0,0 +1,127 @@
+use rgx::kit::Rgba8;
+use rgx::rect::Rect;
+
+use std::fmt;
+
+/// Thresholds of the 4x4 Bayer matrix.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// An ordered dither pattern, which mixes two colors. Patterns are anchored to
+/// canvas coordinates, so that separate strokes and fills line up.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub enum Dither {
+    /// Alternate between both colors, like a checkerboard.
+    Checker,
+    /// Mix both colors using a 4x4 Bayer matrix, painting the given number of
+    /// pixels out of sixteen with the first color.
+    Bayer(u8),
+}
+
+impl Dither {
+    /// The density at which the Bayer pattern only paints the first color.
+    pub const MAX_DENSITY: u8 = 16;
+
+    /// Check whether the pixel at the given canvas coordinates is painted with
+    /// the first color.
+    pub fn is_set(&self, x: i32, y: i32) -> bool {
+        match self {
+            Self::Checker => (x + y).rem_euclid(2) == 0,
+            Self::Bayer(density) => {
+                BAYER[y.rem_euclid(4) as usize][x.rem_euclid(4) as usize] < *density
+            }
+        }
+    }
+
+    /// Split a rectangle of pixels into runs of the same color along each row.
+    /// Pixels of the second color are left out if it isn't given.
+    pub fn fill(&self, r: Rect<i32>, fg: Rgba8, bg: Option<Rgba8>) -> Vec<(Rect<i32>, Rgba8)> {
+        let mut runs = Vec::new();
+
+        for y in r.y1..r.y2 {
+            let mut x = r.x1;
+
+            while x < r.x2 {
+                let set = self.is_set(x, y);
+                let start = x;
+
+                while x < r.x2 && self.is_set(x, y) == set {
+                    x += 1;
+                }
+                match (set, bg) {
+                    (true, _) => runs.push((Rect::new(start, y, x, y + 1), fg)),
+                    (false, Some(bg)) => runs.push((Rect::new(start, y, x, y + 1), bg)),
+                    (false, None) => {}
+                }
+            }
+        }
+        runs
+    }
+}
+
+impl fmt::Display for Dither {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Checker => write!(f, "checker"),
+            Self::Bayer(density) => write!(f, "bayer {}", density),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_density() {
+        for density in 0..=Dither::MAX_DENSITY {
+            let dither = Dither::Bayer(density);
+            let set = (0..4)
+                .flat_map(|y| (0..4).map(move |x| (x, y)))
+                .filter(|(x, y)| dither.is_set(*x, *y))
+                .count();
+            assert_eq!(set, density as usize);
+
+            // Denser patterns include the pixels of lighter ones.
+            if density > 0 {
+                let lighter = Dither::Bayer(density - 1);
+                assert!((-4..4)
+                    .flat_map(|y| (-4..4).map(move |x| (x, y)))
+                    .all(|(x, y)| !lighter.is_set(x, y) || dither.is_set(x, y)));
+            }
+        }
+        assert_eq!(
+            (0..8)
+                .map(|x| Dither::Checker.is_set(x, 3))
+                .collect::<Vec<_>>(),
+            (0..8)
+                .map(|x| Dither::Bayer(8).is_set(x, 3))
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn test_anchoring() {
+        let (fg, bg) = (Rgba8::WHITE, Rgba8::BLACK);
+        let dither = Dither::Bayer(4);
+
+        // The same pixels are painted no matter how the area is split up.
+        let whole = dither.fill(Rect::new(-3, -2, 9, 6), fg, Some(bg));
+        let mut parts = dither.fill(Rect::new(-3, -2, 2, 6), fg, Some(bg));
+        parts.extend(dither.fill(Rect::new(2, -2, 9, 6), fg, Some(bg)));
+
+        let pixels = |runs: &[(Rect<i32>, Rgba8)]| {
+            let mut pixels: Vec<(i32, i32, Rgba8)> = runs
+                .iter()
+                .flat_map(|(r, c)| (r.x1..r.x2).map(move |x| (x, r.y1, *c)))
+                .collect();
+            pixels.sort_by_key(|(x, y, _)| (*y, *x));
+            pixels
+        };
+        assert_eq!(pixels(&whole), pixels(&parts));
+        assert_eq!(pixels(&whole).len(), 12 * 8);
+
+        let sparse = dither.fill(Rect::new(0, 0, 4, 4), fg, None);
+        assert_eq!(pixels(&sparse).len(), 4);
+        assert!(sparse.iter().all(|(_, c)| *c == fg));
+    }
+}
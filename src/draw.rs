@@ -1,4 +1,4 @@
-use crate::brush::{Align, Brush, BrushMode};
+use crate::brush::{Align, Brush, BrushMode, Curve, Stamp};
 use crate::color;
 use crate::execution::Execution;
 use crate::font::{TextAlign, TextBatch};
@@ -6,14 +6,14 @@ use crate::platform;
 use crate::session;
 use crate::session::{Mode, Rgb8, Session, Tool, VisualState};
 use crate::sprite;
-use crate::view::{layer::LayerCoords, View};
+use crate::view::{layer::LayerCoords, View, ViewCoords};
 
 use rgx::kit::shape2d::{Fill, Line, Rotation, Shape, Stroke};
 use rgx::kit::Rgba;
 use rgx::kit::{self, Geometry};
 use rgx::kit::{shape2d, sprite2d};
 use rgx::kit::{Rgba8, ZDepth};
-use rgx::math::{Matrix4, Vector2};
+use rgx::math::{Matrix4, Point2, Vector2};
 use rgx::rect::Rect;
 
 use std::time;
@@ -89,6 +89,10 @@ pub mod cursors {
             Tool::Sampler => self::SAMPLER,
             Tool::Pan(_) => self::PAN,
             Tool::FloodFill => self::FLOOD,
+            Tool::Rectangle | Tool::Ellipse | Tool::Curve if b.is_set(BrushMode::Erase) => {
+                self::ERASE
+            }
+            Tool::Rectangle | Tool::Ellipse | Tool::Curve | Tool::Gradient => self::CROSSHAIR,
 
             Tool::Brush => match m {
                 Mode::Visual(_) if in_selection && in_view && in_layer => self::OMNI,
@@ -350,9 +354,51 @@ fn draw_ui(session: &Session, canvas: &mut shape2d::Batch, text: &mut TextBatch)
         }
     }
 
+    // Macro recording status & pending keys
+    let mut offset = session.width - MARGIN;
+
+    if let Some(r) = session.macros.recording() {
+        let s = format!("recording @{}", r);
+        text.add(
+            &s,
+            offset,
+            MARGIN,
+            self::TEXT_LAYER,
+            color::RED,
+            TextAlign::Right,
+        );
+        offset -= (s.len() + 1) as f32 * self::GLYPH_WIDTH;
+    }
+    if session.pending_count.is_some() || !session.pending_keys.is_empty() {
+        let count = session
+            .pending_count
+            .map_or(String::new(), |n| n.to_string());
+        let keys = session
+            .pending_keys
+            .iter()
+            .map(|k| k.to_string())
+            .collect::<String>();
+        text.add(
+            &format!("{}{}", count, keys),
+            offset,
+            MARGIN,
+            self::TEXT_LAYER,
+            Rgba8::WHITE,
+            TextAlign::Right,
+        );
+    }
+
     // Command-line & message
     if session.mode == Mode::Command {
-        let s = format!("{}", &session.cmdline.input());
+        let s = match &session.cmdline.search {
+            Some(search) => format!(
+                "({}reverse-i-search)`{}': {}",
+                if search.failed { "failing " } else { "" },
+                search.query,
+                session.cmdline.input()
+            ),
+            None => session.cmdline.input(),
+        };
         text.add(
             &s,
             MARGIN,
@@ -361,7 +407,7 @@ fn draw_ui(session: &Session, canvas: &mut shape2d::Batch, text: &mut TextBatch)
             Rgba8::WHITE,
             TextAlign::Left,
         );
-        if session.settings["ui/cursor"].is_set() {
+        if session.settings["ui/cursor"].is_set() && session.cmdline.search.is_none() {
             text.glyph(
                 96,
                 MARGIN + session.cmdline.cursor as f32 * self::GLYPH_WIDTH,
@@ -370,6 +416,18 @@ fn draw_ui(session: &Session, canvas: &mut shape2d::Batch, text: &mut TextBatch)
                 Rgba8::RED,
             );
         }
+        // Hints, such as the help for the command being completed, are
+        // shown alongside the command line.
+        if session.message.is_hint() && session.settings["ui/message"].is_set() {
+            text.add(
+                &format!("{}", &session.message),
+                offset,
+                MARGIN,
+                self::TEXT_LAYER,
+                session.message.color(),
+                TextAlign::Right,
+            );
+        }
     } else if !session.message.is_execution()
         && !session.message.is_debug()
         && session.settings["ui/message"].is_set()
@@ -531,9 +589,9 @@ fn draw_checker(session: &Session, batch: &mut sprite2d::Batch) {
 }
 
 fn draw_grid(session: &Session, batch: &mut shape2d::Batch) {
-    if session.settings["grid"].is_set() {
-        let color = session.settings["grid/color"].to_rgba8().alpha(0xcc);
-        let (gx, gy) = session.settings["grid/spacing"].clone().into();
+    if session.setting("grid").is_set() {
+        let color = session.setting("grid/color").to_rgba8().alpha(0xcc);
+        let (gx, gy) = session.setting("grid/spacing").clone().into();
 
         let v = session.active_view();
         let t = session.offset + v.offset;
@@ -630,6 +688,81 @@ fn draw_cursor(session: &Session, inverted: &mut sprite::Sprite, batch: &mut spr
     }
 }
 
+/// Draw the control polygon and points of the curve being placed.
+fn draw_curve(session: &Session, curve: &Curve, shapes: &mut shape2d::Batch) {
+    let v = session.active_view();
+    let z = v.zoom;
+    let offset = v.layer_offset(v.active_layer_id, z);
+    let center = |p: Point2<i32>| {
+        *session.session_coords(v.id, ViewCoords::new(p.x as f32, p.y as f32))
+            + offset
+            + Vector2::new(z / 2., z / 2.)
+    };
+
+    for pair in curve.points.windows(2) {
+        let (p0, p1) = (center(pair[0]), center(pair[1]));
+
+        shapes.add(Shape::Line(
+            Line::new([p0.x, p0.y], [p1.x, p1.y]),
+            self::UI_LAYER,
+            Rotation::ZERO,
+            Stroke::new(1.0, Rgba::new(1., 1., 1., 0.6)),
+        ));
+    }
+    for (i, p) in curve.points.iter().enumerate() {
+        let c = center(*p);
+        let r = 3.;
+        let fill = if curve.is_anchor(i) {
+            Fill::Solid(Rgba::WHITE)
+        } else {
+            Fill::Empty
+        };
+
+        shapes.add(Shape::Rectangle(
+            Rect::new(c.x - r, c.y - r, c.x + r, c.y + r),
+            self::UI_LAYER,
+            Rotation::ZERO,
+            Stroke::new(1.0, Rgba::WHITE),
+            fill,
+        ));
+    }
+}
+
+fn draw_gradient(
+    session: &Session,
+    from: Point2<i32>,
+    to: Point2<i32>,
+    shapes: &mut shape2d::Batch,
+) {
+    let v = session.active_view();
+    let z = v.zoom;
+    let offset = v.layer_offset(v.active_layer_id, z);
+    let center = |p: Point2<i32>| {
+        *session.session_coords(v.id, ViewCoords::new(p.x as f32, p.y as f32))
+            + offset
+            + Vector2::new(z / 2., z / 2.)
+    };
+    let (p0, p1) = (center(from), center(to));
+
+    shapes.add(Shape::Line(
+        Line::new([p0.x, p0.y], [p1.x, p1.y]),
+        self::UI_LAYER,
+        Rotation::ZERO,
+        Stroke::new(1.0, Rgba::new(1., 1., 1., 0.6)),
+    ));
+    for (c, color) in &[(p0, session.fg), (p1, session.bg)] {
+        let r = 3.;
+
+        shapes.add(Shape::Rectangle(
+            Rect::new(c.x - r, c.y - r, c.x + r, c.y + r),
+            self::UI_LAYER,
+            Rotation::ZERO,
+            Stroke::new(1.0, Rgba::WHITE),
+            Fill::Solid((*color).into()),
+        ));
+    }
+}
+
 fn draw_brush(session: &Session, brush: &Brush, shapes: &mut shape2d::Batch) {
     if session.palette.hover.is_some() {
         return;
@@ -641,6 +774,10 @@ fn draw_brush(session: &Session, brush: &Brush, shapes: &mut shape2d::Batch) {
     let c = session.cursor;
     let z = v.zoom;
 
+    if let Some((from, to)) = session.gradient {
+        draw_gradient(session, from, to, shapes);
+    }
+
     match session.mode {
         Mode::Visual(VisualState::Selecting { .. }) => {
             if session.is_selected(session.layer_coords(v.id, v.active_layer_id, c).into()) {
@@ -659,7 +796,10 @@ fn draw_brush(session: &Session, brush: &Brush, shapes: &mut shape2d::Batch) {
             }
         }
         Mode::Normal => {
-            if let Tool::Brush = session.tool {
+            if let (Tool::Curve, Some(curve)) = (&session.tool, &session.curve) {
+                draw_curve(session, curve, shapes);
+            }
+            if let Tool::Brush | Tool::Rectangle | Tool::Ellipse | Tool::Curve = session.tool {
                 let view_coords = session.active_view_coords(c);
                 let layer_coords = session.active_layer_coords(c);
 
@@ -692,14 +832,39 @@ fn draw_brush(session: &Session, brush: &Brush, shapes: &mut shape2d::Batch) {
                     };
 
                     for p in brush.expand(view_coords.into(), v.extent()) {
-                        shapes.add(brush.shape(
-                            *session.session_coords(v.id, p.into()),
-                            self::BRUSH_LAYER,
-                            stroke,
-                            fill,
-                            v.zoom,
-                            Align::BottomLeft,
-                        ));
+                        if let Some(stamp) = &brush.stamp {
+                            for (q, color) in stamp.pixels_at(*p) {
+                                let q = session
+                                    .session_coords(v.id, ViewCoords::new(q.x as f32, q.y as f32));
+                                let fill = match fill {
+                                    Fill::Solid(_) if !brush.is_set(BrushMode::Tint) => {
+                                        Fill::Solid(color.into())
+                                    }
+                                    Fill::Solid(_) => {
+                                        Fill::Solid(Stamp::tint(color, session.fg).into())
+                                    }
+                                    other => other,
+                                };
+                                shapes.add(Shape::Rectangle(
+                                    Rect::new(q.x, q.y, q.x + z, q.y + z),
+                                    self::BRUSH_LAYER,
+                                    Rotation::ZERO,
+                                    stroke,
+                                    fill,
+                                ));
+                            }
+                        } else {
+                            for shape in brush.tip_shapes(
+                                *session.session_coords(v.id, p.into()),
+                                self::BRUSH_LAYER,
+                                stroke,
+                                fill,
+                                v.zoom,
+                                Align::BottomLeft,
+                            ) {
+                                shapes.add(shape);
+                            }
+                        }
                     }
 
                     // X-Ray brush mode.
@@ -729,14 +894,16 @@ fn draw_brush(session: &Session, brush: &Brush, shapes: &mut shape2d::Batch) {
                     } else {
                         session.fg
                     };
-                    shapes.add(brush.shape(
+                    for shape in brush.tip_shapes(
                         *c,
                         self::UI_LAYER,
                         Stroke::new(1.0, color.into()),
                         Fill::Empty,
                         v.zoom,
                         Align::Center,
-                    ));
+                    ) {
+                        shapes.add(shape);
+                    }
                 }
             }
         }
@@ -819,6 +986,24 @@ pub fn draw_help(session: &Session, text: &mut TextBatch, shape: &mut shape2d::B
         TextAlign::Left,
     );
 
+    if let Some(listing) = &session.listing {
+        for (i, msg) in listing.iter().enumerate() {
+            let y = session.height - (i + 4) as f32 * self::LINE_HEIGHT;
+
+            text.add(
+                &msg.to_string(),
+                left_margin,
+                y,
+                self::HELP_LAYER,
+                msg.color(),
+                TextAlign::Left,
+            );
+        }
+        text.offset(session.help_offset.x, session.help_offset.y);
+
+        return;
+    }
+
     let (normal_kbs, visual_kbs): (
         Vec<(&String, &session::KeyBinding)>,
         Vec<(&String, &session::KeyBinding)>,
@@ -885,10 +1070,10 @@ pub fn draw_help(session: &Session, text: &mut TextBatch, shape: &mut shape2d::B
             );
         }
     }
-    for l in session::SETTINGS.lines() {
+    for l in session.settings.help() {
         if let Some(y) = line.next() {
             text.add(
-                l,
+                &l,
                 left_margin,
                 y as f32,
                 self::HELP_LAYER,
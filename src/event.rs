@@ -74,9 +74,17 @@ pub enum Event {
 impl From<Event> for String {
     fn from(event: Event) -> String {
         match event {
-            Event::MouseInput(_, platform::InputState::Pressed) => format!("mouse/input pressed"),
-            Event::MouseInput(_, platform::InputState::Released) => format!("mouse/input released"),
             Event::MouseInput(_, platform::InputState::Repeated) => unreachable!(),
+            Event::MouseInput(button, state) => {
+                let state = match state {
+                    platform::InputState::Pressed => "pressed",
+                    _ => "released",
+                };
+                match button {
+                    platform::MouseButton::Left => format!("mouse/input {}", state),
+                    other => format!("mouse/input {} {}", other, state),
+                }
+            }
             Event::MouseWheel(delta) => format!("mouse/wheel {} {}", delta.x, delta.y),
             Event::CursorMoved(platform::LogicalPosition { x, y }) => {
                 format!("cursor/moved {} {}", x, y)
@@ -107,11 +115,16 @@ impl FromStr for Event {
 
         let result: Result<(Self, &str), Self::Err> = match event.as_str() {
             "mouse/input" => {
-                let (s, p) = parser::param::<platform::InputState>()
-                    .followed_by(end())
-                    .parse(p)
-                    .map_err(|(e, _)| e)?;
-                Ok((Event::MouseInput(platform::MouseButton::Left, s), p))
+                let ((b, s), p) =
+                    optional(parser::param::<platform::MouseButton>().skip(whitespace()))
+                        .then(parser::param::<platform::InputState>())
+                        .followed_by(end())
+                        .parse(p)
+                        .map_err(|(e, _)| e)?;
+                Ok((
+                    Event::MouseInput(b.unwrap_or(platform::MouseButton::Left), s),
+                    p,
+                ))
             }
             "mouse/wheel" => {
                 let ((x, y), p) = parser::tuple::<f64>(rational(), rational())
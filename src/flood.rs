@@ -1,5 +1,6 @@
+use crate::dither::Dither;
 use crate::view::layer::LayerCoords;
-use crate::view::{View, ViewResource};
+use crate::view::{View, ViewExtent, ViewResource};
 use rgx::color::Rgba8;
 use rgx::kit::shape2d::{Fill, Rotation, Shape, Stroke};
 use rgx::kit::ZDepth;
@@ -28,22 +29,20 @@ impl Grid {
             None
         }
     }
-
-    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Rgba8> {
-        if x < self.width && y < self.height {
-            self.pixels.get_mut(x + y * self.width)
-        } else {
-            None
-        }
-    }
 }
 
 pub struct FloodFiller {
     grid: Grid,
     replacement_color: Rgba8,
-    target_color: Rgba8,
+    tolerance: u8,
+    diagonal: bool,
+    global: bool,
+    dither: Option<(Dither, Rgba8)>,
     rects: Vec<(Rect<f32>, Rgba8)>,
-    stack: Vec<Point2<usize>>,
+    /// Points to fill from. Each has its own target color.
+    starts: Vec<Point2<usize>>,
+    /// Pixels which have already been filled.
+    visited: Vec<bool>,
 }
 
 impl FloodFiller {
@@ -64,116 +63,235 @@ impl FloodFiller {
             starting_point.x as usize,
             grid.height - starting_point.y as usize - 1,
         );
+        grid.get(starting_point.x, starting_point.y)?;
 
-        let target_color = *grid.get(starting_point.x, starting_point.y)?;
         Some(FloodFiller {
+            visited: vec![false; grid.width * grid.height],
             grid,
-            target_color,
             replacement_color,
+            tolerance: 0,
+            diagonal: false,
+            global: false,
+            dither: None,
             rects: Vec::new(),
-            stack: vec![starting_point],
+            starts: vec![starting_point],
         })
     }
 
-    fn push_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: Rgba8) {
-        self.rects.push((
-            Rect::new(
-                x as f32,
-                (self.grid.height - y - 1) as f32,
-                (x + w) as f32,
-                (self.grid.height - y - 1 + h) as f32,
-            ),
-            color,
-        ));
+    /// Dither the replacement color with the given background color.
+    pub fn dither(mut self, dither: Dither, background: Rgba8) -> Self {
+        self.dither = Some((dither, background));
+        self
     }
 
-    fn try_set_at(&mut self, x: usize, y: usize) -> bool {
-        match self.grid.get_mut(x, y) {
-            Some(c) => {
-                if *c != self.target_color {
-                    false
-                } else {
-                    *c = self.replacement_color;
-                    true
-                }
+    /// Also fill pixels whose channels differ from the target color by at most
+    /// the given amount.
+    pub fn tolerance(mut self, tolerance: u8) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Also fill pixels which only touch the region diagonally.
+    pub fn diagonal(mut self, diagonal: bool) -> Self {
+        self.diagonal = diagonal;
+        self
+    }
+
+    /// Fill every pixel of the target color, whether or not it is connected
+    /// to the starting point.
+    pub fn global(mut self, global: bool) -> Self {
+        self.global = global;
+        self
+    }
+
+    /// Find the region to fill in the composite of all visible layers, rather
+    /// than in the active layer alone.
+    pub fn composite(mut self, view: &View<ViewResource>) -> Self {
+        let mut composite = vec![Rgba8::TRANSPARENT; self.grid.pixels.len()];
+
+        for (_, layer) in view.resource.layers() {
+            let (_, pixels) = layer.current_snapshot();
+
+            for (dst, src) in composite.iter_mut().zip(pixels) {
+                *dst = blend(*src, *dst);
             }
-            None => false,
         }
+        self.grid.pixels = composite;
+        self
     }
 
-    fn push_on_change(&mut self, x: usize, y: usize, edge: &mut bool) {
-        if let Some(c) = self.grid.get(x, y) {
-            if *c == self.target_color {
-                if *edge {
-                    // We're at an edge, we'll come back to this point in the next loop to start a
-                    // new horizontal span.
-                    self.stack.push(Point2::new(x, y));
-                    *edge = false;
-                }
-            } else {
-                *edge = true;
+    /// Fill from the same point in every frame of the given extent.
+    pub fn all_frames(mut self, extent: ViewExtent) -> Self {
+        let fw = extent.fw as usize;
+        let start = self.starts[0];
+
+        self.starts = (0..extent.nframes)
+            .map(|i| Point2::new(start.x % fw + i * fw, start.y))
+            .collect();
+        self
+    }
+
+    /// Return the region that would be filled, as spans along each row.
+    pub fn region(mut self) -> Vec<Rect<i32>> {
+        self.spans()
+    }
+
+    fn push_rect(&mut self, rect: Rect<i32>, color: Rgba8) {
+        if let Some((dither, background)) = self.dither {
+            for (r, c) in dither.fill(rect, color, Some(background)) {
+                self.rects.push((r.map(|n| n as f32), c));
             }
+        } else {
+            self.rects.push((rect.map(|n| n as f32), color));
         }
     }
 
-    fn look_above_below(&mut self, x: usize, y: usize, up: &mut bool, down: &mut bool) {
-        if y > 0 {
-            self.push_on_change(x, y - 1, up);
+    /// Check whether the pixel at the given position is yet to be filled, and
+    /// close enough to the target color.
+    fn is_fillable(&self, x: usize, y: usize, target: Rgba8) -> bool {
+        match self.grid.get(x, y) {
+            Some(c) => !self.visited[x + y * self.grid.width] && self.is_similar(*c, target),
+            None => false,
         }
+    }
+
+    fn is_similar(&self, a: Rgba8, b: Rgba8) -> bool {
+        let near = |a: u8, b: u8| (a as i32 - b as i32).abs() <= self.tolerance as i32;
 
-        if y < self.grid.height - 1 {
-            self.push_on_change(x, y + 1, down);
+        near(a.r, b.r) && near(a.g, b.g) && near(a.b, b.b) && near(a.a, b.a)
+    }
+
+    /// Mark a span of pixels as filled, and return it in layer coordinates.
+    fn visit(&mut self, min_x: usize, max_x: usize, y: usize) -> Rect<i32> {
+        for x in min_x..max_x {
+            self.visited[x + y * self.grid.width] = true;
         }
+        // Rows are flipped, since layer coordinates grow upwards.
+        let y = (self.grid.height - y - 1) as i32;
+        Rect::new(min_x as i32, y, max_x as i32, y + 1)
     }
 
     pub fn run(mut self) -> Option<Vec<Shape>> {
-        // This algorithm fills horizontally from the starting point, looking for edges above and
-        // below. An "edge" is a place where a solid pixel changes to a fillable one. "Solid" means
-        // not equal to self.target_color. When we see one of these transitions, we push the next
-        // point onto the stack and, later, we come back and repeat the horizontal scan from that
-        // point.
-        if self.target_color == self.replacement_color {
+        if self.tolerance == 0 {
+            let replacement = self.replacement_color;
+            let grid = &self.grid;
+
+            self.starts
+                .retain(|p| grid.get(p.x, p.y) != Some(&replacement));
+        }
+        if self.starts.is_empty() {
             return None;
         }
+        for rect in self.spans() {
+            self.push_rect(rect, self.replacement_color);
+        }
+        Some(to_shapes(self.rects))
+    }
 
-        while let Some(p) = self.stack.pop() {
-            let mut min_x = p.x;
-            let mut max_x = p.x;
-
-            // Keep track of whether the pixels above/below us are transitioning from solid to
-            // fillable. These will be true as long as we're "in" (above/below) a solid region, and
-            // will become false when we are past it.
-            let mut up_edge = true;
-            let mut down_edge = true;
-
-            // scan right
-            for x in p.x..=self.grid.width {
-                max_x = x;
-                if !self.try_set_at(x, p.y) {
-                    break;
-                }
-                self.look_above_below(x, p.y, &mut up_edge, &mut down_edge);
+    fn spans(&mut self) -> Vec<Rect<i32>> {
+        let mut spans = Vec::new();
+
+        for start in self.starts.clone() {
+            let target = match self.grid.get(start.x, start.y) {
+                Some(c) => *c,
+                None => continue,
+            };
+            if self.global {
+                self.global_spans(target, &mut spans);
+            } else {
+                self.contiguous_spans(start, target, &mut spans);
             }
+        }
+        spans
+    }
+
+    fn global_spans(&mut self, target: Rgba8, spans: &mut Vec<Rect<i32>>) {
+        for y in 0..self.grid.height {
+            let mut x = 0;
 
-            up_edge = p.y > 0 && self.grid.get(p.x, p.y - 1) != Some(&self.target_color);
-            down_edge = p.y < self.grid.height - 1
-                && self.grid.get(p.x, p.y + 1) != Some(&self.target_color);
+            while x < self.grid.width {
+                let min_x = x;
 
-            // scan left
-            for x in (0..p.x).rev() {
-                min_x = x;
-                if !self.try_set_at(x, p.y) {
-                    min_x += 1;
-                    break;
+                while self.is_fillable(x, y, target) {
+                    x += 1;
+                }
+                if x > min_x {
+                    spans.push(self.visit(min_x, x, y));
+                } else {
+                    x += 1;
                 }
-                self.look_above_below(x, p.y, &mut up_edge, &mut down_edge);
             }
+        }
+    }
+
+    fn contiguous_spans(
+        &mut self,
+        start: Point2<usize>,
+        target: Rgba8,
+        spans: &mut Vec<Rect<i32>>,
+    ) {
+        // This algorithm fills horizontally from each point on the stack, and then looks
+        // for fillable runs in the rows above and below the filled span. The first pixel
+        // of every such run is pushed onto the stack, so that we come back to it later.
+        let mut stack = vec![start];
+
+        while let Some(p) = stack.pop() {
+            if !self.is_fillable(p.x, p.y, target) {
+                continue;
+            }
+            let mut min_x = p.x;
+            let mut max_x = p.x + 1;
+
+            while min_x > 0 && self.is_fillable(min_x - 1, p.y, target) {
+                min_x -= 1;
+            }
+            while self.is_fillable(max_x, p.y, target) {
+                max_x += 1;
+            }
+            spans.push(self.visit(min_x, max_x, p.y));
+
+            // With diagonal connectivity, runs which only touch the corners of
+            // the span are also part of the region.
+            let (x1, x2) = if self.diagonal {
+                (min_x.saturating_sub(1), max_x + 1)
+            } else {
+                (min_x, max_x)
+            };
+            let above = p.y.checked_sub(1);
+            let below = Some(p.y + 1).filter(|y| *y < self.grid.height);
+
+            for y in above.into_iter().chain(below) {
+                let mut inside = false;
 
-            self.push_rect(min_x, p.y, max_x - min_x, 1, self.replacement_color);
+                for x in x1..x2 {
+                    let fillable = self.is_fillable(x, y, target);
+
+                    if fillable && !inside {
+                        stack.push(Point2::new(x, y));
+                    }
+                    inside = fillable;
+                }
+            }
         }
+    }
+}
 
-        Some(to_shapes(self.rects))
+/// Blend a color over another.
+fn blend(src: Rgba8, dst: Rgba8) -> Rgba8 {
+    let (sa, da) = (src.a as f32 / 255., dst.a as f32 / 255.);
+    let a = sa + da * (1. - sa);
+
+    if a == 0. {
+        return Rgba8::TRANSPARENT;
     }
+    let channel = |s: u8, d: u8| ((s as f32 * sa + d as f32 * da * (1. - sa)) / a).round() as u8;
+
+    Rgba8::new(
+        channel(src.r, dst.r),
+        channel(src.g, dst.g),
+        channel(src.b, dst.b),
+        (a * 255.).round() as u8,
+    )
 }
 
 fn to_shapes(input: Vec<(Rect<f32>, Rgba8)>) -> Vec<Shape> {
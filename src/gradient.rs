@@ -0,0 +1,198 @@
+use crate::dither::Dither;
+
+use rgx::kit::Rgba8;
+use rgx::math::Point2;
+use rgx::rect::Rect;
+
+/// How colors vary across a gradient.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum GradientKind {
+    /// Colors vary along the gradient line.
+    Linear,
+    /// Colors vary with the distance from the start of the gradient line.
+    Radial,
+}
+
+/// A ramp of colors painted across a line, from its first color at the start
+/// of the line to its last color at the end.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    pub colors: Vec<Rgba8>,
+    /// Whether to dither the transitions between colors, rather than painting
+    /// hard bands.
+    pub dithered: bool,
+
+    /// Center of the first pixel of the line.
+    from: Point2<f32>,
+    /// Center of the last pixel of the line.
+    to: Point2<f32>,
+}
+
+impl Gradient {
+    pub fn new(
+        kind: GradientKind,
+        from: Point2<i32>,
+        to: Point2<i32>,
+        colors: Vec<Rgba8>,
+        dithered: bool,
+    ) -> Self {
+        Self {
+            kind,
+            colors,
+            dithered,
+            from: Point2::new(from.x as f32 + 0.5, from.y as f32 + 0.5),
+            to: Point2::new(to.x as f32 + 0.5, to.y as f32 + 0.5),
+        }
+    }
+
+    /// Return the position of the given pixel along the gradient, between
+    /// `0.` at the start of the line and `1.` at the end.
+    fn position(&self, x: i32, y: i32) -> f32 {
+        let (dx, dy) = (self.to.x - self.from.x, self.to.y - self.from.y);
+        let (px, py) = (x as f32 + 0.5 - self.from.x, y as f32 + 0.5 - self.from.y);
+        let length = (dx * dx + dy * dy).sqrt();
+
+        if length == 0. {
+            return 0.;
+        }
+        let t = match self.kind {
+            GradientKind::Linear => (px * dx + py * dy) / (length * length),
+            GradientKind::Radial => (px * px + py * py).sqrt() / length,
+        };
+        t.clamp(0., 1.)
+    }
+
+    /// Return the color of the pixel at the given canvas coordinates.
+    pub fn color_at(&self, x: i32, y: i32) -> Rgba8 {
+        let n = self.colors.len();
+        let t = self.position(x, y);
+
+        if n < 2 {
+            return self.colors.first().copied().unwrap_or(Rgba8::TRANSPARENT);
+        }
+        if self.dithered {
+            // Mix neighbouring colors with an ordered dither, painting more of
+            // the next color the further the pixel is along the transition.
+            let t = t * (n - 1) as f32;
+            let i = (t.floor() as usize).min(n - 2);
+            let density = ((t - i as f32) * Dither::MAX_DENSITY as f32).round() as u8;
+
+            if Dither::Bayer(density).is_set(x, y) {
+                self.colors[i + 1]
+            } else {
+                self.colors[i]
+            }
+        } else {
+            // Every color gets a band of the same width.
+            self.colors[((t * n as f32) as usize).min(n - 1)]
+        }
+    }
+
+    /// Split a rectangle of pixels into runs of the same color along each row.
+    pub fn fill(&self, r: Rect<i32>) -> Vec<(Rect<i32>, Rgba8)> {
+        let mut runs = Vec::new();
+
+        for y in r.y1..r.y2 {
+            let mut x = r.x1;
+
+            while x < r.x2 {
+                let color = self.color_at(x, y);
+                let start = x;
+
+                while x < r.x2 && self.color_at(x, y) == color {
+                    x += 1;
+                }
+                runs.push((Rect::new(start, y, x, y + 1), color));
+            }
+        }
+        runs
+    }
+}
+
+/// Blend between two colors in the given number of steps, including both.
+pub fn ramp(start: Rgba8, end: Rgba8, number: usize) -> Vec<Rgba8> {
+    fn blend_component(start: u8, end: u8, coef: f32) -> u8 {
+        (start as f32 * (1.0 - coef) + end as f32 * coef).round() as u8
+    }
+
+    let step: f32 = 1.0 / ((number - 1) as f32);
+    (0..number)
+        .map(|i| {
+            let coef = i as f32 * step;
+            Rgba8 {
+                r: blend_component(start.r, end.r, coef),
+                g: blend_component(start.g, end.g, coef),
+                b: blend_component(start.b, end.b, coef),
+                a: blend_component(start.a, end.a, coef),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ramp() {
+        let colors = ramp(Rgba8::BLACK, Rgba8::WHITE, 5);
+        assert_eq!(colors.len(), 5);
+        assert_eq!(colors[0], Rgba8::BLACK);
+        assert_eq!(colors[2], Rgba8::new(0x80, 0x80, 0x80, 0xff));
+        assert_eq!(colors[4], Rgba8::WHITE);
+    }
+
+    #[test]
+    fn test_bands() {
+        let (r, g, b, w) = (Rgba8::RED, Rgba8::GREEN, Rgba8::BLUE, Rgba8::WHITE);
+        let gradient = Gradient::new(
+            GradientKind::Linear,
+            Point2::new(0, 0),
+            Point2::new(7, 0),
+            vec![r, g, b, w],
+            false,
+        );
+        let row: Vec<_> = (0..8).map(|x| gradient.color_at(x, 5)).collect();
+        assert_eq!(row, vec![r, r, g, g, b, b, w, w]);
+
+        // Pixels beyond the ends of the line get the first and last colors.
+        assert_eq!(gradient.color_at(-10, 0), r);
+        assert_eq!(gradient.color_at(20, 0), w);
+
+        let runs = gradient.fill(Rect::new(0, 0, 8, 2));
+        assert_eq!(runs.len(), 8);
+        assert!(runs.iter().all(|(r, _)| r.width() == 2));
+
+        let radial = Gradient::new(
+            GradientKind::Radial,
+            Point2::new(0, 0),
+            Point2::new(4, 0),
+            vec![r, g],
+            false,
+        );
+        assert_eq!(radial.color_at(1, 0), r);
+        assert_eq!(radial.color_at(0, -1), r);
+        assert_eq!(radial.color_at(-3, 0), g);
+        assert_eq!(radial.color_at(0, 3), g);
+    }
+
+    #[test]
+    fn test_dithered() {
+        let (b, w) = (Rgba8::BLACK, Rgba8::WHITE);
+        let gradient = Gradient::new(
+            GradientKind::Linear,
+            Point2::new(0, 0),
+            Point2::new(0, 15),
+            vec![b, w],
+            true,
+        );
+        let whites = |y: i32| (0..4).filter(|x| gradient.color_at(*x, y) == w).count();
+
+        assert_eq!(whites(0), 0);
+        assert_eq!(whites(15), 4);
+
+        let middle: usize = (6..10).map(whites).sum();
+        assert!(middle > 4 && middle < 12, "the middle is mixed");
+    }
+}
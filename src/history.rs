@@ -124,6 +124,17 @@ impl History {
             })
     }
 
+    /// Search for the most recent entry containing `query`, starting at entry
+    /// `start` and going back in time. Returns the entry and its index.
+    pub fn search(&self, query: &str, start: usize) -> Option<(usize, &str)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .skip(start)
+            .find(|(_, e)| e.contains(query))
+            .map(|(i, e)| (i, e.as_str()))
+    }
+
     pub fn get(&self, index: usize) -> Option<&str> {
         self.entries.get(index).map(|s| s.as_str())
     }
@@ -190,6 +201,21 @@ mod test {
         assert_eq!(h.next("fir"), None);
     }
 
+    #[test]
+    fn test_history_search() {
+        let mut h = History::new("/dev/null", 16);
+
+        h.add(":set scale = 2");
+        h.add(":p/add #ff0000");
+        h.add(":set grid");
+
+        assert_eq!(h.search("set", 0), Some((0, ":set grid")));
+        assert_eq!(h.search("set", 1), Some((2, ":set scale = 2")));
+        assert_eq!(h.search("set", 3), None);
+        assert_eq!(h.search("#ff", 0), Some((1, ":p/add #ff0000")));
+        assert_eq!(h.search("zoom", 0), None);
+    }
+
     #[test]
     fn test_history_empty() {
         let mut h = History::new("/dev/null", 16);
@@ -12,6 +12,8 @@ use std::path::{Path, PathBuf};
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Manifest {
     pub extent: ViewExtent,
+    /// Script applied to the view when it is loaded, eg. `set grid/spacing = 16 16`.
+    pub settings: Option<String>,
 }
 
 #[derive(Debug)]
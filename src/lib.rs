@@ -24,13 +24,16 @@ mod autocomplete;
 mod brush;
 mod cmd;
 mod color;
+mod dither;
 mod draw;
 mod event;
 mod flood;
 mod font;
+mod gradient;
 mod history;
 mod image;
 mod io;
+mod macros;
 mod palette;
 mod parser;
 mod pixels;
@@ -200,7 +203,14 @@ pub fn init<P: AsRef<Path>>(paths: &[P], options: Options<'_>) -> std::io::Resul
                     events.poll();
                 }
             }
-            _ if wait_events => events.wait(),
+            _ if wait_events => {
+                // If a key sequence is pending, wake up when it times out.
+                if let Some(remaining) = session.key_sequence_timeout() {
+                    events.wait_timeout(remaining);
+                } else {
+                    events.wait();
+                }
+            }
             _ => events.poll(),
         }
 
@@ -0,0 +1,177 @@
+use crate::cmd::Command;
+use crate::session::Mode;
+
+use std::collections::HashMap;
+
+/// Register which refers to the last macro played.
+pub const LAST_REGISTER: char = '@';
+
+/// Records and stores sequences of user commands, by register.
+#[derive(Debug, Default)]
+pub struct Macros {
+    /// Recorded macros, by register.
+    registers: HashMap<char, Vec<Command>>,
+    /// The register being recorded into, and the commands recorded so far.
+    recording: Option<(char, Vec<Command>)>,
+    /// The last register played.
+    last: Option<char>,
+    /// Current playback depth. Used to guard against runaway recursion.
+    pub depth: usize,
+}
+
+impl Macros {
+    /// Maximum macro playback depth, for macros which play other macros.
+    pub const MAX_DEPTH: usize = 16;
+
+    /// Create an empty set of macros.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether a character is a valid register name.
+    pub fn is_register(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == LAST_REGISTER
+    }
+
+    /// The register being recorded into, if any.
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(r, _)| *r)
+    }
+
+    /// Start recording into the given register. Upper-case registers append
+    /// to the existing contents of their lower-case counterpart.
+    pub fn start(&mut self, reg: char) {
+        let cmds = if reg.is_ascii_uppercase() {
+            self.registers
+                .get(&reg.to_ascii_lowercase())
+                .cloned()
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        self.recording = Some((reg.to_ascii_lowercase(), cmds));
+    }
+
+    /// Stop recording, and store the recorded commands in their register.
+    /// Returns the register and the number of commands recorded.
+    pub fn stop(&mut self) -> Option<(char, usize)> {
+        let (reg, cmds) = self.recording.take()?;
+        let len = cmds.len();
+
+        self.registers.insert(reg, cmds);
+
+        Some((reg, len))
+    }
+
+    /// Record a user command, if recording.
+    pub fn record(&mut self, cmd: &Command) {
+        if let Some((_, cmds)) = &mut self.recording {
+            match cmd {
+                // Commands which control recording are never recorded, and entering
+                // command mode is recorded as the command that is eventually run.
+                Command::MacroRecord(_)
+                | Command::MacroStop
+                | Command::MacroPlay(None, _)
+                | Command::Mode(Mode::Command)
+                | Command::Noop => {}
+                other => cmds.push(other.clone()),
+            }
+        }
+    }
+
+    /// Get the commands stored in a register, and remember it as the last
+    /// register played.
+    pub fn play(&mut self, reg: char) -> Option<Vec<Command>> {
+        let reg = if reg == LAST_REGISTER {
+            self.last?
+        } else {
+            reg.to_ascii_lowercase()
+        };
+        let cmds = self.registers.get(&reg)?.clone();
+
+        self.last = Some(reg);
+
+        Some(cmds)
+    }
+
+    /// Append a command to a register.
+    pub fn append(&mut self, reg: char, cmd: Command) {
+        self.registers
+            .entry(reg.to_ascii_lowercase())
+            .or_default()
+            .push(cmd);
+    }
+
+    /// Clear a register, or all registers if `None` is given.
+    pub fn clear(&mut self, reg: Option<char>) {
+        if let Some(reg) = reg {
+            self.registers.remove(&reg.to_ascii_lowercase());
+        } else {
+            self.registers.clear();
+        }
+    }
+
+    /// Iterate over all non-empty registers, in register order.
+    pub fn iter(&self) -> impl Iterator<Item = (char, &[Command])> {
+        let mut regs: Vec<_> = self
+            .registers
+            .iter()
+            .filter(|(_, cmds)| !cmds.is_empty())
+            .map(|(r, cmds)| (*r, cmds.as_slice()))
+            .collect();
+        regs.sort_by_key(|(r, _)| *r);
+        regs.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut macros = Macros::new();
+
+        macros.record(&Command::Undo);
+        assert_eq!(macros.recording(), None);
+
+        macros.start('a');
+        macros.record(&Command::Mode(Mode::Command));
+        macros.record(&Command::Undo);
+        macros.record(&Command::MacroStop);
+        macros.record(&Command::SwapColors);
+
+        assert_eq!(macros.recording(), Some('a'));
+        assert_eq!(macros.stop(), Some(('a', 2)));
+        assert_eq!(macros.recording(), None);
+        assert_eq!(
+            macros.play('a'),
+            Some(vec![Command::Undo, Command::SwapColors])
+        );
+        assert_eq!(macros.play('b'), None);
+    }
+
+    #[test]
+    fn test_append_and_last() {
+        let mut macros = Macros::new();
+
+        assert_eq!(macros.play(LAST_REGISTER), None);
+
+        macros.start('a');
+        macros.record(&Command::Undo);
+        macros.stop();
+
+        macros.start('A');
+        macros.record(&Command::Redo);
+        assert_eq!(macros.stop(), Some(('a', 2)));
+
+        macros.play('a');
+        assert_eq!(
+            macros.play(LAST_REGISTER),
+            Some(vec![Command::Undo, Command::Redo])
+        );
+
+        macros.clear(Some('a'));
+        assert_eq!(macros.play('a'), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::gradient;
 use crate::session::SessionCoords;
 
 use arrayvec::ArrayVec;
@@ -5,6 +6,8 @@ use rgx::kit::Rgba8;
 
 pub struct Palette {
     pub colors: ArrayVec<[Rgba8; 256]>,
+    /// Color ramps used for shading, in addition to the palette itself.
+    pub ramps: Vec<Vec<Rgba8>>,
     pub hover: Option<Rgba8>,
     pub cellsize: f32,
     pub height: usize,
@@ -16,6 +19,7 @@ impl Palette {
     pub fn new(cellsize: f32, height: usize) -> Self {
         Self {
             colors: ArrayVec::new(),
+            ramps: Vec::new(),
             hover: None,
             cellsize,
             height,
@@ -31,26 +35,31 @@ impl Palette {
     }
 
     pub fn gradient(&mut self, colorstart: Rgba8, colorend: Rgba8, number: usize) {
-        fn blend_component(start: u8, end: u8, coef: f32) -> u8 {
-            (start as f32 * (1.0 - coef) + end as f32 * coef).round() as u8
-        }
-
-        let step: f32 = 1.0 / ((number - 1) as f32);
-        for i in 0..number {
-            let coef = i as f32 * step;
-            let color: Rgba8 = Rgba8 {
-                r: blend_component(colorstart.r, colorend.r, coef),
-                g: blend_component(colorstart.g, colorend.g, coef),
-                b: blend_component(colorstart.b, colorend.b, coef),
-                a: blend_component(colorstart.a, colorend.a, coef),
-            };
-
+        for color in gradient::ramp(colorstart, colorend, number) {
             self.colors.push(color);
         }
     }
 
     pub fn clear(&mut self) {
         self.colors.clear();
+        self.ramps.clear();
+    }
+
+    /// Return the color found the given number of steps away from the given
+    /// color along its ramp, stopping at the ends of the ramp. Ramps added with
+    /// `p/ramp` are searched first, then the palette itself. Returns `None` if
+    /// the color isn't in any ramp.
+    pub fn shade(&self, color: Rgba8, offset: isize) -> Option<Rgba8> {
+        self.ramps
+            .iter()
+            .map(|r| r.as_slice())
+            .chain(std::iter::once(&self.colors[..]))
+            .find_map(|ramp| {
+                let i = ramp.iter().position(|c| *c == color)? as isize;
+                let j = (i + offset).max(0).min(ramp.len() as isize - 1);
+
+                Some(ramp[j as usize])
+            })
     }
 
     pub fn size(&self) -> usize {
@@ -5,9 +5,11 @@ use directories as dirs;
 
 use rgx::kit::Rgba8;
 
-use crate::brush::BrushMode;
+use crate::brush::{BrushMode, Step, Tip};
+use crate::dither::Dither;
+use crate::macros::Macros;
 use crate::platform;
-use crate::session::{Direction, Mode, VisualState};
+use crate::session::{Direction, Input, MessageType, Mode, VisualState, Wheel};
 
 use std::ffi::OsString;
 use std::str::FromStr;
@@ -106,6 +108,80 @@ impl Parse for platform::Key {
     }
 }
 
+impl Parse for platform::MouseButton {
+    fn parser() -> Parser<Self> {
+        natural::<u8>()
+            .map(platform::MouseButton::Other)
+            .or(word().try_map(|w| match w.as_str() {
+                "left" => Ok(platform::MouseButton::Left),
+                "right" => Ok(platform::MouseButton::Right),
+                "middle" => Ok(platform::MouseButton::Middle),
+                other => Err(format!("unknown mouse button: {}", other)),
+            }))
+            .label("<button>")
+    }
+}
+
+impl Parse for Wheel {
+    fn parser() -> Parser<Self> {
+        word()
+            .try_map(|w| match w.as_str() {
+                "up" => Ok(Wheel::Up),
+                "down" => Ok(Wheel::Down),
+                "left" => Ok(Wheel::Left),
+                "right" => Ok(Wheel::Right),
+                other => Err(format!("unknown wheel direction: {}", other)),
+            })
+            .label("up/down/left/right")
+    }
+}
+
+impl Parse for Input {
+    fn parser() -> Parser<Self> {
+        let character = between('\'', '\'', character()).map(Input::Character);
+        let leader = peek(string("<leader>")).value(Input::Leader);
+        let mouse = peek(between(
+            '<',
+            '>',
+            string("mouse/").then(param::<platform::MouseButton>()),
+        ))
+        .map(|(_, b)| Input::Mouse(b));
+        let wheel = peek(between('<', '>', string("wheel/").then(param::<Wheel>())))
+            .map(|(_, w)| Input::Wheel(w));
+        let key = param::<platform::Key>().map(Input::Key);
+
+        character
+            .or(leader)
+            .or(mouse)
+            .or(wheel)
+            .or(key)
+            .label("<input>")
+    }
+}
+
+/// Modifier keys held with an input, eg. the `<ctrl>` in `<ctrl><mouse/right>`.
+pub fn modifiers() -> Parser<platform::ModifiersState> {
+    many::<_, Vec<platform::Key>>(peek(param::<platform::Key>().try_map(|k| {
+        if k.is_modifier() {
+            Ok(k)
+        } else {
+            Err(format!("{} is not a modifier key", k))
+        }
+    })))
+    .map(|keys| {
+        let mut mods = platform::ModifiersState::default();
+        for k in keys {
+            match k {
+                platform::Key::Control => mods.ctrl = true,
+                platform::Key::Shift => mods.shift = true,
+                _ => mods.alt = true,
+            }
+        }
+        mods
+    })
+    .label("<modifiers>")
+}
+
 impl Parse for platform::InputState {
     fn parser() -> Parser<Self> {
         word().try_map(|w| match w.as_str() {
@@ -117,6 +193,24 @@ impl Parse for platform::InputState {
     }
 }
 
+impl Parse for MessageType {
+    fn parser() -> Parser<Self> {
+        word()
+            .try_map(|w| match w.as_str() {
+                "hint" => Ok(MessageType::Hint),
+                "info" => Ok(MessageType::Info),
+                "echo" => Ok(MessageType::Echo),
+                "error" => Ok(MessageType::Error),
+                "warning" => Ok(MessageType::Warning),
+                "execution" => Ok(MessageType::Execution),
+                "debug" => Ok(MessageType::Debug),
+                "okay" => Ok(MessageType::Okay),
+                other => Err(format!("unknown message type: {}", other)),
+            })
+            .label("<type>")
+    }
+}
+
 impl Parse for Direction {
     fn parser() -> Parser<Self> {
         character()
@@ -133,37 +227,148 @@ pub fn param<T: Parse>() -> Parser<T> {
     T::parser()
 }
 
+/// A color, eg. `#ff0000`, `#f00`, `rgb(255, 0, 0)`, `hsl(0, 100%, 50%)` or `red`.
 pub fn color() -> Parser<Rgba8> {
-    peek(
-        token()
-            .try_map(move |input| {
-                if input.is_empty() {
-                    return Err("expected color".to_owned());
-                }
-                if input.len() < 7 {
-                    return Err(format!("{:?} is not a valid color value", input));
-                }
-                let (s, alpha) = input.split_at(7);
-
-                match Rgba8::from_str(s) {
-                    Ok(color) => {
-                        if let Ok((a, _)) = symbol('/')
-                            .then(rational::<f64>())
-                            .map(|(_, a)| a)
-                            .parse(alpha)
-                        {
-                            Ok(color.alpha((a * std::u8::MAX as f64) as u8))
-                        } else {
-                            Ok(color)
-                        }
-                    }
-                    Err(_) => Err(format!("malformed color value `{}`", s)),
-                }
-            })
-            .label("<color>"),
+    Parser::new(
+        |input| match parse_color(input) {
+            Ok(result) => Ok(result),
+            Err(err) => Err((Error::new(err), input)),
+        },
+        "<color>",
     )
 }
 
+fn parse_color(input: &str) -> Result<(Rgba8, &str), String> {
+    const FUNCTIONS: &[&str] = &["rgba", "rgb", "hsla", "hsl", "hsva", "hsv"];
+
+    for f in FUNCTIONS {
+        if let Some(rest) = input.strip_prefix(f).and_then(|s| s.strip_prefix('(')) {
+            let end = rest
+                .find(')')
+                .ok_or_else(|| format!("unclosed '(' in `{}`", input))?;
+            let color = color_function(f, &rest[..end])?;
+
+            return Ok((color, &rest[end + 1..]));
+        }
+    }
+
+    let end = input.find(char::is_whitespace).unwrap_or(input.len());
+    let (token, rest) = input.split_at(end);
+
+    if token.is_empty() {
+        return Err("expected color".to_owned());
+    }
+    if let Some(hex) = token.strip_prefix('#') {
+        return color_hex(hex).map(|c| (c, rest));
+    }
+    crate::color::named(token)
+        .map(|c| (c, rest))
+        .ok_or_else(|| format!("{:?} is not a valid color value", token))
+}
+
+/// Parse a hex color, without its `#` prefix. The alpha can also be given
+/// as a fraction, eg. `#ff0000/0.5`.
+fn color_hex(hex: &str) -> Result<Rgba8, String> {
+    let (digits, alpha) = match hex.find('/') {
+        Some(i) => (&hex[..i], Some(&hex[i + 1..])),
+        None => (hex, None),
+    };
+    let malformed = || format!("malformed color value `#{}`", hex);
+
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(malformed());
+    }
+    let component = |i: usize, len: usize| {
+        let c = u8::from_str_radix(&digits[i * len..(i + 1) * len], 16).unwrap();
+        if len == 1 {
+            c * 0x11
+        } else {
+            c
+        }
+    };
+    let color = match digits.len() {
+        3 => Rgba8::new(component(0, 1), component(1, 1), component(2, 1), 0xff),
+        4 => Rgba8::new(
+            component(0, 1),
+            component(1, 1),
+            component(2, 1),
+            component(3, 1),
+        ),
+        6 => Rgba8::new(component(0, 2), component(1, 2), component(2, 2), 0xff),
+        8 => Rgba8::new(
+            component(0, 2),
+            component(1, 2),
+            component(2, 2),
+            component(3, 2),
+        ),
+        _ => {
+            return Err(format!(
+                "{:?} is not a valid color value",
+                format!("#{}", hex)
+            ))
+        }
+    };
+
+    match alpha {
+        Some(a) => match f64::from_str(a) {
+            Ok(a) if (0. ..=1.).contains(&a) => Ok(color.alpha((a * std::u8::MAX as f64) as u8)),
+            _ => Err(malformed()),
+        },
+        None => Ok(color),
+    }
+}
+
+/// Parse the arguments of a color function, eg. the `255, 0, 0` in `rgb(255, 0, 0)`.
+fn color_function(f: &str, args: &str) -> Result<Rgba8, String> {
+    let malformed = || format!("malformed color value `{}({})`", f, args);
+    let args: Vec<&str> = args.split(',').map(|a| a.trim()).collect();
+
+    // Parse a number, or a percentage as a fraction of `max`.
+    let number = |s: &str, max: f32| match s.strip_suffix('%') {
+        Some(p) => f32::from_str(p).map(|p| p / 100. * max),
+        None => f32::from_str(s),
+    };
+    let (rgb, alpha) = match args.as_slice() {
+        [a, b, c] => ((*a, *b, *c), None),
+        [a, b, c, alpha] => ((*a, *b, *c), Some(*alpha)),
+        _ => return Err(malformed()),
+    };
+    let alpha = match alpha {
+        Some(a) => number(a, 1.).map_err(|_| malformed())?,
+        None => 1.,
+    };
+    let (r, g, b) = if f.starts_with("rgb") {
+        (
+            number(rgb.0, 255.).map_err(|_| malformed())? / 255.,
+            number(rgb.1, 255.).map_err(|_| malformed())? / 255.,
+            number(rgb.2, 255.).map_err(|_| malformed())? / 255.,
+        )
+    } else {
+        // Saturation, lightness and value are percentages, with or without the `%`.
+        let percent = |s: &str| {
+            f32::from_str(s.trim_end_matches('%'))
+                .map(|p| p / 100.)
+                .map_err(|_| malformed())
+        };
+        let h = f32::from_str(rgb.0.trim_end_matches("deg")).map_err(|_| malformed())?;
+        let (s, x) = (percent(rgb.1)?, percent(rgb.2)?);
+
+        if f.starts_with("hsl") {
+            crate::color::hsl_to_rgb(h, s, x)
+        } else {
+            crate::color::hsv_to_rgb(h, s, x)
+        }
+    };
+    let channel = |c: f32| (c.clamp(0., 1.) * 255.).round() as u8;
+
+    Ok(Rgba8::new(
+        channel(r),
+        channel(g),
+        channel(b),
+        channel(alpha),
+    ))
+}
+
 impl Parse for BrushMode {
     fn parser() -> Parser<Self> {
         Parser::new(
@@ -176,10 +381,21 @@ impl Parse for BrushMode {
                     "xsym" => Ok((BrushMode::XSym, p)),
                     "ysym" => Ok((BrushMode::YSym, p)),
                     "xray" => Ok((BrushMode::XRay, p)),
+                    "tint" => Ok((BrushMode::Tint, p)),
                     "line" => optional(whitespace())
                         .then(optional(natural()))
                         .parse(p)
                         .map(|((_, snap), p)| (BrushMode::Line(snap), p)),
+                    "dither" => optional(whitespace())
+                        .then(optional(Dither::parser()))
+                        .parse(p)
+                        .map(|((_, dither), p)| {
+                            (BrushMode::Dither(dither.unwrap_or(Dither::Checker)), p)
+                        }),
+                    "shade" => optional(whitespace())
+                        .then(optional(Step::parser()))
+                        .parse(p)
+                        .map(|((_, step), p)| (BrushMode::Shade(step.unwrap_or(Step::Up)), p)),
                     mode => Err((
                         memoir::result::Error::new(format!("unknown brush mode '{}'", mode)),
                         input,
@@ -191,6 +407,81 @@ impl Parse for BrushMode {
     }
 }
 
+impl Parse for Tip {
+    fn parser() -> Parser<Self> {
+        Parser::new(
+            |input| {
+                let (id, p) = identifier().parse(input)?;
+                match id.as_str() {
+                    "square" => Ok((Tip::Square, p)),
+                    "circle" => Ok((Tip::Circle, p)),
+                    "diamond" => Ok((Tip::Diamond, p)),
+                    "hline" => Ok((Tip::HLine, p)),
+                    "vline" => Ok((Tip::VLine, p)),
+                    shape => Err((
+                        memoir::result::Error::new(format!("unknown brush shape '{}'", shape)),
+                        input,
+                    )),
+                }
+            },
+            "<shape>",
+        )
+    }
+}
+
+impl Parse for Step {
+    fn parser() -> Parser<Self> {
+        Parser::new(
+            |input| {
+                let (id, p) = identifier().parse(input)?;
+                match id.as_str() {
+                    "up" => Ok((Step::Up, p)),
+                    "down" => Ok((Step::Down, p)),
+                    step => Err((
+                        memoir::result::Error::new(format!("unknown shading step '{}'", step)),
+                        input,
+                    )),
+                }
+            },
+            "<step>",
+        )
+    }
+}
+
+impl Parse for Dither {
+    fn parser() -> Parser<Self> {
+        Parser::new(
+            |input| {
+                let (id, p) = identifier().parse(input)?;
+                match id.as_str() {
+                    "checker" => Ok((Dither::Checker, p)),
+                    "bayer" => {
+                        let ((_, density), p) = optional(whitespace())
+                            .then(optional(natural::<u8>()))
+                            .parse(p)?;
+                        match density.unwrap_or(Dither::MAX_DENSITY / 2) {
+                            d if d <= Dither::MAX_DENSITY => Ok((Dither::Bayer(d), p)),
+                            d => Err((
+                                memoir::result::Error::new(format!(
+                                    "dither density {} is out of range, it must be between 0 and {}",
+                                    d,
+                                    Dither::MAX_DENSITY
+                                )),
+                                input,
+                            )),
+                        }
+                    }
+                    pattern => Err((
+                        memoir::result::Error::new(format!("unknown dither pattern '{}'", pattern)),
+                        input,
+                    )),
+                }
+            },
+            "<pattern>",
+        )
+    }
+}
+
 impl Parse for Mode {
     fn parser() -> Parser<Self> {
         Parser::new(
@@ -201,6 +492,7 @@ impl Parse for Mode {
                     "normal" => Ok((Mode::Normal, p)),
                     "visual" => Ok((Mode::Visual(VisualState::default()), p)),
                     "present" => Ok((Mode::Present, p)),
+                    "help" => Ok((Mode::Help, p)),
                     mode => Err((
                         memoir::result::Error::new(format!("unknown mode: {}", mode)),
                         input,
@@ -212,8 +504,15 @@ impl Parse for Mode {
     }
 }
 
+/// A double-quoted string. Quotes and backslashes inside the string are
+/// escaped with a backslash, eg. `"say \"hi\""`.
 pub fn quoted() -> Parser<String> {
-    between('"', '"', until(symbol('"')))
+    let escaped = symbol('\\')
+        .then(symbol('"').or(symbol('\\')))
+        .map(|(_, c)| c);
+    let unescaped = satisfy(|c| c != '"', "!\"");
+
+    between('"', '"', any::<_, String>(escaped.or(unescaped)))
 }
 
 pub fn paths() -> Parser<Vec<String>> {
@@ -224,6 +523,10 @@ pub fn setting() -> Parser<String> {
     identifier().label("<setting>")
 }
 
+pub fn register() -> Parser<char> {
+    satisfy(Macros::is_register, "<register>").label("<register>")
+}
+
 pub fn tuple<O>(x: Parser<O>, y: Parser<O>) -> Parser<(O, O)> {
     x.skip(whitespace()).then(y)
 }
@@ -252,4 +555,44 @@ mod test {
         assert_eq!(a, Rgba8::new(0xff, 0xaa, 0x44, 127));
         assert_eq!(b, Rgba8::new(0x14, 0x14, 0x14, 255));
     }
+
+    #[test]
+    fn test_color_syntax() {
+        let p = color();
+        let red = Rgba8::new(0xff, 0, 0, 0xff);
+
+        for input in &[
+            "#f00",
+            "#f00f",
+            "#ff0000",
+            "#ff0000ff",
+            "rgb(255, 0, 0)",
+            "rgba(255,0,0,1.0)",
+            "rgb(100%, 0%, 0%)",
+            "hsl(0, 100%, 50%)",
+            "hsl(360deg, 100, 50)",
+            "hsv(0, 100%, 100%)",
+            "red",
+        ] {
+            assert_eq!(p.parse(input), Ok((red, "")), "{}", input);
+        }
+        assert_eq!(
+            p.parse("#f008 rest"),
+            Ok((Rgba8::new(0xff, 0, 0, 0x88), " rest"))
+        );
+        assert_eq!(
+            p.parse("hsla(120, 100%, 25%, 50%) rest"),
+            Ok((Rgba8::new(0, 0x80, 0, 0x80), " rest"))
+        );
+
+        assert!(p.parse("#ff00").is_ok());
+        assert!(p.parse("#ff000").is_err());
+        assert!(p.parse("#gg0000").is_err());
+        assert!(p.parse("rgb(255, 0)").is_err());
+        assert!(p.parse("rgb(255, 0, 0").is_err());
+        assert!(
+            p.parse("skin").is_err(),
+            "color names are referenced with `@`"
+        );
+    }
 }
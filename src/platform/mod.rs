@@ -302,6 +302,17 @@ pub struct ModifiersState {
     pub meta: bool,
 }
 
+impl fmt::Display for MouseButton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MouseButton::Left => "left".fmt(f),
+            MouseButton::Right => "right".fmt(f),
+            MouseButton::Middle => "middle".fmt(f),
+            MouseButton::Other(n) => n.fmt(f),
+        }
+    }
+}
+
 impl fmt::Display for ModifiersState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut s = String::new();
@@ -1,14 +1,19 @@
 ///! Session
 use crate::autocomplete::FileCompleter;
 use crate::brush::*;
-use crate::cmd::{self, Command, CommandLine, KeyMapping, Op, Value};
+use crate::cmd::{self, ColorRef, Command, CommandLine, KeyMapping, KeySequence, Op, Scope, Value};
 use crate::color;
 use crate::data;
+use crate::dither::Dither;
 use crate::event::{Event, TimedEvent};
 use crate::execution::{DigestMode, DigestState, Execution};
 use crate::flood::FloodFiller;
+use crate::gradient::{self, Gradient, GradientKind};
 use crate::hashmap;
+use crate::image;
+use crate::macros::{self, Macros};
 use crate::palette::*;
+use crate::parser::param;
 use crate::platform::{self, InputState, Key, KeyboardInput, LogicalSize, ModifiersState};
 use crate::util;
 use crate::view::layer::{LayerCoords, LayerId};
@@ -26,10 +31,12 @@ use rgx::rect::Rect;
 
 use arrayvec::ArrayVec;
 
+use chrono::{DateTime, Local};
+
 use directories as dirs;
 use nonempty::NonEmpty;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
 use std::fmt;
 use std::fs::File;
@@ -39,21 +46,6 @@ use std::ops::{Add, Deref, Sub};
 use std::path::{Path, PathBuf};
 use std::time;
 
-/// Settings help string.
-pub const SETTINGS: &str = r#"
-SETTINGS
-
-debug             on/off             Debug mode
-checker           on/off             Alpha checker toggle
-scale             1.0..4.0           UI scale
-animation         on/off             View animation toggle
-animation/delay   1..1000            View animation delay (ms)
-background        #000000..#ffffff   Set background appearance to <color>
-grid              on/off             Grid display
-grid/color        #000000..#ffffff   Grid color
-grid/spacing      <x> <y>            Grid spacing
-"#;
-
 /// An RGB 8-bit color. Used when the alpha value isn't used.
 #[repr(C)]
 #[derive(Copy, Clone)]
@@ -294,6 +286,14 @@ pub enum Tool {
     Brush,
     /// Used for filling enclosed regions with color.
     FloodFill,
+    /// Used to draw rectangles, by dragging from one corner to the other.
+    Rectangle,
+    /// Used to draw ellipses, by dragging out their bounding box.
+    Ellipse,
+    /// Used to draw polylines and Bézier curves through points placed by clicking.
+    Curve,
+    /// Used to fill the selection or a region with a gradient, by dragging a line across it.
+    Gradient,
     /// Used to sample colors.
     Sampler,
     /// Used to pan the workspace.
@@ -338,6 +338,7 @@ impl From<Direction> for i32 {
 }
 
 /// A message to the user, displayed in the session.
+#[derive(PartialEq, Clone, Debug)]
 pub struct Message {
     /// The message string.
     string: String,
@@ -367,6 +368,15 @@ impl Message {
         self.message_type == MessageType::Debug
     }
 
+    pub fn is_hint(&self) -> bool {
+        self.message_type == MessageType::Hint
+    }
+
+    /// Return the type of a message.
+    pub fn message_type(&self) -> MessageType {
+        self.message_type
+    }
+
     /// Log a message to stdout/stderr.
     fn log(&self) {
         match self.message_type {
@@ -431,15 +441,93 @@ impl MessageType {
     }
 }
 
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hint => "hint".fmt(f),
+            Self::Info => "info".fmt(f),
+            Self::Echo => "echo".fmt(f),
+            Self::Error => "error".fmt(f),
+            Self::Warning => "warning".fmt(f),
+            Self::Execution => "execution".fmt(f),
+            Self::Debug => "debug".fmt(f),
+            Self::Okay => "okay".fmt(f),
+        }
+    }
+}
+
+/// A bounded log of the messages shown to the user, oldest first.
+#[derive(Debug)]
+pub struct Messages {
+    entries: VecDeque<(DateTime<Local>, Message)>,
+    capacity: usize,
+}
+
+impl Messages {
+    /// Create an empty message log holding up to `capacity` messages.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Add a message to the log, dropping the oldest message if the log is full.
+    pub fn push(&mut self, msg: Message) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((Local::now(), msg));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over the logged messages and the time they were shown.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &(DateTime<Local>, Message)> {
+        self.entries.iter()
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 
 /// A session error.
 type Error = String;
 
+/// A mouse wheel direction.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Wheel {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl fmt::Display for Wheel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Up => "up".fmt(f),
+            Self::Down => "down".fmt(f),
+            Self::Left => "left".fmt(f),
+            Self::Right => "right".fmt(f),
+        }
+    }
+}
+
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum Input {
     Key(Key),
     Character(char),
+    Mouse(platform::MouseButton),
+    Wheel(Wheel),
+    /// The leader key, as configured with the `leader` setting. Only used in
+    /// key mappings, and resolved when the mapping is added.
+    Leader,
 }
 
 impl fmt::Display for Input {
@@ -447,6 +535,9 @@ impl fmt::Display for Input {
         match self {
             Self::Key(k) => write!(f, "{}", k),
             Self::Character(c) => write!(f, "{}", c),
+            Self::Mouse(b) => write!(f, "<mouse/{}>", b),
+            Self::Wheel(w) => write!(f, "<wheel/{}>", w),
+            Self::Leader => write!(f, "<leader>"),
         }
     }
 }
@@ -460,6 +551,9 @@ pub struct KeyBinding {
     pub modifiers: ModifiersState,
     /// Input expected to trigger the binding.
     pub input: Input,
+    /// Keys which must be pressed before `input`, for key sequences like `g g`.
+    /// Empty for single-key bindings.
+    pub prefix: Vec<Key>,
     /// Whether the key should be pressed or released.
     pub state: InputState,
     /// The `Command` to run when this binding is triggered.
@@ -469,9 +563,28 @@ pub struct KeyBinding {
     /// How this key binding should be displayed to the user.
     /// If `None`, then this binding shouldn't be shown to the user.
     pub display: Option<String>,
+    /// Where this key binding was defined, if it was sourced from a script.
+    pub source: Option<SourceLocation>,
 }
 
 impl KeyBinding {
+    /// Whether two bindings are triggered by the same input.
+    fn same_keys(&self, other: &KeyBinding) -> bool {
+        self.input == other.input
+            && self.prefix == other.prefix
+            && self.modifiers == other.modifiers
+            && self.state == other.state
+    }
+
+    /// The key sequence triggering this binding.
+    fn keys(&self) -> KeySequence {
+        KeySequence {
+            input: self.input,
+            prefix: self.prefix.iter().map(|k| Input::Key(*k)).collect(),
+            modifiers: self.modifiers,
+        }
+    }
+
     fn is_match(
         &self,
         input: Input,
@@ -497,13 +610,25 @@ impl KeyBinding {
                     && self.state == state
                     && self.modifiers.ctrl == modifiers.ctrl
             }
+            (Input::Mouse(a), Input::Mouse(b)) => {
+                a == b
+                    && self.modes.contains(&mode)
+                    && self.state == state
+                    && (self.modifiers == modifiers || state == InputState::Released)
+            }
+            (Input::Wheel(a), Input::Wheel(b)) => {
+                a == b
+                    && self.modes.contains(&mode)
+                    && self.state == state
+                    && self.modifiers == modifiers
+            }
             _ => false,
         }
     }
 }
 
 /// Manages a list of key bindings.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct KeyBindings {
     elems: Vec<KeyBinding>,
 }
@@ -523,8 +648,10 @@ impl KeyBindings {
     /// Add a key binding.
     pub fn add(&mut self, binding: KeyBinding) {
         for mode in binding.modes.iter() {
-            self.elems
-                .retain(|kb| !kb.is_match(binding.input, binding.state, binding.modifiers, *mode));
+            self.elems.retain(|kb| {
+                kb.prefix != binding.prefix
+                    || !kb.is_match(binding.input, binding.state, binding.modifiers, *mode)
+            });
         }
         self.elems.push(binding);
     }
@@ -537,6 +664,29 @@ impl KeyBindings {
         self.elems.is_empty()
     }
 
+    /// Remove the key bindings for the given input in the given modes.
+    /// Returns `true` if any binding was removed.
+    pub fn remove(
+        &mut self,
+        input: Input,
+        prefix: &[Key],
+        modifiers: ModifiersState,
+        modes: &[Mode],
+    ) -> bool {
+        let mut removed = false;
+
+        for kb in self.elems.iter_mut() {
+            if kb.input == input && kb.prefix == prefix && kb.modifiers == modifiers {
+                let len = kb.modes.len();
+                kb.modes.retain(|m| !modes.contains(m));
+                removed |= kb.modes.len() != len;
+            }
+        }
+        self.elems.retain(|kb| !kb.modes.is_empty());
+
+        removed
+    }
+
     /// Find a key binding based on some input state.
     pub fn find(
         &self,
@@ -549,7 +699,32 @@ impl KeyBindings {
             .iter()
             .rev()
             .cloned()
-            .find(|kb| kb.is_match(input, state, modifiers, mode))
+            .find(|kb| kb.prefix.is_empty() && kb.is_match(input, state, modifiers, mode))
+    }
+
+    /// Check whether the given keys are the start of a key sequence binding.
+    pub fn is_prefix(&self, keys: &[Key], mode: Mode) -> bool {
+        self.elems.iter().any(|kb| {
+            kb.state == InputState::Pressed
+                && kb.modes.contains(&mode)
+                && kb.prefix.starts_with(keys)
+        })
+    }
+
+    /// Find a key binding matching a complete key sequence.
+    pub fn find_sequence(&self, keys: &[Key], mode: Mode) -> Option<KeyBinding> {
+        let (key, prefix) = keys.split_last()?;
+
+        self.elems
+            .iter()
+            .rev()
+            .find(|kb| {
+                kb.state == InputState::Pressed
+                    && kb.modes.contains(&mode)
+                    && kb.prefix == prefix
+                    && kb.input == Input::Key(*key)
+            })
+            .cloned()
     }
 
     /// Iterate over all key bindings.
@@ -560,10 +735,148 @@ impl KeyBindings {
 
 ///////////////////////////////////////////////////////////////////////////////
 
+/// A line in a sourced script.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SourceLocation {
+    /// Path of the script, as it was sourced.
+    pub path: String,
+    /// Line number, starting at `1`.
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.path, self.line)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// A constraint on the values a setting can take, beyond its type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Constraint {
+    /// Any value of the setting's type.
+    None,
+    /// A number, or pair of numbers, within an inclusive range.
+    Range(f64, f64),
+    /// One of a set of strings.
+    OneOf(&'static [&'static str]),
+}
+
+/// Function called when a setting changes, with the old and new values.
+pub type SettingHook = fn(&mut Session, &Value, &Value);
+
+/// A setting's schema: its default value, description and allowed values.
+#[derive(Clone)]
+pub struct Setting {
+    pub default: Value,
+    pub description: &'static str,
+    pub constraint: Constraint,
+    /// Whether the setting can be set for a single view, eg. from a sidecar script.
+    pub per_view: bool,
+    on_change: Option<SettingHook>,
+}
+
+impl fmt::Debug for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Setting")
+            .field("default", &self.default)
+            .field("description", &self.description)
+            .field("constraint", &self.constraint)
+            .field("per_view", &self.per_view)
+            .field("on_change", &self.on_change.is_some())
+            .finish()
+    }
+}
+
+impl Setting {
+    fn new(default: Value, description: &'static str) -> Self {
+        Self {
+            default,
+            description,
+            constraint: Constraint::None,
+            per_view: false,
+            on_change: None,
+        }
+    }
+
+    fn range(mut self, min: f64, max: f64) -> Self {
+        self.constraint = Constraint::Range(min, max);
+        self
+    }
+
+    fn one_of(mut self, values: &'static [&'static str]) -> Self {
+        self.constraint = Constraint::OneOf(values);
+        self
+    }
+
+    fn per_view(mut self) -> Self {
+        self.per_view = true;
+        self
+    }
+
+    fn on_change(mut self, hook: SettingHook) -> Self {
+        self.on_change = Some(hook);
+        self
+    }
+
+    /// Describe the values this setting accepts, eg. `1..1000`.
+    pub fn allowed(&self) -> String {
+        match (&self.constraint, &self.default) {
+            (Constraint::Range(min, max), Value::F64(_)) => format!("{:.1}..{:.1}", min, max),
+            (Constraint::Range(min, max), _) => format!("{}..{}", min, max),
+            (Constraint::OneOf(values), _) => values.join("/"),
+            (Constraint::None, Value::Bool(_)) => String::from("on/off"),
+            (Constraint::None, Value::Rgba8(_)) => String::from("<color>"),
+            (Constraint::None, Value::U32Tuple(_, _)) => String::from("<x> <y>"),
+            (Constraint::None, Value::Str(_)) => String::from("<string>"),
+            (Constraint::None, _) => String::from("<value>"),
+        }
+    }
+
+    /// Read `on` and `off` as words when the setting expects one of them,
+    /// since the command parser reads them as booleans.
+    fn coerce(&self, v: Value) -> Value {
+        match (&self.constraint, v) {
+            (Constraint::OneOf(values), Value::Bool(b))
+                if values.contains(&Value::Bool(b).to_string().as_str()) =>
+            {
+                Value::Ident(Value::Bool(b).to_string())
+            }
+            (_, v) => v,
+        }
+    }
+
+    /// Check that a value is allowed for this setting.
+    fn validate(&self, v: &Value) -> bool {
+        if std::mem::discriminant(v) != std::mem::discriminant(&self.default) {
+            return false;
+        }
+        match (&self.constraint, v) {
+            (Constraint::None, _) => true,
+            (Constraint::Range(min, max), v) => {
+                let within = |n: f64| n >= *min && n <= *max;
+
+                match v {
+                    Value::U32(n) => within(*n as f64),
+                    Value::F64(n) => within(*n),
+                    Value::U32Tuple(x, y) => within(*x as f64) && within(*y as f64),
+                    Value::F32Tuple(x, y) => within(*x as f64) && within(*y as f64),
+                    _ => true,
+                }
+            }
+            (Constraint::OneOf(values), Value::Str(s))
+            | (Constraint::OneOf(values), Value::Ident(s)) => values.contains(&s.as_str()),
+            (Constraint::OneOf(_), _) => true,
+        }
+    }
+}
+
 /// A dictionary used to store session settings.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Settings {
     map: HashMap<String, Value>,
+    schema: HashMap<String, Setting>,
 }
 
 impl Settings {
@@ -574,57 +887,210 @@ impl Settings {
         self.map.get(setting)
     }
 
+    /// Lookup a setting's schema.
+    pub fn schema(&self, setting: &str) -> Option<&Setting> {
+        self.schema.get(setting)
+    }
+
+    /// The names of all settings which aren't deprecated, in alphabetical order.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .map
+            .keys()
+            .map(|k| k.as_str())
+            .filter(|k| !Self::DEPRECATED.contains(k))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
     /// Set an existing setting to a new value. Returns `Err` if there is a type
-    /// mismatch or the setting isn't found. Otherwise, returns `Ok` with the
-    /// old value.
+    /// mismatch, the value is out of range, or the setting isn't found. Otherwise,
+    /// returns `Ok` with the old value.
     pub fn set(&mut self, k: &str, v: Value) -> Result<Value, Error> {
-        if let Some(current) = self.get(k) {
-            if std::mem::discriminant(&v) == std::mem::discriminant(current) {
-                return Ok(self.map.insert(k.to_string(), v).unwrap());
-            }
-            Err(format!(
-                "invalid value `{}` for `{}`, expected {}",
-                v,
-                k,
-                current.description()
-            ))
-        } else {
-            Err(format!("no such setting `{}`", k))
+        let v = self.coerce(k, v);
+        self.check(k, &v)?;
+
+        Ok(self.map.insert(k.to_string(), v).unwrap())
+    }
+
+    /// Convert a value to the type a setting expects, where possible.
+    pub fn coerce(&self, k: &str, v: Value) -> Value {
+        match self.schema.get(k) {
+            Some(setting) => setting.coerce(v),
+            None => v,
+        }
+    }
+
+    /// Check that a setting exists, and that the value is allowed for it.
+    pub fn check(&self, k: &str, v: &Value) -> Result<&Setting, Error> {
+        let setting = self
+            .schema
+            .get(k)
+            .ok_or_else(|| format!("no such setting `{}`", k))?;
+
+        if setting.validate(v) {
+            return Ok(setting);
+        }
+        Err(format!(
+            "invalid value `{}` for `{}`, expected {}",
+            v,
+            k,
+            match setting.constraint {
+                Constraint::None => setting.default.description().to_owned(),
+                _ => setting.allowed(),
+            }
+        ))
+    }
+
+    /// Settings help, as displayed in `:help`.
+    pub fn help(&self) -> Vec<String> {
+        let mut lines = vec![String::new(), String::from("SETTINGS"), String::new()];
+
+        for name in self.names() {
+            let setting = &self.schema[name];
+            lines.push(format!(
+                "{:<17} {:<18} {}{}",
+                name,
+                setting.allowed(),
+                setting.description,
+                if setting.per_view { " (per view)" } else { "" }
+            ));
+        }
+        lines
+    }
+
+    /// Settings listing, as displayed by `:set` without arguments.
+    pub fn listing(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{:<17} {:<16} {:<16} {}",
+            "NAME", "VALUE", "DEFAULT", "DESCRIPTION"
+        )];
+
+        for name in self.names() {
+            let setting = &self.schema[name];
+            lines.push(format!(
+                "{:<17} {:<16} {:<16} {}",
+                name,
+                self.map[name].to_string(),
+                setting.default.to_string(),
+                setting.description
+            ));
         }
+        lines
     }
 }
 
 impl Default for Settings {
     /// The default settings.
     fn default() -> Self {
+        let schema: HashMap<String, Setting> = hashmap! {
+            "debug" => Setting::new(Value::Bool(false), "Debug mode"),
+            "checker" => Setting::new(Value::Bool(false), "Alpha checker toggle"),
+            "background" => Setting::new(
+                Value::Rgba8(color::TRANSPARENT),
+                "Set background appearance to <color>"
+            ),
+            "input/mouse" => Setting::new(Value::Bool(true), "Mouse input toggle"),
+            "input/timeout" => Setting::new(
+                Value::U32(1000),
+                "Time to wait for the next key of a sequence (ms)"
+            )
+            .range(0., 10_000.),
+            "leader" => Setting::new(Value::Str(String::from("\\")), "Leader key"),
+            "scale" => Setting::new(Value::F64(1.0), "UI scale")
+                .range(1., 4.)
+                .on_change(Session::scale_changed),
+            "animation" => Setting::new(Value::Bool(true), "View animation toggle"),
+            "animation/delay" => Setting::new(Value::U32(160), "View animation delay (ms)")
+                .range(1., 1000.)
+                .per_view(),
+            "shape/fill" => Setting::new(Value::Bool(false), "Fill rectangles and ellipses instead of outlining them"),
+            "fill/dither" => Setting::new(
+                Value::Ident(String::from("off")),
+                "Dither pattern used by flood and selection fills"
+            )
+            .one_of(&["off", "checker", "bayer"]),
+            "fill/density" => Setting::new(
+                Value::U32(8),
+                "Pixels out of 16 filled with the foreground color by the bayer pattern"
+            )
+            .range(0., 16.),
+            "flood/tolerance" => Setting::new(
+                Value::U32(0),
+                "Difference per color channel up to which flood fills treat colors as the same"
+            )
+            .range(0., 255.),
+            "flood/diagonal" => Setting::new(Value::Bool(false), "Flood fill across diagonally touching pixels"),
+            "flood/global" => Setting::new(Value::Bool(false), "Flood fill every pixel of the target color, connected or not"),
+            "flood/sample" => Setting::new(
+                Value::Ident(String::from("layer")),
+                "Find flood fill regions in the active layer, or in the composite of visible layers"
+            )
+            .one_of(&["layer", "composite"]),
+            "flood/all-frames" => Setting::new(Value::Bool(false), "Flood fill the same point in every frame"),
+            "shape/curve" => Setting::new(
+                Value::Ident(String::from("line")),
+                "Curve drawn through the points placed with the curve tool"
+            )
+            .one_of(&["line", "quad", "cubic"])
+            .on_change(Session::curve_kind_changed),
+            "brush/shape" => Setting::new(
+                Value::Ident(String::from("square")),
+                "Shape of the brush tip"
+            )
+            .one_of(&["square", "circle", "diamond", "hline", "vline"])
+            .on_change(Session::brush_shape_changed),
+            "gradient/shape" => Setting::new(
+                Value::Ident(String::from("linear")),
+                "Whether gradients vary along the line or with the distance from its start"
+            )
+            .one_of(&["linear", "radial"]),
+            "gradient/colors" => Setting::new(
+                Value::Ident(String::from("blend")),
+                "Blend gradients from fg to bg, or run through the palette entries between them"
+            )
+            .one_of(&["blend", "palette"]),
+            "gradient/steps" => Setting::new(Value::U32(8), "Number of colors in blended gradients")
+                .range(2., 256.),
+            "gradient/mode" => Setting::new(
+                Value::Ident(String::from("bands")),
+                "Paint gradients in hard bands, or dither the transitions between colors"
+            )
+            .one_of(&["bands", "dither"]),
+            "ui/palette" => Setting::new(Value::Bool(true), "Palette display"),
+            "ui/status" => Setting::new(Value::Bool(true), "Status bar display"),
+            "ui/cursor" => Setting::new(Value::Bool(true), "Cursor display"),
+            "ui/message" => Setting::new(Value::Bool(true), "Message display"),
+            "ui/switcher" => Setting::new(Value::Bool(true), "Brush switcher display"),
+            "ui/view-info" => Setting::new(Value::Bool(true), "View info display"),
+
+            "grid" => Setting::new(Value::Bool(false), "Grid display").per_view(),
+            "grid/color" => Setting::new(Value::Rgba8(color::BLUE), "Grid color").per_view(),
+            "grid/spacing" => Setting::new(Value::U32Tuple(8, 8), "Grid spacing")
+                .range(1., 4096.)
+                .per_view(),
+
+            "p/height" => Setting::new(
+                Value::U32(Session::PALETTE_HEIGHT),
+                "Number of palette rows"
+            )
+            .range(1., 256.)
+            .on_change(Session::palette_height_changed),
+
+            "debug/crosshair" => Setting::new(Value::Bool(false), "Debug crosshair display"),
+
+            // Deprecated.
+            "frame_delay" => Setting::new(Value::F64(0.0), "Deprecated"),
+            "input/delay" => Setting::new(Value::F64(8.0), "Deprecated")
+        };
+
         Self {
-            map: hashmap! {
-                "debug" => Value::Bool(false),
-                "checker" => Value::Bool(false),
-                "background" => Value::Rgba8(color::TRANSPARENT),
-                "input/mouse" => Value::Bool(true),
-                "scale" => Value::F64(1.0),
-                "animation" => Value::Bool(true),
-                "animation/delay" => Value::U32(160),
-                "ui/palette" => Value::Bool(true),
-                "ui/status" => Value::Bool(true),
-                "ui/cursor" => Value::Bool(true),
-                "ui/message" => Value::Bool(true),
-                "ui/switcher" => Value::Bool(true),
-                "ui/view-info" => Value::Bool(true),
-
-                "grid" => Value::Bool(false),
-                "grid/color" => Value::Rgba8(color::BLUE),
-                "grid/spacing" => Value::U32Tuple(8, 8),
-
-                "p/height" => Value::U32(Session::PALETTE_HEIGHT),
-
-                "debug/crosshair" => Value::Bool(false),
-
-                // Deprecated.
-                "frame_delay" => Value::F64(0.0),
-                "input/delay" => Value::F64(8.0)
-            },
+            map: schema
+                .iter()
+                .map(|(k, s)| (k.clone(), s.default.clone()))
+                .collect(),
+            schema,
         }
     }
 }
@@ -641,6 +1107,17 @@ impl std::ops::Index<&str> for Settings {
 
 ///////////////////////////////////////////////////////////////////////////////
 
+/// Session state as it was after loading the built-in configuration.
+/// Used to write out only what the user has changed.
+#[derive(Default)]
+struct Defaults {
+    settings: Settings,
+    key_bindings: KeyBindings,
+    palette: Vec<Rgba8>,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
 /// The user session.
 ///
 /// Stores all relevant session state.
@@ -671,8 +1148,12 @@ pub struct Session {
     pub offset: Vector2<f32>,
     /// The help view offset.
     pub help_offset: Vector2<f32>,
+    /// Lines shown in place of the help, eg. the output of `:maps`.
+    pub listing: Option<Vec<Message>>,
     /// The current message displayed to the user.
     pub message: Message,
+    /// Log of recent messages.
+    pub messages: Messages,
 
     /// The session foreground color.
     pub fg: Rgba8,
@@ -693,14 +1174,36 @@ pub struct Session {
     keys_pressed: HashSet<platform::Key>,
     /// The list of all active key bindings.
     pub key_bindings: KeyBindings,
+    /// Recorded macros.
+    pub macros: Macros,
+    /// Command waiting for the user to type a register name, eg. after `q`.
+    pending_register: Option<Command>,
+    /// Count typed before a key binding, eg. the `5` in `5l`.
+    pub pending_count: Option<usize>,
+    /// Keys typed so far in a key sequence, eg. the first `g` in `g g`.
+    pub pending_keys: Vec<Key>,
+    /// Time elapsed since the last key of a pending key sequence was typed.
+    pending_keys_elapsed: time::Duration,
+    /// Character typed with the first key of a pending key sequence, if any.
+    pending_char: Option<(char, ModifiersState)>,
+    /// The script line being sourced, if any.
+    sourcing: Option<SourceLocation>,
+    /// The view whose settings script is being sourced, if any.
+    sourcing_view: Option<ViewId>,
+    /// Errors encountered while sourcing scripts, with their location.
+    pub source_errors: Vec<String>,
 
     /// Current pixel selection.
     pub selection: Option<Selection>,
 
     /// The session's current settings.
     pub settings: Settings,
+    /// Settings, key bindings and palette after loading the built-in configuration.
+    defaults: Defaults,
     /// Settings recently changed.
     pub settings_changed: HashSet<String>,
+    /// User-defined variables, set with the `let` command.
+    pub variables: HashMap<String, Value>,
 
     /// Views loaded in the session.
     pub views: ViewManager<ViewResource>,
@@ -714,6 +1217,8 @@ pub struct Session {
     pub cmdline: CommandLine,
     /// The color palette.
     pub palette: Palette,
+    /// Color names defined with `color/def`.
+    pub colors: HashMap<String, Rgba8>,
 
     /// Average time it takes for a session update.
     pub avg_time: time::Duration,
@@ -724,6 +1229,10 @@ pub struct Session {
     pub prev_tool: Option<Tool>,
     /// The brush tool settings.
     pub brush: Brush,
+    /// The curve being placed with the curve tool, if any.
+    pub curve: Option<Curve>,
+    /// The line being dragged with the gradient tool, if any.
+    pub gradient: Option<(Point2<i32>, Point2<i32>)>,
 
     /// Input state of the mouse.
     mouse_state: InputState,
@@ -755,6 +1264,10 @@ impl Session {
     const MIN_BRUSH_SIZE: usize = 1;
     /// Maximum frame width or height.
     const MAX_FRAME_SIZE: u32 = 4096;
+    /// Maximum count that can be typed before a key binding.
+    const MAX_COUNT: usize = 9999;
+    /// Maximum number of messages kept in the message log.
+    const MAX_MESSAGES: usize = 256;
     /// Maximum zoom amount as a multiplier.
     const MAX_ZOOM: f32 = 128.0;
     /// Zoom levels used when zooming in/out.
@@ -799,6 +1312,7 @@ impl Session {
             proj_dirs,
             offset: Vector2::zero(),
             help_offset: Vector2::zero(),
+            listing: None,
             tool: Tool::default(),
             prev_tool: Option::default(),
             mouse_state: InputState::Released,
@@ -807,20 +1321,35 @@ impl Session {
             fg: color::WHITE,
             bg: color::BLACK,
             brush: Brush::default(),
+            curve: None,
+            gradient: None,
             settings: Settings::default(),
+            defaults: Defaults::default(),
             settings_changed: HashSet::new(),
+            variables: HashMap::new(),
             views: ViewManager::new(),
             effects: Vec::new(),
             accumulator: time::Duration::from_secs(0),
             palette: Palette::new(Self::PALETTE_CELL_SIZE, Self::PALETTE_HEIGHT as usize),
+            colors: HashMap::new(),
             key_bindings: KeyBindings::default(),
             keys_pressed: HashSet::new(),
             ignore_received_characters: false,
+            macros: Macros::new(),
+            pending_register: None,
+            pending_count: None,
+            pending_keys: Vec::new(),
+            pending_keys_elapsed: time::Duration::from_secs(0),
+            pending_char: None,
+            sourcing: None,
+            sourcing_view: None,
+            source_errors: Vec::new(),
             cmdline: CommandLine::new(cwd, history_path, path::SUPPORTED_READ_FORMATS),
             mode: Mode::Normal,
             prev_mode: Option::default(),
             selection: Option::default(),
             message: Message::default(),
+            messages: Messages::new(Self::MAX_MESSAGES),
             avg_time: time::Duration::from_secs(0),
             frame_number: 0,
             queue: Vec::new(),
@@ -835,14 +1364,14 @@ impl Session {
         if let Some(init) = source {
             // The special source '-' is used to skip initialization.
             if init.as_os_str() != "-" {
-                self.source_path(&init)?;
+                self.source_path(&init, true)?;
             }
         } else {
             let dir = self.proj_dirs.config_dir().to_owned();
             let cfg = dir.join(Self::INIT);
 
             if cfg.exists() {
-                self.source_path(cfg)?;
+                self.source_path(cfg, false)?;
             }
         }
 
@@ -850,6 +1379,16 @@ impl Session {
         self.cmdline.history.load()?;
         self.message(format!("rx v{}", crate::VERSION), MessageType::Debug);
 
+        if !self.source_errors.is_empty() {
+            self.message(
+                format!(
+                    "Error: {} error(s) during initialization, see `:errors`",
+                    self.source_errors.len()
+                ),
+                MessageType::Error,
+            );
+        }
+
         Ok(self)
     }
 
@@ -857,9 +1396,18 @@ impl Session {
     pub fn reset(&mut self) -> io::Result<()> {
         self.key_bindings = KeyBindings::default();
         self.settings = Settings::default();
+        self.variables.clear();
+        self.colors.clear();
         self.tool = Tool::default();
 
-        self.source_reader(io::BufReader::new(data::CONFIG), "<init>")
+        self.source_reader(io::BufReader::new(data::CONFIG), "<init>", true)?;
+        self.defaults = Defaults {
+            settings: self.settings.clone(),
+            key_bindings: self.key_bindings.clone(),
+            palette: self.palette.colors.to_vec(),
+        };
+
+        Ok(())
     }
 
     /// Create a blank view.
@@ -902,8 +1450,9 @@ impl Session {
     ) -> Vec<Effect> {
         self.settings_changed.clear();
         self.avg_time = avg_time;
+        self.brush.background = self.bg;
 
-        if let Tool::Brush = self.tool {
+        if let Tool::Brush | Tool::Rectangle | Tool::Ellipse = self.tool {
             self.brush.update();
         }
 
@@ -919,6 +1468,13 @@ impl Session {
         if self.ignore_received_characters {
             self.ignore_received_characters = false;
         }
+        if !self.pending_keys.is_empty() {
+            self.pending_keys_elapsed += delta;
+
+            if self.key_sequence_timeout().is_none() {
+                self.flush_key_sequence();
+            }
+        }
 
         // TODO: This whole block needs refactoring..
         if let Execution::Replaying {
@@ -1005,42 +1561,65 @@ impl Session {
             }
         }
 
-        if let Tool::Brush = self.tool {
-            let brush = &self.brush;
-            let output = brush.output(
-                Stroke::NONE,
-                Fill::Solid(brush.color.into()),
-                1.0,
-                Align::BottomLeft,
-            );
-            if !output.is_empty() {
-                match brush.state {
-                    // If we're erasing, we can't use the staging framebuffer, since we
-                    // need to be replacing pixels on the real buffer.
-                    _ if brush.is_set(BrushMode::Erase) => {
-                        self.effects.extend_from_slice(&[
-                            Effect::ViewBlendingChanged(Blending::Constant),
-                            Effect::ViewPaintFinal(output),
-                        ]);
-                    }
-                    // As long as we haven't finished drawing, render into the staging buffer.
-                    BrushState::DrawStarted(_) | BrushState::Drawing(_) => {
-                        self.effects.push(Effect::ViewPaintDraft(output));
-                    }
-                    // Once we're done drawing, we can render into the real buffer.
-                    BrushState::DrawEnded(_) => {
-                        self.effects.extend_from_slice(&[
-                            Effect::ViewBlendingChanged(Blending::Alpha),
-                            Effect::ViewPaintFinal(output),
-                        ]);
+        if let Tool::Brush | Tool::Rectangle | Tool::Ellipse = self.tool {
+            if let Some(step) = self.brush.shading() {
+                self.paint_shading(step);
+            } else {
+                let brush = &self.brush;
+                let output = brush.output(
+                    Stroke::NONE,
+                    Fill::Solid(brush.color.into()),
+                    1.0,
+                    Align::BottomLeft,
+                );
+                if !output.is_empty() {
+                    match brush.state {
+                        // Figures are redrawn from scratch as the brush moves, so when
+                        // erasing, we can only erase once the figure is complete.
+                        BrushState::DrawStarted(_) | BrushState::Drawing(_)
+                            if brush.is_set(BrushMode::Erase) && brush.figure().is_some() => {}
+                        // If we're erasing, we can't use the staging framebuffer, since we
+                        // need to be replacing pixels on the real buffer.
+                        _ if brush.is_set(BrushMode::Erase) => {
+                            self.effects.extend_from_slice(&[
+                                Effect::ViewBlendingChanged(Blending::Constant),
+                                Effect::ViewPaintFinal(output),
+                            ]);
+                        }
+                        // As long as we haven't finished drawing, render into the staging buffer.
+                        BrushState::DrawStarted(_) | BrushState::Drawing(_) => {
+                            self.effects.push(Effect::ViewPaintDraft(output));
+                        }
+                        // Once we're done drawing, we can render into the real buffer.
+                        BrushState::DrawEnded(_) => {
+                            self.effects.extend_from_slice(&[
+                                Effect::ViewBlendingChanged(Blending::Alpha),
+                                Effect::ViewPaintFinal(output),
+                            ]);
+                        }
+                        // If the brush output isn't empty, we can't possibly not
+                        // be drawing!
+                        BrushState::NotDrawing => unreachable!(),
                     }
-                    // If the brush output isn't empty, we can't possibly not
-                    // be drawing!
-                    BrushState::NotDrawing => unreachable!(),
                 }
             }
         }
 
+        if let (Tool::Curve, Some(curve)) = (&self.tool, &self.curve) {
+            // Like figures, curves can only be erased once they are complete.
+            if !self.brush.is_set(BrushMode::Erase) {
+                let shapes = self.brush.shapes(
+                    &curve.points(),
+                    self.active_view().extent(),
+                    Stroke::NONE,
+                    Fill::Solid(self.fg.into()),
+                    1.0,
+                    Align::BottomLeft,
+                );
+                self.effects.push(Effect::ViewPaintDraft(shapes));
+            }
+        }
+
         if self.views.is_empty() {
             self.quit(ExitReason::Normal);
         } else {
@@ -1120,13 +1699,26 @@ impl Session {
         .floor()
     }
 
+    /// Lookup a setting, giving precedence to the active view's settings.
+    pub fn setting(&self, name: &str) -> &Value {
+        self.view_setting(self.views.active_id, name)
+    }
+
+    /// Lookup a setting for the given view, falling back to the session settings.
+    pub fn view_setting(&self, id: ViewId, name: &str) -> &Value {
+        self.views
+            .get(id)
+            .and_then(|v| v.settings.get(name))
+            .unwrap_or_else(|| &self.settings[name])
+    }
+
     /// Get the current animation delay. Returns `None` if animations aren't playing,
     /// or if none of the views have more than one frame.
     pub fn animation_delay(&self) -> Option<time::Duration> {
         let animations = self.views.iter().any(|v| v.animation.len() > 1);
 
         if self.settings["animation"].is_set() && animations {
-            let delay = self.settings["animation/delay"].to_u64();
+            let delay = self.setting("animation/delay").to_u64();
             Some(time::Duration::from_millis(delay))
         } else {
             None
@@ -1147,6 +1739,279 @@ impl Session {
             .collect()
     }
 
+    /// Resolve a color argument against the palette, the color names defined
+    /// in this session, and the CSS color names.
+    fn resolve_color(&self, color: &ColorRef) -> Result<Rgba8, Error> {
+        match color {
+            ColorRef::Color(c) => Ok(*c),
+            ColorRef::Palette(i) => self.palette.colors.get(*i).copied().ok_or_else(|| {
+                format!(
+                    "palette index {} is out of range, the palette has {} color(s)",
+                    i,
+                    self.palette.size()
+                )
+            }),
+            ColorRef::Named(name) => self
+                .colors
+                .get(name)
+                .copied()
+                .or_else(|| color::named(name))
+                .ok_or_else(|| format!("unknown color `{}`", name)),
+        }
+    }
+
+    /// Resolve the `<leader>` key in a key sequence, and check that the
+    /// sequence is made up of keys. Returns the final input and the keys
+    /// preceding it.
+    fn resolve_keys(&self, keys: KeySequence) -> Result<(Input, Vec<Key>), Error> {
+        let leader = match &self.settings["leader"] {
+            Value::Str(s) => param::<platform::Key>().parse(s).ok(),
+            _ => None,
+        };
+        let resolve = |i: Input| match (i, leader) {
+            (Input::Leader, Some((key, ""))) => Ok(Input::Key(key)),
+            (Input::Leader, _) => Err(format!(
+                "Error: invalid leader key `{}`",
+                self.settings["leader"]
+            )),
+            (other, _) => Ok(other),
+        };
+        let prefix = keys
+            .prefix
+            .into_iter()
+            .map(|i| match resolve(i)? {
+                Input::Key(k) => Ok(k),
+                other => Err(format!(
+                    "Error: `{}` can't be used in a key sequence",
+                    other
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((resolve(keys.input)?, prefix))
+    }
+
+    /// Generate a script which restores the current settings, key bindings and
+    /// palette. Only what differs from the defaults is included.
+    pub fn config(&self) -> Vec<String> {
+        // Split modes into groups that `map` and `unmap` can express.
+        fn mode_groups(modes: &[Mode]) -> Vec<Vec<Mode>> {
+            let all = [
+                Mode::Normal,
+                Mode::Visual(VisualState::selecting()),
+                Mode::Visual(VisualState::Pasting),
+            ];
+            if modes == all {
+                return vec![modes.to_vec()];
+            }
+            let (visual, other): (Vec<Mode>, Vec<Mode>) =
+                modes.iter().partition(|m| matches!(m, Mode::Visual(_)));
+            let mut groups: Vec<Vec<Mode>> = other.into_iter().map(|m| vec![m]).collect();
+
+            if !visual.is_empty() {
+                groups.push(visual);
+            }
+            groups
+        }
+
+        let defaults = &self.defaults;
+        let mut cmds = Vec::new();
+
+        let mut colors: Vec<_> = self.colors.iter().collect();
+        colors.sort();
+        cmds.extend(
+            colors
+                .into_iter()
+                .map(|(name, rgba)| Command::ColorDef(name.clone(), *rgba)),
+        );
+
+        for name in self.settings.names() {
+            let value = &self.settings[name];
+
+            if defaults.settings.get(name) != Some(value) {
+                cmds.push(Command::Set(name.to_owned(), value.clone()));
+            }
+        }
+
+        // Default key bindings that were removed.
+        for kb in defaults
+            .key_bindings
+            .iter()
+            .filter(|kb| kb.state == InputState::Pressed)
+        {
+            for modes in mode_groups(&kb.modes) {
+                let removed = modes.iter().all(|m| {
+                    !self
+                        .key_bindings
+                        .iter()
+                        .any(|other| other.same_keys(kb) && other.modes.contains(m))
+                });
+                if removed {
+                    cmds.push(Command::Unmap(kb.keys(), modes));
+                }
+            }
+        }
+
+        // Key bindings that were added or changed.
+        for kb in self
+            .key_bindings
+            .iter()
+            .filter(|kb| kb.state == InputState::Pressed)
+        {
+            let is_default = defaults.key_bindings.iter().any(|other| {
+                other.same_keys(kb) && other.modes == kb.modes && other.command == kb.command
+            });
+            if is_default {
+                continue;
+            }
+            for modes in mode_groups(&kb.modes) {
+                let release = self
+                    .key_bindings
+                    .iter()
+                    .find(|other| {
+                        other.state == InputState::Released
+                            && other.input == kb.input
+                            && other.modifiers == kb.modifiers
+                            && modes.iter().all(|m| other.modes.contains(m))
+                    })
+                    .filter(|_| kb.is_toggle)
+                    .map(|other| other.command.clone());
+                let keys = kb.keys();
+
+                cmds.push(Command::Map(Box::new(KeyMapping {
+                    input: keys.input,
+                    prefix: keys.prefix,
+                    modifiers: keys.modifiers,
+                    press: kb.command.clone(),
+                    release,
+                    modes,
+                })));
+            }
+        }
+
+        if self.palette.colors[..] != defaults.palette[..] {
+            cmds.push(Command::PaletteClear);
+            cmds.extend(
+                self.palette
+                    .colors
+                    .iter()
+                    .map(|c| Command::PaletteAdd((*c).into())),
+            );
+        }
+        for ramp in &self.palette.ramps {
+            cmds.push(Command::PaletteRamp(
+                ramp.iter().map(|c| ColorRef::Color(*c)).collect(),
+            ));
+        }
+
+        std::iter::once(format!(
+            "{} rx v{} configuration, generated with `:mkconfig`",
+            cmd::COMMENT.to_string().repeat(2),
+            crate::VERSION
+        ))
+        .chain(cmds.into_iter().map(String::from))
+        .collect()
+    }
+
+    /// Write the output of [`Session::config`] to a file.
+    fn write_config<P: AsRef<Path>>(&self, path: P, force: bool) -> io::Result<()> {
+        let path = path.as_ref();
+
+        if path.exists() && !force {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "`{}` already exists, use `:mkconfig!` to overwrite it",
+                    path.display()
+                ),
+            ));
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut f = File::create(path)?;
+
+        for line in self.config() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// List the key bindings active in the given mode, starting with the given
+    /// keys, one per line.
+    fn key_bindings_listing(
+        &self,
+        mode: Option<Mode>,
+        keys: Option<(ModifiersState, Input, Vec<Key>)>,
+    ) -> Vec<String> {
+        let matches = |kb: &KeyBinding| {
+            if let Some(mode) = mode {
+                if !kb.modes.contains(&mode) {
+                    return false;
+                }
+            }
+            if let Some((mods, input, prefix)) = &keys {
+                let expected: Vec<Input> = prefix
+                    .iter()
+                    .map(|k| Input::Key(*k))
+                    .chain(std::iter::once(*input))
+                    .collect();
+                let actual: Vec<Input> = kb
+                    .prefix
+                    .iter()
+                    .map(|k| Input::Key(*k))
+                    .chain(std::iter::once(kb.input))
+                    .collect();
+
+                if !actual.starts_with(&expected) {
+                    return false;
+                }
+                if *mods != ModifiersState::default()
+                    && (actual.len() != expected.len() || kb.modifiers != *mods)
+                {
+                    return false;
+                }
+            }
+            true
+        };
+
+        self.key_bindings
+            .iter()
+            .filter(|kb| kb.state == InputState::Pressed && matches(kb))
+            .map(|kb| {
+                let mut modes: Vec<&str> = Vec::new();
+                for m in kb.modes.iter() {
+                    let name = match m {
+                        Mode::Visual(_) => "visual",
+                        Mode::Normal => "normal",
+                        Mode::Command => "command",
+                        Mode::Present => "present",
+                        Mode::Help => "help",
+                    };
+                    if !modes.contains(&name) {
+                        modes.push(name);
+                    }
+                }
+                let keys = kb
+                    .prefix
+                    .iter()
+                    .map(|k| k.to_string())
+                    .chain(std::iter::once(format!("{}{}", kb.modifiers, kb.input)))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let source = kb.source.as_ref().map_or(String::new(), |s| s.to_string());
+
+                format!(
+                    "{:<16} {:<20} {:<48} {}",
+                    modes.join(","),
+                    keys,
+                    kb.command.to_string(),
+                    source
+                )
+            })
+            .collect()
+    }
+
     ////////////////////////////////////////////////////////////////////////////
 
     /// Pan the view by a relative amount.
@@ -1187,7 +2052,9 @@ impl Session {
         let gained_palette_focus = !palette_hover && self.palette.hover.is_some();
 
         match &self.tool {
-            Tool::Brush if !self.brush.is_drawing() => {
+            Tool::Brush | Tool::Rectangle | Tool::Ellipse | Tool::Curve
+                if !self.brush.is_drawing() =>
+            {
                 if gained_palette_focus {
                     self.tool(Tool::Sampler);
                 }
@@ -1228,22 +2095,42 @@ impl Session {
 
         self.settings_changed.insert(name.to_owned());
 
-        match name {
-            "p/height" => {
-                self.palette.height = new.to_u64() as usize;
-                self.center_palette();
-            }
-            "scale" => {
-                // TODO: We need to recompute the cursor position here
-                // from the window coordinates. Currently, cursor position
-                // is stored only in `SessionCoords`, which would have
-                // to change.
-                self.rescale(old.to_f64(), new.to_f64());
-            }
-            _ => {}
+        if let Some(hook) = self.settings.schema(name).and_then(|s| s.on_change) {
+            hook(self, old, new);
+        }
+    }
+
+    fn palette_height_changed(&mut self, _old: &Value, new: &Value) {
+        self.palette.height = new.to_u64() as usize;
+        self.center_palette();
+    }
+
+    fn curve_kind_changed(&mut self, _old: &Value, _new: &Value) {
+        let kind = self.curve_kind();
+
+        if let Some(curve) = &mut self.curve {
+            curve.kind = kind;
         }
     }
 
+    fn brush_shape_changed(&mut self, _old: &Value, new: &Value) {
+        self.brush.tip = match new.to_string().as_str() {
+            "circle" => Tip::Circle,
+            "diamond" => Tip::Diamond,
+            "hline" => Tip::HLine,
+            "vline" => Tip::VLine,
+            _ => Tip::Square,
+        };
+    }
+
+    fn scale_changed(&mut self, old: &Value, new: &Value) {
+        // TODO: We need to recompute the cursor position here
+        // from the window coordinates. Currently, cursor position
+        // is stored only in `SessionCoords`, which would have
+        // to change.
+        self.rescale(old.to_f64(), new.to_f64());
+    }
+
     /// Toggle the session mode.
     fn toggle_mode(&mut self, mode: Mode) {
         if self.mode == mode {
@@ -1264,6 +2151,9 @@ impl Session {
             Mode::Command => {
                 self.cmdline.clear();
             }
+            Mode::Help => {
+                self.listing = None;
+            }
             _ => {}
         }
 
@@ -1284,6 +2174,9 @@ impl Session {
         }
 
         self.release_inputs();
+        self.pending_count = None;
+        self.pending_keys.clear();
+        self.pending_char = None;
         self.prev_mode = Some(self.mode);
         self.mode = new;
     }
@@ -1312,8 +2205,32 @@ impl Session {
 
     /// Display a message to the user. Also logs.
     pub fn message<D: fmt::Display>(&mut self, msg: D, t: MessageType) {
-        self.message = Message::new(msg, t);
+        self.message = match (&self.sourcing, t) {
+            // Errors while sourcing a script are reported with their location.
+            (Some(loc), MessageType::Error) => {
+                let msg = msg.to_string();
+                let msg = format!("{}: {}", loc, msg.trim_start_matches("Error: "));
+
+                self.source_errors.push(msg.clone());
+
+                Message::new(msg, t)
+            }
+            _ => Message::new(msg, t),
+        };
         self.message.log();
+
+        match t {
+            MessageType::Hint | MessageType::Execution | MessageType::Debug => {}
+            _ if self.message.string.is_empty() => {}
+            _ => self.messages.push(self.message.clone()),
+        }
+    }
+
+    /// Show lines of text in place of the help.
+    fn show_listing(&mut self, lines: Vec<Message>) {
+        self.switch_mode(Mode::Help);
+        self.help_offset = Vector2::zero();
+        self.listing = Some(lines);
     }
 
     fn message_clear(&mut self) {
@@ -1381,6 +2298,8 @@ impl Session {
         if self.views.active_id == id {
             return;
         }
+        // A curve belongs to the view it was placed in.
+        self.curve = None;
         self.views.activate(id);
         self.effects.push(Effect::ViewActivated(id));
     }
@@ -1511,6 +2430,10 @@ impl Session {
                     if path.file_name() == Some(OsStr::new(".rxrc")) {
                         continue;
                     }
+                    // Skip view settings scripts, eg. `sprite.png.rx`.
+                    if path.extension() == Some(OsStr::new("rx")) {
+                        continue;
+                    }
 
                     if self.load_view(path).is_err() {
                         fail_count += 1;
@@ -1660,7 +2583,8 @@ impl Session {
             "gif" => {
                 let palette = self.colors();
                 let view = self.view(id);
-                let delay = time::Duration::from_millis(self.settings["animation/delay"].to_u64());
+                let delay =
+                    time::Duration::from_millis(self.view_setting(id, "animation/delay").to_u64());
 
                 view.save_gif(layer_id, &path, delay, &palette, scale)?
             }
@@ -1702,7 +2626,7 @@ impl Session {
             view::Format::Png => {
                 let (width, height, pixels) = crate::io::load_image(&*path)?;
 
-                self.add_view(
+                let id = self.add_view(
                     FileStatus::Saved(FileStorage::Single((*path).into())),
                     width,
                     height,
@@ -1712,6 +2636,7 @@ impl Session {
                     format!("\"{}\" {} pixels read", path.display(), width * height),
                     MessageType::Info,
                 );
+                self.source_view_settings(id, &path)?;
             }
             view::Format::Archive => {
                 let archive = crate::io::load_archive(&*path)?;
@@ -1735,6 +2660,8 @@ impl Session {
                     );
                     self.view_mut(view_id).add_layer(Some(pixels));
                 }
+                self.view_mut(view_id).script = archive.manifest.settings;
+                self.source_view_settings(view_id, &path)?;
             }
             view::Format::Gif => {
                 return Err(io::Error::new(
@@ -1779,6 +2706,9 @@ impl Session {
     fn destroy_view(&mut self, id: ViewId) {
         assert!(!self.views.is_empty());
 
+        if self.is_active(id) {
+            self.curve = None;
+        }
         self.views.remove(id);
         self.effects.push(Effect::ViewRemoved(id));
     }
@@ -1981,9 +2911,28 @@ impl Session {
     }
 
     fn handle_mouse_input(&mut self, button: platform::MouseButton, state: platform::InputState) {
-        if button != platform::MouseButton::Left {
-            return;
+        if let Some(kb) =
+            self.key_bindings
+                .find(Input::Mouse(button), self.modifiers(), state, self.mode)
+        {
+            // Bindings with a release command act as the primary button while
+            // held, eg. `:swap {:swap}` paints with the background color.
+            if state == InputState::Pressed {
+                self.user_command(kb.command);
+
+                if kb.is_toggle {
+                    self.handle_primary_mouse_input(state);
+                }
+            } else {
+                self.handle_primary_mouse_input(state);
+                self.user_command(kb.command);
+            }
+        } else if button == platform::MouseButton::Left {
+            self.handle_primary_mouse_input(state);
         }
+    }
+
+    fn handle_primary_mouse_input(&mut self, state: platform::InputState) {
         self.mouse_state = state;
 
         // Pan tool.
@@ -2041,13 +2990,45 @@ impl Session {
                                     };
                                     self.brush.start_drawing(p.into(), color, extent);
                                 }
+                                Tool::Rectangle | Tool::Ellipse => {
+                                    let color = if self.brush.is_set(BrushMode::Erase) {
+                                        Rgba8::TRANSPARENT
+                                    } else {
+                                        self.fg
+                                    };
+                                    if let Some(figure) = self.figure() {
+                                        self.brush.start_figure(figure, p.into(), color, extent);
+                                    }
+                                }
+                                Tool::Curve => {
+                                    let kind = self.curve_kind();
+                                    let p: LayerCoords<i32> = p.into();
+                                    let curve = self.curve.get_or_insert_with(|| Curve::new(kind));
+
+                                    curve.dragging = curve.point_at(*p).or_else(|| {
+                                        curve.points.push(*p);
+                                        Some(curve.points.len() - 1)
+                                    });
+                                }
+                                Tool::Gradient => {
+                                    let p: LayerCoords<i32> = p.into();
+                                    self.gradient = Some((*p, *p));
+                                }
                                 Tool::Sampler => {
                                     self.sample_color();
                                 }
                                 Tool::Pan(_) => {}
                                 Tool::FloodFill => {
                                     let start_time = time::Instant::now();
-                                    let filler = FloodFiller::new(self.active_view(), p, self.fg);
+                                    let extent = self.active_view().extent();
+                                    let all_frames = self.settings["flood/all-frames"].is_set();
+                                    let filler = self.flood_filler(p, self.fg).map(|f| {
+                                        let f = if all_frames { f.all_frames(extent) } else { f };
+                                        match self.fill_dither() {
+                                            Some(dither) => f.dither(dither, self.bg),
+                                            None => f,
+                                        }
+                                    });
                                     if let Some(shapes) = filler.and_then(|f| f.run()) {
                                         self.effects.push(Effect::ViewPaintFinal(shapes));
                                         self.active_view_mut().touch_layer();
@@ -2058,6 +3039,15 @@ impl Session {
                             Mode::Command => {
                                 // TODO
                             }
+                            Mode::Visual(VisualState::Selecting { .. })
+                                if self.tool == Tool::Gradient
+                                    && matches!(self.selection, Some(s)
+                                        if s.abs().bounds().contains(p.map(|n| n as i32))) =>
+                            {
+                                // Drag a gradient across the selection.
+                                let p: LayerCoords<i32> = p.into();
+                                self.gradient = Some((*p, *p));
+                            }
                             Mode::Visual(VisualState::Selecting { ref mut dragging }) => {
                                 let p = p.map(|n| n as i32);
                                 let unit = Selection::new(p.x, p.y, p.x + 1, p.y + 1);
@@ -2094,28 +3084,58 @@ impl Session {
                     }
                 }
             }
-            InputState::Released => match self.mode {
-                Mode::Visual(VisualState::Selecting { ref mut dragging }) => {
-                    *dragging = false;
+            InputState::Released => {
+                if let Some((from, to)) = self.gradient.take() {
+                    self.command(Command::PaintGradient(from.x, from.y, to.x, to.y));
                 }
-                Mode::Normal => {
-                    if let Tool::Brush = self.tool {
-                        match self.brush.state {
-                            BrushState::Drawing { .. } | BrushState::DrawStarted { .. } => {
-                                self.brush.stop_drawing();
-                                self.active_view_mut().touch_layer();
+                match self.mode {
+                    Mode::Visual(VisualState::Selecting { ref mut dragging }) => {
+                        *dragging = false;
+                    }
+                    Mode::Normal => {
+                        if let Tool::Brush | Tool::Rectangle | Tool::Ellipse = self.tool {
+                            match self.brush.state {
+                                BrushState::Drawing { .. } | BrushState::DrawStarted { .. } => {
+                                    self.brush.stop_drawing();
+                                    self.active_view_mut().touch_layer();
+                                }
+                                _ => {}
                             }
-                            _ => {}
+                        } else if let Some(curve) = &mut self.curve {
+                            curve.dragging = None;
                         }
                     }
+                    _ => {}
                 }
-                _ => {}
-            },
+            }
             InputState::Repeated => {}
         }
     }
 
     fn handle_mouse_wheel(&mut self, delta: platform::LogicalDelta) {
+        let wheel = if delta.y > 0. {
+            Some(Wheel::Up)
+        } else if delta.y < 0. {
+            Some(Wheel::Down)
+        } else if delta.x > 0. {
+            Some(Wheel::Right)
+        } else if delta.x < 0. {
+            Some(Wheel::Left)
+        } else {
+            None
+        };
+        if let Some(kb) = wheel.and_then(|w| {
+            self.key_bindings.find(
+                Input::Wheel(w),
+                self.modifiers(),
+                InputState::Pressed,
+                self.mode,
+            )
+        }) {
+            self.user_command(kb.command);
+            return;
+        }
+
         if delta.y > 0. {
             if let Some((v, _)) = self.hover_view {
                 self.activate(v);
@@ -2146,25 +3166,43 @@ impl Session {
             Tool::Sampler if self.mouse_state == InputState::Pressed => {
                 self.sample_color();
             }
+            Tool::Gradient if self.gradient.is_some() => {
+                if let Some((_, to)) = &mut self.gradient {
+                    *to = *LayerCoords::<i32>::from(p);
+                }
+            }
             _ => {
                 match self.mode {
                     Mode::Normal => match self.tool {
-                        Tool::Brush if p != prev_p => match self.brush.state {
-                            BrushState::DrawStarted { .. } | BrushState::Drawing { .. } => {
-                                let mut p: LayerCoords<i32> = p.into();
-                                let brush = &mut self.brush;
-                                if brush.is_set(BrushMode::Multi) {
-                                    p.clamp(Rect::new(
-                                        (brush.size / 2) as i32,
-                                        (brush.size / 2) as i32,
-                                        vw as i32 - (brush.size / 2) as i32 - 1,
-                                        vh as i32 - (brush.size / 2) as i32 - 1,
-                                    ));
+                        Tool::Brush | Tool::Rectangle | Tool::Ellipse if p != prev_p => {
+                            match self.brush.state {
+                                BrushState::DrawStarted { .. } | BrushState::Drawing { .. } => {
+                                    let mut p: LayerCoords<i32> = p.into();
+                                    let constrain = self.modifiers().alt;
+                                    let brush = &mut self.brush;
+                                    if brush.is_set(BrushMode::Multi) {
+                                        p.clamp(Rect::new(
+                                            (brush.size / 2) as i32,
+                                            (brush.size / 2) as i32,
+                                            vw as i32 - (brush.size / 2) as i32 - 1,
+                                            vh as i32 - (brush.size / 2) as i32 - 1,
+                                        ));
+                                    }
+                                    if constrain && brush.figure().is_some() {
+                                        p = brush.constrain(p);
+                                    }
+                                    brush.draw(p);
                                 }
-                                brush.draw(p);
+                                _ => self.activate_hover_layer(),
                             }
-                            _ => self.activate_hover_layer(),
-                        },
+                        }
+                        Tool::Curve if p != prev_p => {
+                            if let Some(curve) = &mut self.curve {
+                                if let Some(i) = curve.dragging {
+                                    curve.points[i] = *LayerCoords::<i32>::from(p);
+                                }
+                            }
+                        }
                         _ => {}
                     },
                     Mode::Visual(VisualState::Selecting { dragging: false }) => {
@@ -2209,15 +3247,50 @@ impl Session {
 
     fn handle_received_character(&mut self, c: char, mods: ModifiersState) {
         if self.mode == Mode::Command {
-            if c.is_control() || self.ignore_received_characters {
+            // Characters typed with <ctrl> are handled as line editing keys.
+            if c.is_control() || self.ignore_received_characters || (mods.ctrl && !mods.alt) {
+                return;
+            }
+            if self.cmdline.search.is_some() {
+                self.cmdline.search_putc(c);
                 return;
             }
             self.cmdline_handle_input(c);
+        } else if self.pending_register.is_some() {
+            if c.is_control() || self.ignore_received_characters {
+                return;
+            }
+            if let Some(cmd) = self.pending_register.take() {
+                if !Macros::is_register(c) {
+                    self.message(
+                        format!("Error: invalid register `{}`", c),
+                        MessageType::Error,
+                    );
+                    return;
+                }
+                self.user_command(match cmd {
+                    Command::MacroRecord(_) => Command::MacroRecord(Some(c)),
+                    Command::MacroPlay(_, n) => Command::MacroPlay(Some(c), n),
+                    other => other,
+                });
+            }
+        } else if self.ignore_received_characters {
+            // The character of a key which started a sequence is kept, in case
+            // the sequence ends there and the character is bound on its own.
+            if self.pending_keys.len() == 1 && self.pending_char.is_none() {
+                self.pending_char = Some((c, mods));
+            }
+        } else if let Some(d) = c
+            .to_digit(10)
+            .filter(|d| self.pending_count.is_some() || (*d != 0 && !self.is_bound(c, mods)))
+        {
+            let n = self.pending_count.unwrap_or(0) * 10 + d as usize;
+            self.pending_count = Some(usize::min(n, Self::MAX_COUNT));
         } else if let Some(kb) =
             self.key_bindings
                 .find(Input::Character(c), mods, InputState::Pressed, self.mode)
         {
-            self.command(kb.command);
+            self.counted_command(kb.command);
         }
     }
 
@@ -2246,6 +3319,27 @@ impl Session {
                 }
             }
 
+            // While waiting for a register name, key presses are only handled
+            // as character input.
+            if self.pending_register.is_some() && state == InputState::Pressed {
+                if key == platform::Key::Escape {
+                    self.pending_register = None;
+                }
+                return;
+            }
+
+            // While typing a count, digit keys only extend the count, and
+            // escape cancels it.
+            if self.pending_count.is_some() && state == InputState::Pressed {
+                if key == platform::Key::Escape {
+                    self.pending_count = None;
+                    return;
+                }
+                if ('0'..='9').any(|c| platform::Key::from(c) == key) {
+                    return;
+                }
+            }
+
             match self.mode {
                 Mode::Visual(VisualState::Selecting { .. }) => {
                     if key == platform::Key::Escape && state == InputState::Pressed {
@@ -2260,6 +3354,29 @@ impl Session {
                     }
                 }
                 Mode::Command => {
+                    if state == InputState::Pressed && self.cmdline.search.is_some() {
+                        match key {
+                            platform::Key::R if modifiers.ctrl => {
+                                self.cmdline.search_start();
+                                return;
+                            }
+                            platform::Key::Backspace => {
+                                self.cmdline.search_delc();
+                                return;
+                            }
+                            platform::Key::Escape => {
+                                self.cmdline.search_cancel();
+                                return;
+                            }
+                            k if k.is_modifier() => {
+                                return;
+                            }
+                            // Any other key ends the search, and is then handled as usual.
+                            _ => {
+                                self.cmdline.search_accept();
+                            }
+                        }
+                    }
                     if state == InputState::Pressed {
                         match key {
                             platform::Key::Up => {
@@ -2274,8 +3391,35 @@ impl Session {
                             platform::Key::Right => {
                                 self.cmdline.cursor_forward();
                             }
+                            platform::Key::Home => {
+                                self.cmdline.cursor_home();
+                            }
+                            platform::Key::End => {
+                                self.cmdline.cursor_end();
+                            }
+                            platform::Key::A if modifiers.ctrl => {
+                                self.cmdline.cursor_home();
+                            }
+                            platform::Key::E if modifiers.ctrl => {
+                                self.cmdline.cursor_end();
+                            }
+                            platform::Key::W if modifiers.ctrl => {
+                                self.cmdline.delw();
+                            }
+                            platform::Key::U if modifiers.ctrl => {
+                                self.cmdline.kill();
+                            }
+                            platform::Key::R if modifiers.ctrl => {
+                                self.cmdline.search_start();
+                            }
                             platform::Key::Tab => {
+                                self.cmdline.autocomplete.completer_mut().colors =
+                                    self.palette.colors.to_vec();
                                 self.cmdline.completion_next();
+
+                                if let Some(help) = self.cmdline.help() {
+                                    self.message(help, MessageType::Hint);
+                                }
                             }
                             platform::Key::Backspace => {
                                 self.cmdline_handle_backspace();
@@ -2297,9 +3441,48 @@ impl Session {
                         return;
                     }
                 }
+                // While placing a curve, <return> paints it, <escape> discards it,
+                // and <backspace> removes the last point placed.
+                Mode::Normal
+                    if self.tool == Tool::Curve
+                        && self.curve.is_some()
+                        && state == InputState::Pressed =>
+                {
+                    match key {
+                        platform::Key::Return => {
+                            self.command(Command::CurveCommit);
+                            return;
+                        }
+                        platform::Key::Escape => {
+                            self.command(Command::CurveCancel);
+                            return;
+                        }
+                        platform::Key::Backspace => {
+                            if let Some(curve) = &mut self.curve {
+                                curve.points.pop();
+                                curve.dragging = None;
+
+                                if curve.points.is_empty() {
+                                    self.curve = None;
+                                }
+                            }
+                            return;
+                        }
+                        _ => {}
+                    }
+                }
                 _ => {}
             }
 
+            if state == InputState::Pressed && !key.is_modifier() {
+                if repeat && !self.pending_keys.is_empty() {
+                    return;
+                }
+                if !repeat && self.handle_key_sequence(key) {
+                    return;
+                }
+            }
+
             if let Some(kb) = self
                 .key_bindings
                 .find(Input::Key(key), modifiers, state, self.mode)
@@ -2308,7 +3491,13 @@ impl Session {
                 // on key repeats. For regular key bindings, we run the command
                 // depending on if it's supposed to repeat.
                 if (repeat && kb.command.repeats() && !kb.is_toggle) || !repeat {
-                    self.command(kb.command);
+                    // Modifier keys are often held while typing the key a count
+                    // applies to, so they don't consume it.
+                    if state == InputState::Pressed && !key.is_modifier() {
+                        self.counted_command(kb.command);
+                    } else {
+                        self.user_command(kb.command);
+                    }
                 }
                 return;
             }
@@ -2328,14 +3517,15 @@ impl Session {
     ///////////////////////////////////////////////////////////////////////////
 
     /// Source an rx script at the given path. Returns an error if the path
-    /// does not exist or the script couldn't be sourced.
-    fn source_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+    /// does not exist or the script couldn't be read. If `strict` is set,
+    /// sourcing stops at the first error, which is returned.
+    fn source_path<P: AsRef<Path>>(&mut self, path: P, strict: bool) -> io::Result<()> {
         let path = path.as_ref();
         debug!("source: {}", path.display());
 
         File::open(&path)
             .or_else(|_| File::open(self.proj_dirs.config_dir().join(path)))
-            .and_then(|f| self.source_reader(io::BufReader::new(f), path))
+            .and_then(|f| self.source_reader(io::BufReader::new(f), path, strict))
             .map_err(|e| {
                 io::Error::new(
                     e.kind(),
@@ -2347,26 +3537,112 @@ impl Session {
     /// Source a directory which contains a `.rxrc` script. Returns an
     /// error if the script wasn't found or couldn't be sourced.
     fn source_dir<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
-        self.source_path(dir.as_ref().join(".rxrc"))
+        self.source_path(dir.as_ref().join(".rxrc"), false)
+    }
+
+    /// Source a view's settings script. Since these scripts come with the
+    /// files being edited, they may only `set` settings for the given view, or
+    /// `slice` it. Any other command is rejected with an error.
+    fn source_view<P: AsRef<Path>, R: io::BufRead>(
+        &mut self,
+        id: ViewId,
+        r: R,
+        path: P,
+    ) -> io::Result<()> {
+        self.activate(id);
+
+        let parent = self.sourcing_view.replace(id);
+        let result = self.source_reader(r, path, false);
+
+        self.sourcing_view = parent;
+
+        result
+    }
+
+    /// Source the settings stored with a view's file: the script in the
+    /// archive manifest, if any, followed by the `<file>.rx` sidecar script.
+    fn source_view_settings(&mut self, id: ViewId, path: &Path) -> io::Result<()> {
+        if let Some(script) = self.view(id).script.clone() {
+            self.source_view(id, script.as_bytes(), path)?;
+        }
+
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(".rx");
+        let sidecar = PathBuf::from(sidecar);
+
+        if sidecar.exists() {
+            let f = File::open(&sidecar)?;
+            self.source_view(id, io::BufReader::new(f), &sidecar)?;
+        }
+        Ok(())
+    }
+
+    /// Set a setting for a single view.
+    fn set_view_setting(&mut self, id: ViewId, k: &str, v: Value) -> Result<(), Error> {
+        let v = self.settings.coerce(k, v);
+        if !self.settings.check(k, &v)?.per_view {
+            return Err(format!("`{}` can't be set per view", k));
+        }
+        self.view_mut(id).settings.insert(k.to_owned(), v);
+
+        Ok(())
     }
 
     /// Source a script from an [`io::BufRead`].
-    fn source_reader<P: AsRef<Path>, R: io::BufRead>(&mut self, r: R, _path: P) -> io::Result<()> {
+    fn source_reader<P: AsRef<Path>, R: io::BufRead>(
+        &mut self,
+        r: R,
+        path: P,
+        strict: bool,
+    ) -> io::Result<()> {
+        let path = path.as_ref().display().to_string();
+        let parent = self.sourcing.take();
+        let result = self.source_lines(r, path, strict);
+
+        self.sourcing = parent;
+
+        result
+    }
+
+    /// Source the lines of a script, keeping track of the current line.
+    /// Errors are collected in `source_errors`.
+    fn source_lines<R: io::BufRead>(&mut self, r: R, path: String, strict: bool) -> io::Result<()> {
         for (i, line) in r.lines().enumerate() {
             let line = line?;
 
             if line.starts_with(cmd::COMMENT) {
                 continue;
             }
-            match self.cmdline.parse(&format!(":{}", line)) {
-                Err(e) => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("{} on line {}", e, i + 1),
-                    ))
+            self.sourcing = Some(SourceLocation {
+                path: path.clone(),
+                line: i + 1,
+            });
+            let errors = self.source_errors.len();
+
+            match self.expand(&line).and_then(|line| {
+                self.cmdline
+                    .parse(&format!(":{}", line))
+                    .map_err(|e| e.to_string())
+            }) {
+                Err(e) => self.message(format!("Error: {}", e), MessageType::Error),
+                Ok(cmd)
+                    if self.sourcing_view.is_some()
+                        && !matches!(cmd, Command::Set(..) | Command::Slice(_) | Command::Noop) =>
+                {
+                    self.message(
+                        format!("Error: `{}` is not allowed in view settings", line.trim()),
+                        MessageType::Error,
+                    );
                 }
                 Ok(cmd) => self.command(cmd),
             }
+
+            if strict && self.source_errors.len() > errors {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    self.source_errors[errors].clone(),
+                ));
+            }
         }
         Ok(())
     }
@@ -2513,6 +3789,271 @@ impl Session {
     /// Commands
     ///////////////////////////////////////////////////////////////////////////
 
+    /// Process a command triggered by the user, via a key binding or the
+    /// command line. These are the commands recorded into macros.
+    fn user_command(&mut self, cmd: Command) {
+        self.macros.record(&cmd);
+        self.command(cmd);
+    }
+
+    /// Process a command triggered by a key press, applying the pending
+    /// count, if any.
+    fn counted_command(&mut self, cmd: Command) {
+        let (cmd, n) = match self.pending_count.take() {
+            Some(count) => cmd.with_count(count),
+            None => (cmd, 1),
+        };
+        for _ in 0..n {
+            self.user_command(cmd.clone());
+        }
+    }
+
+    /// Handle a key press which may be part of a key sequence, eg. `g g`.
+    /// Returns `true` if the key was handled.
+    fn handle_key_sequence(&mut self, key: Key) -> bool {
+        if key == platform::Key::Escape && !self.pending_keys.is_empty() {
+            self.pending_keys.clear();
+            self.pending_char = None;
+            return true;
+        }
+        let mut keys = self.pending_keys.clone();
+        keys.push(key);
+
+        if self.key_bindings.is_prefix(&keys, self.mode) {
+            self.pending_keys = keys;
+            self.pending_keys_elapsed = time::Duration::from_secs(0);
+            // Ignore the character input of the keys in the sequence.
+            self.ignore_received_characters = true;
+
+            return true;
+        }
+        if self.pending_keys.is_empty() {
+            return false;
+        }
+        if let Some(kb) = self.key_bindings.find_sequence(&keys, self.mode) {
+            self.pending_keys.clear();
+            self.pending_char = None;
+            self.ignore_received_characters = true;
+            self.counted_command(kb.command);
+
+            return true;
+        }
+        // The key doesn't continue the sequence. Run the binding for the keys typed
+        // so far, if any, and let the key be handled on its own.
+        self.flush_key_sequence();
+
+        false
+    }
+
+    /// Run the binding matching the pending key sequence, if any, and clear it.
+    /// A single pending key falls back to the binding for its character.
+    fn flush_key_sequence(&mut self) {
+        let keys = std::mem::take(&mut self.pending_keys);
+        let character = self.pending_char.take().filter(|_| keys.len() == 1);
+        let mode = self.mode;
+
+        if let Some(kb) = self.key_bindings.find_sequence(&keys, mode).or_else(|| {
+            character.and_then(|(c, mods)| {
+                self.key_bindings
+                    .find(Input::Character(c), mods, InputState::Pressed, mode)
+            })
+        }) {
+            self.counted_command(kb.command);
+        } else {
+            self.pending_count = None;
+        }
+    }
+
+    /// Time left until the pending key sequence times out, if there is one.
+    pub fn key_sequence_timeout(&self) -> Option<time::Duration> {
+        if self.pending_keys.is_empty() {
+            return None;
+        }
+        let timeout = time::Duration::from_millis(self.settings["input/timeout"].to_u64());
+
+        timeout.checked_sub(self.pending_keys_elapsed)
+    }
+
+    /// The figure drawn by the current tool, if any.
+    fn figure(&self) -> Option<Figure> {
+        let filled = self.settings["shape/fill"].is_set();
+
+        match self.tool {
+            Tool::Rectangle => Some(Figure::Rectangle { filled }),
+            Tool::Ellipse => Some(Figure::Ellipse { filled }),
+            _ => None,
+        }
+    }
+
+    /// The dither pattern used by fills, if any.
+    fn fill_dither(&self) -> Option<Dither> {
+        match self.settings["fill/dither"].to_string().as_str() {
+            "checker" => Some(Dither::Checker),
+            "bayer" => Some(Dither::Bayer(self.settings["fill/density"].to_u64() as u8)),
+            _ => None,
+        }
+    }
+
+    /// Create a flood filler starting at the given point of the active view,
+    /// configured with the flood fill settings.
+    fn flood_filler(&self, p: LayerCoords<f32>, color: Rgba8) -> Option<FloodFiller> {
+        let v = self.active_view();
+        let filler = FloodFiller::new(v, p, color)?
+            .tolerance(self.settings["flood/tolerance"].to_u64() as u8)
+            .diagonal(self.settings["flood/diagonal"].is_set())
+            .global(self.settings["flood/global"].is_set());
+
+        if self.settings["flood/sample"].to_string() == "composite" {
+            Some(filler.composite(v))
+        } else {
+            Some(filler)
+        }
+    }
+
+    /// The kind of gradient painted by the gradient tool.
+    fn gradient_kind(&self) -> GradientKind {
+        match self.settings["gradient/shape"].to_string().as_str() {
+            "radial" => GradientKind::Radial,
+            _ => GradientKind::Linear,
+        }
+    }
+
+    /// The colors of the gradient painted by the gradient tool, from the
+    /// foreground to the background color.
+    fn gradient_colors(&self) -> Result<Vec<Rgba8>, String> {
+        match self.settings["gradient/colors"].to_string().as_str() {
+            "palette" => {
+                let colors = &self.palette.colors;
+                let index = |color| colors.iter().position(|c| *c == color);
+
+                match (index(self.fg), index(self.bg)) {
+                    (Some(a), Some(b)) if a <= b => Ok(colors[a..=b].to_vec()),
+                    (Some(a), Some(b)) => Ok(colors[b..=a].iter().rev().cloned().collect()),
+                    _ => Err(String::from(
+                        "foreground and background colors must both be in the palette",
+                    )),
+                }
+            }
+            _ => Ok(gradient::ramp(
+                self.fg,
+                self.bg,
+                self.settings["gradient/steps"].to_u64() as usize,
+            )),
+        }
+    }
+
+    /// The kind of curve drawn by the curve tool.
+    fn curve_kind(&self) -> CurveKind {
+        match self.settings["shape/curve"].to_string().as_str() {
+            "quad" => CurveKind::Quadratic,
+            "cubic" => CurveKind::Cubic,
+            _ => CurveKind::Polyline,
+        }
+    }
+
+    /// Paint a figure spanning the given points on the active view.
+    fn paint_figure(&mut self, figure: Figure, p0: Point2<i32>, p1: Point2<i32>) {
+        let mut points = Vec::new();
+        figure.points(p0, p1, &mut points);
+
+        self.paint_points(&points);
+    }
+
+    /// Shade the pixels under the brush stroke along their color ramps.
+    fn paint_shading(&mut self, step: Step) {
+        let brush = &self.brush;
+
+        match brush.state {
+            BrushState::DrawStarted(extent)
+            | BrushState::Drawing(extent)
+            | BrushState::DrawEnded(extent) => {
+                // Pixels are shaded from the layer as it was before the stroke,
+                // so that every pixel is shaded at most once per stroke.
+                let view = self.active_view();
+                let output = brush.shade(&brush.stroke, extent, |p| {
+                    if p.x < 0 || p.y < 0 {
+                        return None;
+                    }
+                    let color = view.color_at(
+                        view.active_layer_id,
+                        LayerCoords::new(p.x as u32, p.y as u32),
+                    )?;
+                    self.palette
+                        .shade(*color, step.offset())
+                        .filter(|c| c != color)
+                });
+
+                if output.is_empty() {
+                    return;
+                }
+                if let BrushState::DrawEnded(_) = brush.state {
+                    self.effects.extend_from_slice(&[
+                        Effect::ViewBlendingChanged(Blending::Constant),
+                        Effect::ViewPaintFinal(output),
+                    ]);
+                } else {
+                    self.effects.push(Effect::ViewPaintDraft(output));
+                }
+            }
+            BrushState::NotDrawing => {}
+        }
+    }
+
+    /// Paint the given points on the active view with the brush, using the
+    /// foreground color, or erase them if the brush is in erase mode.
+    fn paint_points(&mut self, points: &[Point2<i32>]) {
+        self.brush.background = self.bg;
+
+        let erase = self.brush.is_set(BrushMode::Erase);
+        let (color, blending) = if erase {
+            (Rgba8::TRANSPARENT, Blending::Constant)
+        } else {
+            (self.fg, Blending::Alpha)
+        };
+        let shapes = self.brush.shapes(
+            points,
+            self.active_view().extent(),
+            Stroke::NONE,
+            Fill::Solid(color.into()),
+            1.0,
+            Align::BottomLeft,
+        );
+        self.effects.extend_from_slice(&[
+            Effect::ViewBlendingChanged(blending),
+            Effect::ViewPaintFinal(shapes),
+        ]);
+        self.active_view_mut().touch_layer();
+    }
+
+    /// The modifier keys currently held.
+    fn modifiers(&self) -> ModifiersState {
+        ModifiersState {
+            shift: self.keys_pressed.contains(&platform::Key::Shift),
+            ctrl: self.keys_pressed.contains(&platform::Key::Control),
+            alt: self.keys_pressed.contains(&platform::Key::Alt),
+            ..ModifiersState::default()
+        }
+    }
+
+    /// Check whether a character is bound to a command in the current mode,
+    /// either as a character or as a key.
+    fn is_bound(&self, c: char, mods: ModifiersState) -> bool {
+        [Input::Character(c), Input::Key(c.into())]
+            .iter()
+            .any(|input| {
+                self.key_bindings
+                    .find(*input, mods, InputState::Pressed, self.mode)
+                    .is_some()
+            })
+    }
+
+    /// Wait for the user to type a register name before running a command.
+    fn await_register(&mut self, cmd: Command) {
+        // Ignore the character input of the key that triggered the command.
+        self.ignore_received_characters = true;
+        self.pending_register = Some(cmd);
+    }
+
     /// Process a command.
     fn command(&mut self, cmd: Command) {
         debug!("command: {:?}", cmd);
@@ -2546,8 +4087,85 @@ impl Session {
             Command::Brush => {
                 self.unimplemented();
             }
-            Command::BrushSize(op) => {
-                let b = &mut self.brush;
+            Command::BrushCapture => {
+                let s = match self.selection {
+                    Some(s) => s.abs().bounds(),
+                    None => {
+                        self.message(
+                            "Error: there is no selection to capture",
+                            MessageType::Error,
+                        );
+                        return;
+                    }
+                };
+                let v = self.active_view();
+                let (id, layer) = (v.id, v.active_layer_id);
+
+                if !s.intersects(v.layer_bounds()) {
+                    self.message(
+                        "Error: the selection is outside of the view",
+                        MessageType::Error,
+                    );
+                    return;
+                }
+                let r = s.intersection(v.layer_bounds());
+
+                if let Some((_, pixels)) = self.views.get_snapshot_rect(id, layer, &r) {
+                    let stamp = Stamp::new(r.width() as u32, r.height() as u32, pixels);
+
+                    self.message(
+                        format!("Brush stamp captured ({}x{})", stamp.width, stamp.height),
+                        MessageType::Info,
+                    );
+                    self.brush.stamp = Some(stamp);
+                    self.switch_mode(Mode::Normal);
+                }
+            }
+            Command::BrushReset => {
+                self.brush.stamp = None;
+            }
+            Command::BrushShape(tip) => {
+                self.command(Command::Set(
+                    "brush/shape".to_owned(),
+                    Value::Ident(tip.to_string()),
+                ));
+            }
+            Command::BrushSave(path) => match &self.brush.stamp {
+                Some(stamp) => {
+                    match image::save_as(&path, stamp.width, stamp.height, 1, &stamp.pixels) {
+                        Ok(()) => {
+                            let msg = format!(
+                                "Brush stamp written to {} ({}x{})",
+                                path, stamp.width, stamp.height
+                            );
+                            self.message(msg, MessageType::Info);
+                        }
+                        Err(err) => {
+                            self.message(format!("Error: `{}`: {}", path, err), MessageType::Error);
+                        }
+                    }
+                }
+                None => {
+                    self.message(
+                        "Error: the brush has no stamp, use `brush/capture` to create one",
+                        MessageType::Error,
+                    );
+                }
+            },
+            Command::BrushLoad(path) => match crate::io::load_image(&path) {
+                Ok((w, h, pixels)) => {
+                    self.brush.stamp = Some(Stamp::new(w, h, pixels));
+                    self.message(
+                        format!("Brush stamp loaded from {} ({}x{})", path, w, h),
+                        MessageType::Info,
+                    );
+                }
+                Err(err) => {
+                    self.message(format!("Error: {}", err), MessageType::Error);
+                }
+            },
+            Command::BrushSize(op) => {
+                let b = &mut self.brush;
                 match op {
                     Op::Incr => {
                         b.size += 1;
@@ -2628,7 +4246,11 @@ impl Session {
                             Ok(Value::F32Tuple(v.offset.x, v.offset.y))
                         }
                         "v/zoom" => Ok(Value::F64(self.active_view().zoom as f64)),
-                        _ => match self.settings.get(s) {
+                        _ => match self
+                            .variables
+                            .get(s)
+                            .or_else(|| self.settings.get(s).map(|_| self.setting(s)))
+                        {
                             None => Err(format!("Error: {} is undefined", s)),
                             Some(result) => Ok(Value::Str(format!("{} = {}", v.clone(), result))),
                         },
@@ -2640,16 +4262,126 @@ impl Session {
                     Err(e) => self.message(e, MessageType::Error),
                 }
             }
-            Command::PaletteAdd(rgba) => {
-                self.palette.add(rgba);
-                self.center_palette();
+            Command::ColorDef(name, rgba) => {
+                self.colors.insert(name, rgba);
             }
+            Command::PaletteAdd(color) => match self.resolve_color(&color) {
+                Ok(rgba) => {
+                    self.palette.add(rgba);
+                    self.center_palette();
+                }
+                Err(e) => {
+                    self.message(format!("Error: {}", e), MessageType::Error);
+                }
+            },
             Command::PaletteClear => {
                 self.palette.clear();
             }
             Command::PaletteGradient(colorstart, colorend, steps) => {
-                self.palette.gradient(colorstart, colorend, steps);
-                self.center_palette();
+                match (
+                    self.resolve_color(&colorstart),
+                    self.resolve_color(&colorend),
+                ) {
+                    (Ok(cs), Ok(ce)) => {
+                        self.palette.gradient(cs, ce, steps);
+                        self.center_palette();
+                    }
+                    (Err(e), _) | (_, Err(e)) => {
+                        self.message(format!("Error: {}", e), MessageType::Error);
+                    }
+                }
+            }
+            Command::PaletteRamp(colors) => {
+                match colors.iter().map(|c| self.resolve_color(c)).collect() {
+                    Ok(ramp) => {
+                        self.palette.ramps.push(ramp);
+                    }
+                    Err(e) => {
+                        self.message(format!("Error: {}", e), MessageType::Error);
+                    }
+                }
+            }
+            Command::ColorReplace(from, to, scope) => {
+                let (from, to) = match (self.resolve_color(&from), self.resolve_color(&to)) {
+                    (Ok(from), Ok(to)) => (from, to),
+                    (Err(e), _) | (_, Err(e)) => {
+                        self.message(format!("Error: {}", e), MessageType::Error);
+                        return;
+                    }
+                };
+                let v = self.active_view();
+                let extent = v.extent();
+
+                // The area to recolor in the active view, if not all of it.
+                let area = match scope {
+                    Scope::Selection => match self.selection {
+                        Some(s) => Some(s.abs().bounds()),
+                        None => {
+                            self.message("Error: there is no selection", MessageType::Error);
+                            return;
+                        }
+                    },
+                    Scope::Frame(i) => {
+                        if i >= extent.nframes {
+                            self.message(
+                                format!("Error: frame {} does not exist", i),
+                                MessageType::Error,
+                            );
+                            return;
+                        }
+                        Some(extent.frame(i).map(|n| n as i32))
+                    }
+                    Scope::View | Scope::Layers | Scope::Views => None,
+                };
+                let views = match scope {
+                    Scope::Views => self.views.ids().collect(),
+                    _ => vec![v.id],
+                };
+
+                let mut replaced = 0;
+                for id in views {
+                    let v = self.view(id);
+                    // Hidden layers are layers whose addition was undone,
+                    // so they are left alone.
+                    let layers: Vec<LayerId> = match scope {
+                        Scope::Layers | Scope::Views => {
+                            v.resource.layers().map(|(l, _)| *l).collect()
+                        }
+                        _ => vec![v.active_layer_id],
+                    };
+                    let (w, h) = v.layer_size();
+                    let mut painted = Vec::new();
+
+                    for layer in layers {
+                        let (_, pixels) = v.resource.layer(layer).current_snapshot();
+                        let mut pixels = pixels.to_vec();
+                        let mut count = 0;
+
+                        for (i, pixel) in pixels.iter_mut().enumerate() {
+                            // Rows are stored from the top, while layer
+                            // coordinates grow upwards.
+                            let (x, y) = (
+                                (i % w as usize) as i32,
+                                (h as usize - i / w as usize - 1) as i32,
+                            );
+
+                            if *pixel == from
+                                && !matches!(area, Some(r) if !r.contains(Point2::new(x, y)))
+                            {
+                                *pixel = to;
+                                count += 1;
+                            }
+                        }
+                        if count > 0 {
+                            painted.push((layer, pixels));
+                            replaced += count;
+                        }
+                    }
+                    if !painted.is_empty() {
+                        self.view_mut(id).paint_layers(painted);
+                    }
+                }
+                self.message(format!("{} pixel(s) replaced", replaced), MessageType::Info);
             }
             Command::PaletteSort => {
                 // Sort by total luminosity. This is pretty lame, but it's
@@ -2696,6 +4428,92 @@ impl Session {
                     self.message(format!("Error: `{}`: {}", path, err), MessageType::Error);
                 }
             },
+            Command::MacroRecord(reg) => match (self.macros.stop(), reg) {
+                (_, Some(macros::LAST_REGISTER)) => {
+                    self.message(
+                        format!(
+                            "Error: can't record into register `{}`",
+                            macros::LAST_REGISTER
+                        ),
+                        MessageType::Error,
+                    );
+                }
+                (_, Some(r)) => {
+                    self.macros.start(r);
+                }
+                (Some((r, n)), None) => {
+                    self.message(
+                        format!("Recorded {} command(s) into register `{}`", n, r),
+                        MessageType::Info,
+                    );
+                }
+                (None, None) => {
+                    self.await_register(Command::MacroRecord(None));
+                }
+            },
+            Command::MacroStop => match self.macros.stop() {
+                Some((r, n)) => {
+                    self.message(
+                        format!("Recorded {} command(s) into register `{}`", n, r),
+                        MessageType::Info,
+                    );
+                }
+                None => {
+                    self.message("Error: not recording", MessageType::Error);
+                }
+            },
+            Command::MacroPlay(None, n) => {
+                self.await_register(Command::MacroPlay(None, n));
+            }
+            Command::MacroPlay(Some(r), n) => {
+                if self.macros.depth >= Macros::MAX_DEPTH {
+                    self.message("Error: macro nesting is too deep", MessageType::Error);
+                    return;
+                }
+                match self.macros.play(r) {
+                    Some(cmds) => {
+                        self.macros.depth += 1;
+                        for _ in 0..n {
+                            for cmd in cmds.iter().cloned() {
+                                self.command(cmd);
+                            }
+                        }
+                        self.macros.depth -= 1;
+                    }
+                    None => {
+                        self.message(
+                            format!("Error: register `{}` is empty", r),
+                            MessageType::Error,
+                        );
+                    }
+                }
+            }
+            Command::MacroAppend(r, cmd) => {
+                self.macros.append(r, *cmd);
+            }
+            Command::MacroClear(r) => {
+                self.macros.clear(r);
+            }
+            Command::MacroWrite(path) => match File::create(&path) {
+                Ok(mut f) => {
+                    let mut n = 0;
+                    for (r, cmds) in self.macros.iter() {
+                        writeln!(&mut f, "macro/clear {}", r).ok();
+                        for cmd in cmds {
+                            writeln!(&mut f, "macro/append {} :{}", r, String::from(cmd.clone()))
+                                .ok();
+                        }
+                        n += 1;
+                    }
+                    self.message(
+                        format!("Macros written to {} ({} register(s))", path, n),
+                        MessageType::Info,
+                    );
+                }
+                Err(err) => {
+                    self.message(format!("Error: `{}`: {}", path, err), MessageType::Error);
+                }
+            },
             Command::Zoom(op) => {
                 let center = if let Some(s) = self.selection {
                     let v = self.active_view();
@@ -2738,9 +4556,10 @@ impl Session {
                 let bg = self.bg;
                 self.active_view_mut().clear(bg);
             }
-            Command::Fill(Some(color)) => {
-                self.active_view_mut().clear(color);
-            }
+            Command::Fill(Some(color)) => match self.resolve_color(&color) {
+                Ok(color) => self.active_view_mut().clear(color),
+                Err(e) => self.message(format!("Error: {}", e), MessageType::Error),
+            },
             Command::Pan(x, y) => {
                 self.pan(
                     -(x * Self::PAN_PIXELS) as f32,
@@ -2820,6 +4639,17 @@ impl Session {
                     );
                 }
             }
+            Command::Set(ref k, Value::ColorRef(ref c)) => match self.resolve_color(c) {
+                Ok(color) => self.command(Command::Set(k.clone(), Value::Rgba8(color))),
+                Err(e) => self.message(format!("Error: {}", e), MessageType::Error),
+            },
+            Command::Set(ref k, Value::Rgba8(color)) if k == "fg" || k == "bg" => {
+                if k == "fg" {
+                    self.fg = color;
+                } else {
+                    self.bg = color;
+                }
+            }
             Command::Set(ref k, ref v) => {
                 if Settings::DEPRECATED.contains(&k.as_str()) {
                     self.message(
@@ -2828,19 +4658,45 @@ impl Session {
                     );
                     return;
                 }
+                // Settings sourced from a view's script, or already set on the
+                // active view, only apply to that view.
+                let view = self.sourcing_view.or_else(|| {
+                    self.views
+                        .active()
+                        .filter(|v| v.settings.contains_key(k))
+                        .map(|v| v.id)
+                });
+                if let Some(id) = view {
+                    if let Err(e) = self.set_view_setting(id, k, v.clone()) {
+                        self.message(format!("Error: {}", e), MessageType::Error);
+                    }
+                    return;
+                }
                 match self.settings.set(k, v.clone()) {
                     Err(e) => {
                         self.message(format!("Error: {}", e), MessageType::Error);
                     }
                     Ok(ref old) => {
-                        if old != v {
-                            self.setting_changed(k, old, v);
+                        let new = self.settings[k.as_str()].clone();
+
+                        if *old != new {
+                            self.setting_changed(k, old, &new);
                         }
                     }
                 }
             }
+            Command::Let(k, v) => {
+                if self.settings.get(&k).is_some() {
+                    self.message(
+                        format!("Error: `{}` is a setting, use `set` instead", k),
+                        MessageType::Error,
+                    );
+                } else {
+                    self.variables.insert(k, v);
+                }
+            }
             #[allow(mutable_borrow_reservation_conflict)]
-            Command::Toggle(ref k) => match self.settings.get(k) {
+            Command::Toggle(ref k) => match self.settings.get(k).map(|_| self.setting(k)) {
                 Some(Value::Bool(b)) => self.command(Command::Set(k.clone(), Value::Bool(!b))),
                 Some(_) => {
                     self.message(format!("Error: can't toggle `{}`", k), MessageType::Error);
@@ -2868,14 +4724,75 @@ impl Session {
                 }
             }
             Command::Source(Some(ref path)) => {
-                if let Err(ref e) = self.source_path(path) {
+                let errors = self.source_errors.len();
+
+                if let Err(ref e) = self.source_path(path, false) {
                     self.message(
                         format!("Error sourcing `{}`: {}", path, e),
                         MessageType::Error,
                     );
+                } else if self.source_errors.len() > errors {
+                    self.message(
+                        format!(
+                            "Error: {} error(s) sourcing `{}`, see `:errors`",
+                            self.source_errors.len() - errors,
+                            path
+                        ),
+                        MessageType::Error,
+                    );
                 }
             }
-            Command::Source(None) => {
+            Command::MkConfig(path, force) => {
+                let path = path
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| self.proj_dirs.config_dir().join(Self::INIT));
+
+                match self.write_config(&path, force) {
+                    Ok(()) => self.message(
+                        format!("Config written to \"{}\"", path.display()),
+                        MessageType::Info,
+                    ),
+                    Err(e) => self.message(format!("Error: {}", e), MessageType::Error),
+                }
+            }
+            Command::SourceStrict(Some(ref path)) => {
+                if let Err(ref e) = self.source_path(path, true) {
+                    self.message(format!("Error: {}", e), MessageType::Error);
+                }
+            }
+            Command::Messages(t) => {
+                let lines: Vec<Message> = self
+                    .messages
+                    .iter()
+                    .rev()
+                    .filter(|(_, m)| t.is_none() || t == Some(m.message_type))
+                    .map(|(time, m)| {
+                        Message::new(
+                            format!("{} {:<8} {}", time.format("%H:%M:%S"), m.message_type, m),
+                            m.message_type,
+                        )
+                    })
+                    .collect();
+
+                if lines.is_empty() {
+                    self.message("No messages", MessageType::Info);
+                } else {
+                    self.show_listing(lines);
+                }
+            }
+            Command::Errors => {
+                if self.source_errors.is_empty() {
+                    self.message("No errors", MessageType::Info);
+                } else {
+                    self.show_listing(
+                        self.source_errors
+                            .iter()
+                            .map(|e| Message::new(e, MessageType::Error))
+                            .collect(),
+                    );
+                }
+            }
+            Command::Source(None) | Command::SourceStrict(None) => {
                 self.message(
                     format!("Error: source command requires a path"),
                     MessageType::Error,
@@ -2977,32 +4894,104 @@ impl Session {
             Command::Map(map) => {
                 let KeyMapping {
                     input,
+                    prefix,
+                    modifiers,
                     press,
                     release,
                     modes,
                 } = *map;
 
+                let (input, prefix) = match self.resolve_keys(KeySequence {
+                    input,
+                    prefix,
+                    modifiers,
+                }) {
+                    Ok(result) => result,
+                    Err(e) => {
+                        self.message(e, MessageType::Error);
+                        return;
+                    }
+                };
+                let display = prefix
+                    .iter()
+                    .map(|k| k.to_string())
+                    .chain(std::iter::once(format!("{}{}", modifiers, input)))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+
                 self.key_bindings.add(KeyBinding {
                     input,
+                    prefix,
                     modes: modes.clone(),
                     command: press,
                     state: InputState::Pressed,
-                    modifiers: platform::ModifiersState::default(),
+                    modifiers,
                     is_toggle: release.is_some(),
-                    display: Some(format!("{}", input)),
+                    display: Some(display),
+                    source: self.sourcing.clone(),
                 });
                 if let Some(cmd) = release {
                     self.key_bindings.add(KeyBinding {
                         input,
+                        prefix: Vec::new(),
                         modes,
                         command: cmd,
                         state: InputState::Released,
-                        modifiers: platform::ModifiersState::default(),
+                        modifiers,
                         is_toggle: true,
                         display: None,
+                        source: self.sourcing.clone(),
                     });
                 }
             }
+            Command::Unmap(keys, modes) => {
+                let modifiers = keys.modifiers;
+                let display = keys.to_string();
+
+                match self.resolve_keys(keys) {
+                    Ok((input, prefix)) => {
+                        if !self.key_bindings.remove(input, &prefix, modifiers, &modes) {
+                            self.message(
+                                format!("Error: no mapping found for `{}`", display),
+                                MessageType::Error,
+                            );
+                        }
+                    }
+                    Err(e) => self.message(e, MessageType::Error),
+                }
+            }
+            Command::Settings => {
+                let listing = self
+                    .settings
+                    .listing()
+                    .into_iter()
+                    .map(|l| Message::new(l, MessageType::Info))
+                    .collect();
+
+                self.show_listing(listing);
+            }
+            Command::Maps(mode, keys) => {
+                let keys = match keys.map(|k| (k.modifiers, self.resolve_keys(k))) {
+                    Some((mods, Ok((input, prefix)))) => Some((mods, input, prefix)),
+                    Some((_, Err(e))) => {
+                        self.message(e, MessageType::Error);
+                        return;
+                    }
+                    None => None,
+                };
+                let listing = self.key_bindings_listing(mode, keys);
+
+                if listing.is_empty() {
+                    self.message("No key mappings found", MessageType::Info);
+                } else {
+                    self.show_listing(
+                        listing
+                            .into_iter()
+                            .map(|l| Message::new(l, MessageType::Info))
+                            .collect(),
+                    );
+                }
+            }
             Command::MapClear => {
                 self.key_bindings = KeyBindings::default();
             }
@@ -3157,15 +5146,36 @@ impl Session {
                 }
             }
             Command::SelectionFill(color) => {
+                let color = match color.map(|c| self.resolve_color(&c)).transpose() {
+                    Ok(color) => color,
+                    Err(e) => {
+                        self.message(format!("Error: {}", e), MessageType::Error);
+                        return;
+                    }
+                };
                 if let Some(s) = self.selection {
-                    self.effects
-                        .push(Effect::ViewPaintFinal(vec![Shape::Rectangle(
-                            s.abs().bounds().map(|n| n as f32),
-                            ZDepth::default(),
-                            Rotation::ZERO,
-                            Stroke::NONE,
-                            Fill::Solid(color.unwrap_or(self.fg).into()),
-                        )]));
+                    let color = color.unwrap_or(self.fg);
+                    let rects = match self.fill_dither() {
+                        Some(dither) => dither.fill(s.abs().bounds(), color, Some(self.bg)),
+                        None => vec![(s.abs().bounds(), color)],
+                    };
+                    self.effects.extend_from_slice(&[
+                        Effect::ViewBlendingChanged(Blending::Alpha),
+                        Effect::ViewPaintFinal(
+                            rects
+                                .into_iter()
+                                .map(|(r, color)| {
+                                    Shape::Rectangle(
+                                        r.map(|n| n as f32),
+                                        ZDepth::default(),
+                                        Rotation::ZERO,
+                                        Stroke::NONE,
+                                        Fill::Solid(color.into()),
+                                    )
+                                })
+                                .collect(),
+                        ),
+                    ]);
                     self.active_view_mut().touch_layer();
                 }
             }
@@ -3184,16 +5194,103 @@ impl Session {
                     self.active_view_mut().touch_layer();
                 }
             }
-            Command::PaintColor(rgba, x, y) => {
-                self.active_view_mut().paint_color(rgba, x, y);
-            }
-            Command::PaintLine(rgba, x1, y1, x2, y2) => {
+            Command::PaintColor(color, x, y) => match self.resolve_color(&color) {
+                Ok(rgba) => {
+                    self.active_view_mut().paint_color(rgba, x, y);
+                }
+                Err(e) => {
+                    self.message(format!("Error: {}", e), MessageType::Error);
+                }
+            },
+            Command::PaintLine(color, x1, y1, x2, y2) => {
+                let rgba = match self.resolve_color(&color) {
+                    Ok(rgba) => rgba,
+                    Err(e) => {
+                        self.message(format!("Error: {}", e), MessageType::Error);
+                        return;
+                    }
+                };
                 let mut stroke = vec![];
                 Brush::line(Point2::new(x1, y1), Point2::new(x2, y2), &mut stroke);
                 for pt in stroke {
                     self.active_view_mut().paint_color(rgba, pt.x, pt.y);
                 }
             }
+            Command::PaintRect(x1, y1, x2, y2) => {
+                let filled = self.settings["shape/fill"].is_set();
+                self.paint_figure(
+                    Figure::Rectangle { filled },
+                    Point2::new(x1, y1),
+                    Point2::new(x2, y2),
+                );
+            }
+            Command::PaintEllipse(x1, y1, x2, y2) => {
+                let filled = self.settings["shape/fill"].is_set();
+                self.paint_figure(
+                    Figure::Ellipse { filled },
+                    Point2::new(x1, y1),
+                    Point2::new(x2, y2),
+                );
+            }
+            Command::PaintGradient(x1, y1, x2, y2) => {
+                let (from, to) = (Point2::new(x1, y1), Point2::new(x2, y2));
+                let gradient = match self.gradient_colors() {
+                    Ok(colors) => Gradient::new(
+                        self.gradient_kind(),
+                        from,
+                        to,
+                        colors,
+                        self.settings["gradient/mode"].to_string() == "dither",
+                    ),
+                    Err(e) => {
+                        self.message(format!("Error: {}", e), MessageType::Error);
+                        return;
+                    }
+                };
+                // Fill the selection if there is one, otherwise the region of
+                // the same color as the start of the line.
+                let region = if let Some(s) = self.selection {
+                    vec![s.abs().bounds()]
+                } else if self.active_view().layer_bounds().contains(from) {
+                    let p = LayerCoords::new(from.x as f32, from.y as f32);
+                    self.flood_filler(p, self.fg)
+                        .map(|f| f.region())
+                        .unwrap_or_default()
+                } else {
+                    self.message(
+                        "Error: gradient must start inside the view",
+                        MessageType::Error,
+                    );
+                    return;
+                };
+                self.effects.extend_from_slice(&[
+                    Effect::ViewBlendingChanged(Blending::Alpha),
+                    Effect::ViewPaintFinal(
+                        region
+                            .into_iter()
+                            .flat_map(|r| gradient.fill(r))
+                            .map(|(r, color)| {
+                                Shape::Rectangle(
+                                    r.map(|n| n as f32),
+                                    ZDepth::default(),
+                                    Rotation::ZERO,
+                                    Stroke::NONE,
+                                    Fill::Solid(color.into()),
+                                )
+                            })
+                            .collect(),
+                    ),
+                ]);
+                self.active_view_mut().touch_layer();
+            }
+            Command::CurveCommit => {
+                if let Some(curve) = self.curve.take() {
+                    self.paint_points(&curve.points());
+                }
+            }
+            Command::CurveCancel => {
+                self.curve = None;
+            }
             Command::PaintForeground(x, y) => {
                 let fg = self.fg;
                 self.active_view_mut().paint_color(fg, x, y);
@@ -3236,15 +5333,60 @@ impl Session {
             return;
         }
 
-        match self.cmdline.parse(&input) {
+        match self
+            .expand(&input)
+            .and_then(|expanded| self.cmdline.parse(&expanded).map_err(|e| e.to_string()))
+        {
             Err(e) => self.message(format!("Error: {}", e), MessageType::Error),
             Ok(cmd) => {
-                self.command(cmd);
+                self.user_command(cmd);
                 self.cmdline.history.add(input);
             }
         }
     }
 
+    /// Expand `$name` references in the input to the value of the variable
+    /// or setting with that name. Trailing comments are left untouched.
+    fn expand(&self, input: &str) -> Result<String, Error> {
+        let mut output = String::with_capacity(input.len());
+        let mut chars = input.char_indices().peekable();
+        let mut boundary = true;
+
+        while let Some((i, c)) = chars.next() {
+            if c == cmd::COMMENT && input[i..].starts_with("--") && boundary {
+                output.push_str(&input[i..]);
+                break;
+            }
+            boundary = c.is_whitespace();
+
+            if c != '$' {
+                output.push(c);
+                continue;
+            }
+
+            let mut name = String::new();
+            while let Some((_, c)) =
+                chars.next_if(|(_, c)| c.is_ascii_alphabetic() || *c == '/' || *c == '-')
+            {
+                name.push(c);
+            }
+
+            if name.is_empty() {
+                output.push(c);
+                continue;
+            }
+
+            let value = self
+                .variables
+                .get(&name)
+                .or_else(|| self.settings.get(&name))
+                .ok_or_else(|| format!("`${}` is undefined", name))?;
+
+            output.push_str(&value.literal());
+        }
+        Ok(output)
+    }
+
     fn cmdline_handle_input(&mut self, c: char) {
         self.cmdline.putc(c);
         self.message_clear();
@@ -3255,10 +5397,19 @@ impl Session {
             self.prev_tool = Some(self.tool.clone());
         }
         self.tool = t;
+        self.tool_changed();
     }
 
     fn prev_tool(&mut self) {
         self.tool = self.prev_tool.clone().unwrap_or(Tool::default());
+        self.tool_changed();
+    }
+
+    /// Discard the curve being placed once the curve tool is put away.
+    fn tool_changed(&mut self) {
+        if self.tool != Tool::Curve {
+            self.curve = None;
+        }
     }
 
     ///////////////////////////////////////////////////////////////////////////
@@ -3288,6 +5439,44 @@ impl Session {
 mod test {
     use super::*;
 
+    /// Create a session with a blank view of the given size.
+    fn session(w: u32, h: u32) -> Session {
+        let proj_dirs = dirs::ProjectDirs::from("io", "cloudhead", "rx").unwrap();
+        let base_dirs = dirs::BaseDirs::new().unwrap();
+
+        Session::new(128, 128, "/dev/null", proj_dirs, base_dirs).with_blank(
+            FileStatus::NoFile,
+            w,
+            h,
+        )
+    }
+
+    /// Drain the session's effects, returning the shapes painted on the view.
+    fn painted(session: &mut Session) -> Vec<Shape> {
+        session
+            .effects
+            .drain(..)
+            .filter_map(|e| match e {
+                Effect::ViewPaintFinal(shapes) => Some(shapes),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Like [`painted`], but return the painted rectangles and their colors.
+    fn painted_rects(session: &mut Session) -> Vec<(Rect<i32>, Rgba8)> {
+        painted(session)
+            .into_iter()
+            .filter_map(|s| match s {
+                Shape::Rectangle(r, _, _, _, Fill::Solid(c)) => {
+                    Some((r.map(|n| n as i32), c.into()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
     #[test]
     fn test_key_bindings() {
         let mut kbs = KeyBindings::new();
@@ -3297,6 +5486,8 @@ mod test {
         let kb1 = KeyBinding {
             modes: vec![Mode::Normal],
             input: Input::Key(platform::Key::A),
+            prefix: vec![],
+            source: None,
             command: Command::Noop,
             is_toggle: false,
             display: None,
@@ -3336,6 +5527,8 @@ mod test {
         let kb = KeyBinding {
             modes: vec![Mode::Normal],
             input: Input::Key(platform::Key::Control),
+            prefix: vec![],
+            source: None,
             command: Command::Noop,
             is_toggle: false,
             display: None,
@@ -3371,4 +5564,1048 @@ mod test {
             Some(kb)
         );
     }
+
+    #[test]
+    fn test_expand() {
+        let mut session = session(16, 16);
+
+        session.command(Command::Let("w".to_owned(), Value::U32(16)));
+
+        assert_eq!(session.expand("let h = $w * 2").unwrap(), "let h = 16 * 2");
+        assert_eq!(
+            session.expand("set grid/spacing = $grid/spacing").unwrap(),
+            "set grid/spacing = 8 8"
+        );
+        assert_eq!(session.expand("echo $ $w").unwrap(), "echo $ 16");
+        assert_eq!(
+            session.expand("echo 1 -- $fnord").unwrap(),
+            "echo 1 -- $fnord"
+        );
+        assert!(session.expand("echo $fnord").is_err());
+    }
+
+    #[test]
+    fn test_macros() {
+        let mut session = session(16, 16);
+        let (fg, bg) = (session.fg, session.bg);
+
+        session.user_command(Command::MacroRecord(Some('a')));
+        session.user_command(Command::SwapColors);
+        session.user_command(Command::MacroRecord(None));
+
+        assert_eq!(session.macros.recording(), None);
+        assert_eq!((session.fg, session.bg), (bg, fg));
+
+        session.user_command(Command::MacroPlay(Some('a'), 3));
+        assert_eq!((session.fg, session.bg), (fg, bg));
+
+        // Macros which play themselves eventually stop.
+        session.command(Command::MacroAppend(
+            'a',
+            Box::new(Command::MacroPlay(Some('a'), 1)),
+        ));
+        session.user_command(Command::MacroPlay(Some('a'), 1));
+        assert_eq!(session.macros.depth, 0);
+    }
+
+    #[test]
+    fn test_counts() {
+        let mut session = session(16, 16);
+        let mods = ModifiersState::default();
+
+        session.key_bindings.add(KeyBinding {
+            modes: vec![Mode::Normal],
+            input: Input::Character('>'),
+            prefix: vec![],
+            source: None,
+            command: Command::BrushSize(Op::Incr),
+            is_toggle: false,
+            display: None,
+            modifiers: mods,
+            state: InputState::Pressed,
+        });
+        session.command(Command::BrushSize(Op::Set(2.)));
+
+        session.handle_received_character('0', mods);
+        assert_eq!(session.pending_count, None, "counts can't start with zero");
+
+        session.handle_received_character('1', mods);
+        session.handle_received_character('0', mods);
+        assert_eq!(session.pending_count, Some(10));
+
+        session.handle_received_character('>', mods);
+        assert_eq!(session.pending_count, None);
+        assert_eq!(session.brush.size, 22);
+
+        session.handle_received_character('>', mods);
+        assert_eq!(session.brush.size, 24);
+    }
+
+    #[test]
+    fn test_key_sequences() {
+        let mut session = session(16, 16);
+        let (fg, bg) = (session.fg, session.bg);
+
+        for map in &[
+            ":map/normal g :brush/size 4",
+            ":map/normal g g :swap",
+            ":map/normal <leader> x :swap",
+            ":map/normal 'z' :brush/size 9",
+            ":map/normal z z :swap",
+        ] {
+            let cmd = session.cmdline.parse(map).unwrap();
+            session.command(cmd);
+        }
+        let press = |session: &mut Session, key| {
+            for state in &[InputState::Pressed, InputState::Released] {
+                session.handle_keyboard_input(
+                    KeyboardInput {
+                        key: Some(key),
+                        modifiers: ModifiersState::default(),
+                        state: *state,
+                    },
+                    &mut Execution::Normal,
+                );
+            }
+        };
+
+        press(&mut session, platform::Key::G);
+        assert_eq!(session.pending_keys, vec![platform::Key::G]);
+        assert!(session.key_sequence_timeout().is_some());
+
+        press(&mut session, platform::Key::G);
+        assert!(session.pending_keys.is_empty());
+        assert_eq!((session.fg, session.bg), (bg, fg));
+
+        press(&mut session, platform::Key::Backslash);
+        press(&mut session, platform::Key::X);
+        assert_eq!((session.fg, session.bg), (fg, bg));
+
+        // A key which doesn't continue the sequence runs the binding
+        // for the keys typed so far.
+        press(&mut session, platform::Key::G);
+        press(&mut session, platform::Key::J);
+        assert!(session.pending_keys.is_empty());
+        assert_eq!(session.brush.size, 4);
+
+        // Characters typed as part of a sequence don't run their own bindings,
+        // unless the sequence ends with them.
+        let mods = ModifiersState::default();
+        press(&mut session, platform::Key::Z);
+        session.handle_received_character('z', mods);
+        press(&mut session, platform::Key::Z);
+        session.handle_received_character('z', mods);
+        assert_eq!((session.fg, session.bg), (bg, fg));
+        assert_eq!(session.brush.size, 4);
+
+        press(&mut session, platform::Key::Z);
+        session.handle_received_character('z', mods);
+        press(&mut session, platform::Key::J);
+        assert_eq!(session.brush.size, 9);
+    }
+
+    #[test]
+    fn test_mouse_bindings() {
+        let mut session = session(16, 16);
+        let (fg, bg) = (session.fg, session.bg);
+
+        for map in &[
+            ":map/normal <mouse/right> :swap {:swap}",
+            ":map/normal <shift><wheel/down> :brush/size 7",
+        ] {
+            let cmd = session.cmdline.parse(map).unwrap();
+            session.command(cmd);
+        }
+
+        session.handle_mouse_input(platform::MouseButton::Right, InputState::Pressed);
+        assert_eq!((session.fg, session.bg), (bg, fg));
+        assert_eq!(session.mouse_state, InputState::Pressed);
+
+        session.handle_mouse_input(platform::MouseButton::Right, InputState::Released);
+        assert_eq!((session.fg, session.bg), (fg, bg));
+        assert_eq!(session.mouse_state, InputState::Released);
+
+        session.keys_pressed.insert(platform::Key::Shift);
+        session.handle_mouse_wheel(platform::LogicalDelta { x: 0., y: -1. });
+        assert_eq!(session.brush.size, 7);
+    }
+
+    #[test]
+    fn test_unmap_and_maps() {
+        let mut session = session(16, 16);
+        let script = "-- Test mappings\n\
+                      map x :swap\n\
+                      map/normal g g :zoom +\n";
+
+        session
+            .source_reader(io::BufReader::new(script.as_bytes()), "test.rx", false)
+            .unwrap();
+
+        let normal = session.key_bindings_listing(Some(Mode::Normal), None);
+        assert_eq!(normal.len(), 2);
+        assert!(normal[0].starts_with("normal,visual"));
+        assert!(normal[0].ends_with("test.rx:2"));
+        assert!(normal[1].ends_with("test.rx:3"));
+
+        let g = session.key_bindings_listing(
+            None,
+            Some((
+                ModifiersState::default(),
+                Input::Key(platform::Key::G),
+                vec![],
+            )),
+        );
+        assert_eq!(g.len(), 1);
+
+        session.command(Command::Maps(Some(Mode::Normal), None));
+        assert_eq!(session.mode, Mode::Help);
+        assert_eq!(
+            session
+                .listing
+                .as_ref()
+                .map(|l| l.iter().map(|m| m.to_string()).collect()),
+            Some(normal)
+        );
+
+        session.switch_mode(Mode::Normal);
+        assert_eq!(session.listing, None);
+
+        let cmd = session.cmdline.parse(":unmap/normal x").unwrap();
+        session.command(cmd);
+        assert!(session
+            .key_bindings_listing(Some(Mode::Normal), None)
+            .iter()
+            .all(|l| !l.contains("test.rx:2")));
+        assert_eq!(
+            session
+                .key_bindings_listing(Some(Mode::Visual(VisualState::selecting())), None)
+                .len(),
+            1,
+            "bindings in other modes are kept"
+        );
+
+        let cmd = session.cmdline.parse(":unmap/normal g g").unwrap();
+        session.command(cmd);
+        assert!(session
+            .key_bindings_listing(Some(Mode::Normal), None)
+            .is_empty());
+
+        let cmd = session.cmdline.parse(":unmap/normal g g").unwrap();
+        session.command(cmd);
+        assert_eq!(session.message.message_type, MessageType::Error);
+    }
+
+    #[test]
+    fn test_source_errors() {
+        let mut session = session(16, 16);
+        let script = "set scale = 2.0\n\
+                      foo\n\
+                      -- Comment\n\
+                      set nonexistent = 1\n\
+                      set scale = 3.0\n";
+
+        session
+            .source_reader(io::BufReader::new(script.as_bytes()), "init.rx", false)
+            .unwrap();
+
+        assert_eq!(session.settings["scale"], Value::F64(3.0));
+        assert_eq!(session.source_errors.len(), 2);
+        assert_eq!(session.source_errors[0], "init.rx:2: unknown command: foo");
+        assert!(session.source_errors[1].starts_with("init.rx:4: "));
+        assert_eq!(session.sourcing, None);
+
+        session.source_errors.clear();
+        let err = session
+            .source_reader(io::BufReader::new(script.as_bytes()), "init.rx", true)
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "init.rx:2: unknown command: foo");
+        assert_eq!(session.source_errors.len(), 1);
+        assert_eq!(session.settings["scale"], Value::F64(2.0));
+
+        session.command(Command::Errors);
+        assert_eq!(session.mode, Mode::Help);
+        assert_eq!(
+            session
+                .listing
+                .as_ref()
+                .map(|l| l.iter().map(|m| m.to_string()).collect()),
+            Some(session.source_errors.clone())
+        );
+    }
+
+    #[test]
+    fn test_messages() {
+        let mut session = session(16, 16);
+
+        session.message("First", MessageType::Info);
+        session.message("Hint", MessageType::Hint);
+        session.message("Error: second", MessageType::Error);
+        session.message("Third", MessageType::Okay);
+        assert_eq!(session.messages.len(), 3, "hints aren't logged");
+
+        session.command(Command::Messages(Some(MessageType::Error)));
+        let listing = session.listing.clone().unwrap();
+        assert_eq!(session.mode, Mode::Help);
+        assert_eq!(listing.len(), 1);
+        assert!(listing[0].to_string().ends_with("error    Error: second"));
+
+        session.command(Command::Messages(None));
+        let listing = session.listing.clone().unwrap();
+        assert_eq!(listing.len(), 3);
+        assert!(listing[0].to_string().ends_with("Third"), "newest first");
+
+        for i in 0..Session::MAX_MESSAGES {
+            session.message(i, MessageType::Info);
+        }
+        assert_eq!(session.messages.len(), Session::MAX_MESSAGES);
+        assert_eq!(
+            session.messages.iter().next().unwrap().1.to_string(),
+            "0",
+            "oldest messages are dropped"
+        );
+    }
+
+    #[test]
+    fn test_color_refs() {
+        let mut other = session(16, 16);
+        let mut session = session(16, 16);
+
+        session.palette.clear();
+        session.palette.add(Rgba8::WHITE);
+        session.palette.add(Rgba8::RED);
+
+        session.command(Command::Set(
+            "fg".to_owned(),
+            Value::ColorRef(ColorRef::Palette(1)),
+        ));
+        assert_eq!(session.fg, Rgba8::RED);
+
+        session.command(Command::PaletteSort);
+        session.command(Command::Set(
+            "bg".to_owned(),
+            Value::ColorRef(ColorRef::Palette(1)),
+        ));
+        assert_eq!(session.bg, Rgba8::WHITE, "references are resolved when run");
+
+        session.command(Command::ColorDef("skin".to_owned(), Rgba8::BLACK));
+        session.command(Command::Set(
+            "background".to_owned(),
+            Value::ColorRef(ColorRef::Named("skin".to_owned())),
+        ));
+        assert_eq!(session.settings["background"].to_rgba8(), Rgba8::BLACK);
+
+        session.command(Command::Set(
+            "fg".to_owned(),
+            Value::ColorRef(ColorRef::Palette(9)),
+        ));
+        assert_eq!(session.fg, Rgba8::RED);
+        assert_eq!(
+            session.message.to_string(),
+            "Error: palette index 9 is out of range, the palette has 2 color(s)"
+        );
+
+        // Names don't shadow other values.
+        session.command(Command::ColorDef("square".to_owned(), Rgba8::WHITE));
+        let cmd = session.cmdline.parse(":set brush/shape = square").unwrap();
+        assert_eq!(
+            cmd,
+            Command::Set("brush/shape".to_owned(), Value::Ident("square".to_owned()))
+        );
+        session.command(Command::PaletteAdd(ColorRef::Named("square".to_owned())));
+        assert!(session.palette.colors.contains(&Rgba8::WHITE));
+
+        session.reset().unwrap();
+        assert!(session.colors.is_empty(), "names are cleared on reset");
+        session.command(Command::Set(
+            "fg".to_owned(),
+            Value::ColorRef(ColorRef::Named("skin".to_owned())),
+        ));
+        assert_eq!(session.message.to_string(), "Error: unknown color `skin`");
+
+        // Names defined in one session aren't visible in another.
+        other.command(Command::ColorDef("skin".to_owned(), Rgba8::RED));
+        assert_eq!(
+            other.resolve_color(&ColorRef::Named("skin".to_owned())),
+            Ok(Rgba8::RED)
+        );
+        assert!(session
+            .resolve_color(&ColorRef::Named("skin".to_owned()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut session = session(16, 16);
+
+        session.command(Command::Set("p/height".to_owned(), Value::U32(0)));
+        assert_eq!(
+            session.message.to_string(),
+            "Error: invalid value `0` for `p/height`, expected 1..256"
+        );
+        session.command(Command::Set("scale".to_owned(), Value::F64(0.)));
+        assert_eq!(
+            session.message.to_string(),
+            "Error: invalid value `0` for `scale`, expected 1.0..4.0"
+        );
+        session.command(Command::Set(
+            "grid/spacing".to_owned(),
+            Value::U32Tuple(4, 0),
+        ));
+        assert_eq!(session.settings["grid/spacing"], Value::U32Tuple(8, 8));
+        assert_eq!(
+            session.settings["p/height"],
+            Value::U32(Session::PALETTE_HEIGHT)
+        );
+
+        session.command(Command::Set("p/height".to_owned(), Value::U32(4)));
+        assert_eq!(session.palette.height, 4, "the on-change hook is called");
+
+        session.command(Command::Settings);
+        let listing = session.listing.clone().unwrap();
+        assert_eq!(session.mode, Mode::Help);
+        assert_eq!(listing.len(), session.settings.names().len() + 1);
+        assert!(listing.iter().any(|l| l
+            .to_string()
+            .starts_with("p/height          4                16 ")));
+
+        let help = session.settings.help();
+        assert!(help
+            .iter()
+            .any(|l| l
+                == "animation/delay   1..1000            View animation delay (ms) (per view)"));
+        assert!(!help.iter().any(|l| l.starts_with("frame_delay")));
+    }
+
+    #[test]
+    fn test_mkconfig() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("init.rx");
+        let new_session = || {
+            let mut session = session(16, 16);
+            session.reset().unwrap();
+            session
+        };
+        let mut session = new_session();
+
+        assert_eq!(session.config().len(), 1, "only the header is written");
+
+        session.command(Command::Set("grid".to_owned(), Value::Bool(true)));
+        session.command(Command::PaletteAdd(Rgba8::WHITE.into()));
+        for line in &[
+            ":color/def skin #f0c0a0",
+            ":map <ctrl> f :v/fill #f00",
+            ":map/normal x :brush/set erase {:brush/unset erase}",
+            ":unmap/visual y",
+        ] {
+            let cmd = session.cmdline.parse(line).unwrap();
+            session.command(cmd);
+        }
+
+        let config = session.config();
+        assert!(config.contains(&"set grid = on".to_owned()));
+        assert!(config.contains(&"unmap/visual y".to_owned()));
+        assert!(config.contains(&"map <ctrl> f :v/fill #ff0000".to_owned()));
+        assert!(config.contains(&"map/normal x :brush/set erase {:brush/unset erase}".to_owned()));
+        assert!(config.contains(&"p/clear".to_owned()));
+        assert!(config.contains(&"color/def skin #f0c0a0".to_owned()));
+        assert!(!config.iter().any(|l| l.starts_with("set scale")));
+
+        session.command(Command::MkConfig(Some(path.display().to_string()), false));
+        assert!(path.exists());
+
+        session.command(Command::MkConfig(Some(path.display().to_string()), false));
+        assert_eq!(session.message.message_type(), MessageType::Error);
+
+        let mut restored = new_session();
+        restored.source_path(&path, true).unwrap();
+        assert_eq!(restored.config(), config);
+    }
+
+    #[test]
+    fn test_view_settings() {
+        let tmp = tempfile::tempdir().unwrap();
+        let new_session = || session(16, 16);
+
+        // A sprite with a sidecar script.
+        let tile = tmp.path().join("tile.png");
+        let mut session = new_session();
+        session.blank(FileStatus::New(FileStorage::Single(tile.clone())), 32, 16);
+        session.command(Command::Write(None));
+        std::fs::write(
+            tmp.path().join("tile.png.rx"),
+            "set grid/spacing = 16 16\n\
+             set scale = 2.0\n\
+             slice 2\n\
+             p/add #ff0000\n\
+             mkconfig! evil.rx\n\
+             q!\n",
+        )
+        .unwrap();
+
+        // A layered sprite with settings in its manifest.
+        let hero = tmp.path().join("hero.rxz");
+        let mut session = new_session();
+        session.blank(FileStatus::New(FileStorage::Single(hero.clone())), 24, 32);
+        session.command(Command::LayerAdd);
+        session.active_view_mut().script = Some(String::from("set grid/spacing = 24 32"));
+        session.command(Command::Write(None));
+
+        let mut session = new_session();
+        session.edit(&[&tile, &hero]).unwrap();
+
+        let (tile, hero) = {
+            let mut views = session.views.iter();
+            (views.next().unwrap().id, views.next().unwrap().id)
+        };
+        assert_eq!(session.view(tile).animation.len(), 2);
+        assert_eq!(
+            session.view_setting(tile, "grid/spacing"),
+            &Value::U32Tuple(16, 16)
+        );
+        assert_eq!(
+            session.view_setting(hero, "grid/spacing"),
+            &Value::U32Tuple(24, 32)
+        );
+        assert_eq!(session.settings["grid/spacing"], Value::U32Tuple(8, 8));
+        assert_eq!(session.settings["scale"], Value::F64(1.0));
+        let sidecar = tmp.path().join("tile.png.rx");
+        assert_eq!(
+            session.source_errors,
+            vec![
+                format!("{}:2: `scale` can't be set per view", sidecar.display()),
+                format!(
+                    "{}:4: `p/add #ff0000` is not allowed in view settings",
+                    sidecar.display()
+                ),
+                format!(
+                    "{}:5: `mkconfig! evil.rx` is not allowed in view settings",
+                    sidecar.display()
+                ),
+                format!(
+                    "{}:6: `q!` is not allowed in view settings",
+                    sidecar.display()
+                ),
+            ]
+        );
+        assert!(!session.palette.colors.contains(&Rgba8::RED));
+        assert_eq!(session.views.iter().count(), 2);
+
+        // Changing an overridden setting only changes the active view.
+        session.activate(hero);
+        session.command(Command::Set(
+            "grid/spacing".to_owned(),
+            Value::U32Tuple(12, 12),
+        ));
+        assert_eq!(session.setting("grid/spacing"), &Value::U32Tuple(12, 12));
+        assert_eq!(
+            session.view_setting(tile, "grid/spacing"),
+            &Value::U32Tuple(16, 16)
+        );
+        assert_eq!(session.settings["grid/spacing"], Value::U32Tuple(8, 8));
+    }
+
+    #[test]
+    fn test_paint_figures() {
+        let mut session = session(16, 16);
+        session.effects.clear();
+
+        session.command(Command::PaintRect(1, 1, 4, 3));
+        assert_eq!(painted(&mut session).len(), 10);
+
+        session.command(Command::Set("shape/fill".to_owned(), Value::Bool(true)));
+        session.command(Command::PaintRect(1, 1, 4, 3));
+        assert_eq!(painted(&mut session).len(), 12);
+
+        session.brush.set(BrushMode::XSym);
+        session.command(Command::PaintRect(1, 1, 4, 3));
+        assert_eq!(painted(&mut session).len(), 24, "brush modes are honored");
+        session.brush.unset(BrushMode::XSym);
+
+        session.command(Command::PaintEllipse(0, 0, 4, 4));
+        assert_eq!(painted(&mut session).len(), 21);
+
+        session.command(Command::Set("shape/fill".to_owned(), Value::Bool(false)));
+        session.command(Command::PaintEllipse(4, 4, 0, 0));
+        assert_eq!(painted(&mut session).len(), 12);
+    }
+
+    #[test]
+    fn test_curve() {
+        let mut session = session(16, 16);
+        session.tool(Tool::Curve);
+
+        let mut curve = Curve::new(session.curve_kind());
+        curve.points = vec![Point2::new(0, 0), Point2::new(4, 8), Point2::new(8, 0)];
+        session.curve = Some(curve);
+
+        session.command(Command::Set(
+            "shape/curve".to_owned(),
+            Value::Ident(String::from("quad")),
+        ));
+        assert_eq!(session.curve.as_ref().unwrap().kind, CurveKind::Quadratic);
+        assert!(session
+            .settings
+            .set("shape/curve", Value::Ident(String::from("spline")))
+            .is_err());
+
+        let expected = session.curve.as_ref().unwrap().points().len();
+        session.effects.clear();
+        session.command(Command::CurveCommit);
+
+        assert!(session.curve.is_none());
+        assert_eq!(painted(&mut session).len(), expected);
+
+        session.curve = Some(Curve::new(CurveKind::Polyline));
+        session.command(Command::CurveCancel);
+        assert!(session.curve.is_none());
+
+        session.curve = Some(Curve::new(CurveKind::Polyline));
+        session.command(Command::Tool(Tool::Curve));
+        assert!(session.curve.is_some(), "the curve tool keeps its curve");
+        session.command(Command::Tool(Tool::Brush));
+        assert!(
+            session.curve.is_none(),
+            "switching tools discards the curve"
+        );
+
+        session.command(Command::Tool(Tool::Curve));
+        session.curve = Some(Curve::new(CurveKind::Polyline));
+        let other = session.add_view(
+            FileStatus::New(FileStorage::Single("other.png".into())),
+            4,
+            4,
+            vec![vec![Rgba8::TRANSPARENT; 16]],
+        );
+        session.activate(other);
+        assert!(
+            session.curve.is_none(),
+            "switching views discards the curve"
+        );
+        session.effects.clear();
+        session.command(Command::CurveCommit);
+        assert!(painted(&mut session).is_empty());
+    }
+
+    #[test]
+    fn test_fill_dither() {
+        let mut session = session(16, 16);
+        session.fg = Rgba8::WHITE;
+        session.bg = Rgba8::BLACK;
+        session.selection = Some(Selection::new(0, 0, 4, 2));
+        session.effects.clear();
+
+        session.command(Command::SelectionFill(None));
+        assert!(matches!(
+            session.effects.as_slice(),
+            [
+                Effect::ViewBlendingChanged(Blending::Alpha),
+                Effect::ViewPaintFinal(_)
+            ]
+        ));
+        assert_eq!(painted(&mut session).len(), 1);
+
+        session.command(Command::Set(
+            "fill/dither".to_owned(),
+            Value::Ident(String::from("checker")),
+        ));
+        session.command(Command::SelectionFill(None));
+
+        let rects = painted_rects(&mut session);
+        assert_eq!(rects.len(), 8);
+        assert!(rects.iter().all(|(r, c)| {
+            let fg = (r.x1 + r.y1) % 2 == 0;
+            *c == if fg { Rgba8::WHITE } else { Rgba8::BLACK }
+        }));
+
+        session.command(Command::Set(
+            "fill/dither".to_owned(),
+            Value::Ident(String::from("bayer")),
+        ));
+        session.command(Command::Set("fill/density".to_owned(), Value::U32(16)));
+        session.command(Command::SelectionFill(None));
+        assert_eq!(painted(&mut session).len(), 2, "one run per row");
+
+        let cmd = session.cmdline.parse(":set fill/dither = off").unwrap();
+        session.command(cmd);
+        assert_eq!(
+            session.settings["fill/dither"],
+            Value::Ident(String::from("off"))
+        );
+        session.command(Command::SelectionFill(None));
+        assert_eq!(
+            painted(&mut session).len(),
+            1,
+            "dithering can be turned off"
+        );
+    }
+
+    #[test]
+    fn test_brush_stamp() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut session = session(16, 16);
+
+        session.command(Command::BrushCapture);
+        assert!(session.brush.stamp.is_none());
+
+        session.command(Command::Mode(Mode::Visual(VisualState::default())));
+        session.selection = Some(Selection::new(12, 14, 20, 20));
+        session.command(Command::BrushCapture);
+        assert_eq!(session.mode, Mode::Normal);
+
+        let stamp = session.brush.stamp.clone().unwrap();
+        assert_eq!(
+            (stamp.width, stamp.height),
+            (4, 2),
+            "the stamp is clipped to the view"
+        );
+
+        let path = tmp.path().join("stamp.png");
+        let path = path.to_str().unwrap();
+        let stamp = Stamp::new(2, 1, vec![Rgba8::RED, Rgba8::BLUE.alpha(0x40)]);
+
+        session.brush.stamp = Some(stamp.clone());
+        session.command(Command::BrushSave(path.to_owned()));
+        session.command(Command::BrushReset);
+        assert!(session.brush.stamp.is_none());
+
+        session.command(Command::BrushLoad(path.to_owned()));
+        assert_eq!(session.brush.stamp, Some(stamp));
+    }
+
+    #[test]
+    fn test_brush_shape() {
+        let mut session = session(16, 16);
+        assert_eq!(session.brush.tip, Tip::Square);
+
+        session.command(Command::BrushShape(Tip::Diamond));
+        assert_eq!(session.brush.tip, Tip::Diamond);
+        assert_eq!(session.settings["brush/shape"].to_string(), "diamond");
+
+        session.command(Command::Set(
+            "brush/shape".to_owned(),
+            Value::Ident(String::from("vline")),
+        ));
+        assert_eq!(session.brush.tip, Tip::VLine);
+        assert!(session
+            .settings
+            .set("brush/shape", Value::Ident(String::from("star")))
+            .is_err());
+    }
+
+    #[test]
+    fn test_gradient() {
+        let mut session = session(16, 16);
+        let area = |rects: &[(Rect<i32>, Rgba8)]| -> i32 {
+            rects.iter().map(|(r, _)| r.width() * r.height()).sum()
+        };
+        session.fg = Rgba8::WHITE;
+        session.bg = Rgba8::BLACK;
+        session.effects.clear();
+
+        // Without a selection, the region under the start of the line is filled.
+        session.command(Command::PaintGradient(0, 0, 15, 0));
+        assert!(matches!(
+            session.effects.as_slice(),
+            [
+                Effect::ViewBlendingChanged(Blending::Alpha),
+                Effect::ViewPaintFinal(_)
+            ]
+        ));
+        let rects = painted_rects(&mut session);
+        assert_eq!(area(&rects), 16 * 16);
+        assert!(rects.iter().any(|(r, c)| r.x1 == 0 && *c == Rgba8::WHITE));
+        assert!(rects.iter().any(|(r, c)| r.x2 == 16 && *c == Rgba8::BLACK));
+        assert!(rects.iter().all(|(r, _)| r.width() == 2), "eight bands");
+
+        session.command(Command::PaintGradient(-1, 0, 15, 0));
+        assert!(painted_rects(&mut session).is_empty());
+        assert_eq!(session.message.message_type(), MessageType::Error);
+
+        session.selection = Some(Selection::new(2, 2, 6, 4));
+        session.command(Command::Set(
+            "gradient/mode".to_owned(),
+            Value::Ident(String::from("dither")),
+        ));
+        session.command(Command::PaintGradient(2, 2, 5, 2));
+        let rects = painted_rects(&mut session);
+        assert_eq!(area(&rects), 8);
+        assert!(rects
+            .iter()
+            .all(|(r, _)| r.x1 >= 2 && r.x2 <= 6 && r.y1 >= 2 && r.y2 <= 4));
+
+        // Palette gradients run through the entries between fg and bg.
+        let (red, green, blue) = (Rgba8::RED, Rgba8::GREEN, Rgba8::BLUE);
+        session.command(Command::Set(
+            "gradient/colors".to_owned(),
+            Value::Ident(String::from("palette")),
+        ));
+        session.command(Command::PaintGradient(2, 2, 5, 2));
+        assert!(painted_rects(&mut session).is_empty());
+        assert_eq!(session.message.message_type(), MessageType::Error);
+
+        session.palette.clear();
+        for c in &[Rgba8::BLACK, blue, green, red, Rgba8::WHITE] {
+            session.palette.add(*c);
+        }
+        session.fg = red;
+        session.bg = blue;
+        session.command(Command::Set(
+            "gradient/mode".to_owned(),
+            Value::Ident(String::from("bands")),
+        ));
+        session.command(Command::PaintGradient(2, 2, 4, 2));
+        let mut colors: Vec<_> = painted_rects(&mut session)
+            .into_iter()
+            .filter(|(r, _)| r.y1 == 2)
+            .map(|(_, c)| c)
+            .collect();
+        colors.dedup();
+        assert_eq!(colors, vec![red, green, blue]);
+    }
+
+    #[test]
+    fn test_flood_options() {
+        let mut session = session(8, 4);
+        let pixels = |rows: &[&str]| -> Vec<Rgba8> {
+            rows.iter()
+                .flat_map(|row| row.chars())
+                .map(|c| match c {
+                    'r' => Rgba8::RED,
+                    's' => Rgba8::new(0xf0, 0x0, 0x0, 0xff),
+                    _ => Rgba8::TRANSPARENT,
+                })
+                .collect()
+        };
+        let v = session.active_view_mut();
+        let extent = v.extent();
+        #[rustfmt::skip]
+        v.resource.record_layer_painted(0, pixels(&[
+            "r...r...",
+            "sr......",
+            "........",
+            "...r...r",
+        ]), extent);
+
+        // Fill from the top-left pixel and return the area painted.
+        let area = |session: &mut Session, f: fn(FloodFiller) -> FloodFiller| -> i32 {
+            let filler = session
+                .flood_filler(LayerCoords::new(0., 3.), Rgba8::BLUE)
+                .unwrap();
+            f(filler)
+                .run()
+                .unwrap()
+                .iter()
+                .map(|s| match s {
+                    Shape::Rectangle(r, ..) => r.width() as i32 * r.height() as i32,
+                    _ => 0,
+                })
+                .sum()
+        };
+        assert_eq!(area(&mut session, |f| f), 1);
+        assert_eq!(
+            area(&mut session, |f| f.all_frames(ViewExtent::new(4, 4, 2))),
+            2,
+            "the same point is filled in the second frame"
+        );
+
+        session.command(Command::Set("flood/diagonal".to_owned(), Value::Bool(true)));
+        assert_eq!(area(&mut session, |f| f), 2);
+        session.command(Command::Set(
+            "flood/diagonal".to_owned(),
+            Value::Bool(false),
+        ));
+
+        session.command(Command::Set("flood/tolerance".to_owned(), Value::U32(0x10)));
+        assert_eq!(area(&mut session, |f| f), 3);
+        session.command(Command::Set("flood/tolerance".to_owned(), Value::U32(0)));
+
+        session.command(Command::Set("flood/global".to_owned(), Value::Bool(true)));
+        assert_eq!(area(&mut session, |f| f), 5);
+        session.command(Command::Set("flood/global".to_owned(), Value::Bool(false)));
+
+        #[rustfmt::skip]
+        session.active_view_mut().add_layer(Some(pixels(&[
+            ".r......",
+            "........",
+            "........",
+            "........",
+        ])));
+        assert_eq!(area(&mut session, |f| f), 1);
+
+        session.command(Command::Set(
+            "flood/sample".to_owned(),
+            Value::Ident(String::from("composite")),
+        ));
+        assert_eq!(
+            area(&mut session, |f| f),
+            3,
+            "the pixel on the upper layer connects the region"
+        );
+    }
+
+    #[test]
+    fn test_color_replace() {
+        let mut session = session(16, 16);
+        let (r, g, b) = (Rgba8::RED, Rgba8::GREEN, Rgba8::BLUE);
+
+        let other = session.add_view(
+            FileStatus::New(FileStorage::Single("other.png".into())),
+            2,
+            2,
+            vec![vec![r; 4]],
+        );
+        let id = session.add_view(
+            FileStatus::New(FileStorage::Single("sprite.png".into())),
+            2,
+            2,
+            vec![vec![r; 4], vec![r, g, g, r]],
+        );
+        session.edit_view(id);
+        session.active_view_mut().add_layer(Some(vec![r; 8]));
+        session.active_view_mut().activate_layer(0);
+
+        let pixels = |session: &Session, id: ViewId, layer: LayerId| -> Vec<Rgba8> {
+            session
+                .view(id)
+                .resource
+                .layer(layer)
+                .current_snapshot()
+                .1
+                .to_vec()
+        };
+        let count = |session: &Session, id: ViewId, layer: LayerId, color: Rgba8| -> usize {
+            pixels(session, id, layer)
+                .into_iter()
+                .filter(|c| *c == color)
+                .count()
+        };
+
+        session.command(Command::ColorReplace(r.into(), b.into(), Scope::View));
+        assert_eq!(count(&session, id, 0, b), 6);
+        assert_eq!(count(&session, id, 1, b), 0, "other layers are left alone");
+        assert_eq!(
+            count(&session, other, 0, b),
+            0,
+            "other views are left alone"
+        );
+
+        session.command(Command::Undo);
+        assert_eq!(count(&session, id, 0, r), 6, "replacing is a single edit");
+
+        // The second frame, in layer coordinates.
+        session.selection = Some(Selection::new(2, 0, 4, 2));
+        session.command(Command::ColorReplace(r.into(), b.into(), Scope::Selection));
+        assert_eq!(
+            pixels(&session, id, 0),
+            vec![r, r, b, g, r, r, g, b],
+            "only the selection is recolored"
+        );
+        session.command(Command::Undo);
+
+        session.command(Command::ColorReplace(r.into(), b.into(), Scope::Frame(1)));
+        assert_eq!(
+            pixels(&session, id, 0),
+            vec![r, r, b, g, r, r, g, b],
+            "only the second frame is recolored"
+        );
+        session.command(Command::Undo);
+
+        session.command(Command::ColorReplace(r.into(), b.into(), Scope::Frame(2)));
+        assert_eq!(session.message.message_type(), MessageType::Error);
+        assert_eq!(count(&session, id, 0, r), 6);
+
+        session.command(Command::ColorReplace(r.into(), b.into(), Scope::Layers));
+        assert_eq!(count(&session, id, 0, b), 6);
+        assert_eq!(count(&session, id, 1, b), 8);
+        assert_eq!(count(&session, other, 0, b), 0);
+
+        session.command(Command::ColorReplace(b.into(), g.into(), Scope::Views));
+        assert_eq!(count(&session, id, 0, g), 8);
+        assert_eq!(count(&session, id, 1, g), 8);
+        assert_eq!(
+            count(&session, other, 0, g),
+            0,
+            "there is no blue in the other view"
+        );
+
+        session.command(Command::ColorReplace(r.into(), g.into(), Scope::Views));
+        assert_eq!(count(&session, other, 0, g), 4);
+
+        session.selection = None;
+        session.command(Command::ColorReplace(r.into(), b.into(), Scope::Selection));
+        assert_eq!(session.message.message_type(), MessageType::Error);
+
+        // Layers whose addition was undone are left alone.
+        let hidden = session.active_view_mut().add_layer(Some(vec![r; 8]));
+        session.command(Command::Undo);
+        session.command(Command::ColorReplace(r.into(), b.into(), Scope::Layers));
+        assert_eq!(count(&session, id, hidden, r), 8);
+    }
+
+    #[test]
+    fn test_shading() {
+        let mut session = session(6, 1);
+        let (black, gray, white) = (
+            Rgba8::BLACK,
+            Rgba8::new(0x80, 0x80, 0x80, 0xff),
+            Rgba8::WHITE,
+        );
+        let (red, green, blue) = (Rgba8::RED, Rgba8::GREEN, Rgba8::BLUE);
+        let other = Rgba8::new(0x12, 0x34, 0x56, 0xff);
+
+        let v = session.active_view_mut();
+        let extent = v.extent();
+        v.resource
+            .record_layer_painted(0, vec![black, gray, white, red, blue, other], extent);
+
+        session.command(Command::PaletteClear);
+        for c in &[black, gray, white] {
+            session.command(Command::PaletteAdd((*c).into()));
+        }
+        session.command(Command::PaletteRamp(vec![
+            red.into(),
+            green.into(),
+            ColorRef::Named("blue".to_owned()),
+        ]));
+        assert_eq!(session.palette.ramps, vec![vec![red, green, blue]]);
+
+        // Shade every pixel of the view in one stroke, and return what was painted.
+        let shade = |session: &mut Session, step: Step| -> Vec<(i32, Rgba8)> {
+            session.command(Command::BrushSet(BrushMode::Shade(step)));
+            session.effects.clear();
+            session
+                .brush
+                .start_drawing(LayerCoords::new(0, 0), Rgba8::WHITE, extent);
+            session.brush.draw(LayerCoords::new(5, 0));
+            session.brush.stop_drawing();
+            session.paint_shading(step);
+            session.brush.update();
+
+            painted_rects(session)
+                .into_iter()
+                .map(|(r, c)| (r.x1, c))
+                .collect()
+        };
+        assert_eq!(
+            shade(&mut session, Step::Up),
+            vec![(0, gray), (1, white), (3, green)],
+            "pixels at the end of a ramp or outside of one are left alone"
+        );
+        assert_eq!(
+            shade(&mut session, Step::Down),
+            vec![(1, black), (2, gray), (4, green)]
+        );
+        assert_eq!(
+            session.brush.shading(),
+            Some(Step::Down),
+            "only one step is active"
+        );
+
+        session.command(Command::BrushUnset(BrushMode::Shade(Step::Up)));
+        assert_eq!(session.brush.shading(), None);
+
+        session.command(Command::PaletteClear);
+        assert!(session.palette.ramps.is_empty());
+    }
 }
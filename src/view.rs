@@ -5,7 +5,7 @@ pub mod resource;
 pub use path::{Format, Path};
 pub use resource::{Edit, EditId, Snapshot, ViewResource};
 
-use crate::cmd::Axis;
+use crate::cmd::{Axis, Value};
 use crate::session::{Direction, Session, SessionCoords};
 use crate::util;
 use crate::view::layer::{FrameRange, Layer, LayerCoords, LayerId};
@@ -19,7 +19,7 @@ use nonempty::NonEmpty;
 use miniserde::{Deserialize, Serialize};
 
 use std::collections::btree_map;
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fmt;
 use std::io;
 use std::ops::Deref;
@@ -206,6 +206,10 @@ pub struct View<R> {
     pub active_layer_id: LayerId,
     /// View resource.
     pub resource: R,
+    /// Settings which apply to this view only, overriding the session settings.
+    pub settings: HashMap<String, Value>,
+    /// Settings script stored with the file, if any.
+    pub script: Option<String>,
 
     /// Which view snapshot has been saved to disk, if any.
     saved_snapshot: Option<EditId>,
@@ -280,6 +284,8 @@ impl<R> View<R> {
             active_layer_id: Default::default(),
             saved_snapshot,
             resource,
+            settings: HashMap::new(),
+            script: None,
         }
     }
 
@@ -683,6 +689,17 @@ impl View<ViewResource> {
         id
     }
 
+    /// Replace the pixels of the given layers, recording a single edit.
+    pub fn paint_layers(&mut self, layers: Vec<(LayerId, Vec<Rgba8>)>) {
+        self.resource.record_view_painted(layers);
+
+        if let FileStatus::Saved(ref f) = self.file_status {
+            self.file_status = FileStatus::Modified(f.clone());
+        }
+        // Have the layers restored from their new snapshots.
+        self.damaged(None);
+    }
+
     /// Get the color at the given view coordinate.
     pub fn color_at(&self, l: LayerId, p: LayerCoords<u32>) -> Option<&Rgba8> {
         self.resource
@@ -749,7 +766,7 @@ impl View<ViewResource> {
                 }
 
                 if nlayers > 1 {
-                    let written = self.resource.save_archive(path)?;
+                    let written = self.resource.save_archive(path, self.script.as_deref())?;
                     let edit_id = self.resource.cursor;
 
                     (edit_id, written)
@@ -198,7 +198,11 @@ impl ViewResource {
         self.cursor
     }
 
-    pub fn save_archive<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+    pub fn save_archive<P: AsRef<Path>>(
+        &self,
+        path: P,
+        settings: Option<&str>,
+    ) -> io::Result<usize> {
         use std::io::Write;
         use zip::write::FileOptions;
 
@@ -218,7 +222,10 @@ impl ViewResource {
             .file_stem()
             .expect("the file must have a stem");
 
-        let manifest = json::to_string(&crate::io::Manifest { extent });
+        let manifest = json::to_string(&crate::io::Manifest {
+            extent,
+            settings: settings.map(str::to_owned),
+        });
 
         zip.start_file_from_path(
             &Path::new(name).join("manifest.json"),